@@ -0,0 +1,39 @@
+//! Time a tight arithmetic loop with and without the builtin lookup cache.
+//!
+//! Run with `cargo bench`. The configurations take turns so both see the same machine load,
+//! and the fastest run of each is reported.
+extern crate teko;
+
+use std::time::{Duration, Instant};
+
+use teko::interpret::{eval, initialize_environment_with_standard_library};
+use teko::parse::parse_string;
+
+const LOOP: &str = "
+(define count (function (n accum)
+	(if (< n 1)
+		accum
+		(count (- n 1) (+ accum (* 2 n) 1)))))
+(count 200000 0)";
+
+fn run(cache_builtins: bool) -> Duration {
+	let program = parse_string(LOOP).ok().unwrap();
+	let mut env = initialize_environment_with_standard_library();
+	env.cache_builtins = cache_builtins;
+	let start = Instant::now();
+	let env = eval(program, env);
+	let elapsed = start.elapsed();
+	assert_eq![env.get_result().as_i64(), Ok(40000400000)];
+	elapsed
+}
+
+fn main() {
+	let mut fastest = [Duration::from_secs(u64::MAX); 2];
+	for _ in 0..10 {
+		for (index, &cache_builtins) in [false, true].iter().enumerate() {
+			fastest[index] = fastest[index].min(run(cache_builtins));
+		}
+	}
+	println!["without cache: {:?}", fastest[0]];
+	println!["with cache:    {:?}", fastest[1]];
+}
@@ -0,0 +1,35 @@
+//! Time a mutually recursive tail call loop.
+//!
+//! Run with `cargo bench`. The loop reports the variables visible at its deepest point, which
+//! stays as small as for a single call, and the fastest of ten runs.
+extern crate teko;
+
+use std::time::{Duration, Instant};
+
+use teko::interpret::{eval, initialize_environment_with_standard_library};
+use teko::parse::parse_string;
+
+const LOOP: &str = "
+(define ping (function (n a) (if (= n 0) (@variable-count) (pong (- n 1) a))))
+(define pong (function (m b) (if (= m 0) (@variable-count) (ping (- m 1) b))))
+(ping 200000 0)";
+
+fn run() -> (Duration, i64) {
+	let program = parse_string(LOOP).ok().unwrap();
+	let env = initialize_environment_with_standard_library();
+	let start = Instant::now();
+	let env = eval(program, env);
+	let elapsed = start.elapsed();
+	(elapsed, env.get_result().as_i64().ok().unwrap())
+}
+
+fn main() {
+	let mut fastest = Duration::from_secs(u64::MAX);
+	let mut variables = 0;
+	for _ in 0..10 {
+		let (elapsed, count) = run();
+		fastest = fastest.min(elapsed);
+		variables = count;
+	}
+	println!["tail calls: {:?}, {} variables at the deepest call", fastest, variables];
+}
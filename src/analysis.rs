@@ -0,0 +1,168 @@
+//! Analysis of parsed programs without evaluating them.
+//!
+//! `non_tail_recursion` finds functions defined as `(define name (function (params) body))`
+//! that call themselves outside of a tail position. Only the last call of a function can be
+//! optimized by `enter_scope`, so every other recursive call grows the stack.
+//!
+//! ```
+//! extern crate teko;
+//! let program = teko::parse::parse_string("(define f (function (n) (+ 1 (f (- n 1)))))").ok().unwrap();
+//! let warnings = teko::analysis::non_tail_recursion(&program);
+//! assert_eq![warnings.len(), 1];
+//! assert_eq![warnings[0].0.as_ref().unwrap().column, 31];
+//! ```
+use data_structures::*;
+use utilities::*;
+
+/// Find recursive calls that are not in tail position, in the order they appear in the source.
+///
+/// Each warning carries the source of the called name. The branches of `if`, the last
+/// statement of `begin`, `when`, `unless` and `let` inherit the tail position of the form.
+/// Nested functions, macros and named `let` loops are not followed since their calls do not
+/// run in the frame of the defined function. A parameter with the same name hides the function.
+pub fn non_tail_recursion(program: &Program) -> Vec<(Option<Source>, String)> {
+	let mut warnings = vec![];
+	for (name, params, body) in recursive_definitions(program) {
+		if params.iter().any(|param| symbol_name(param) == Some(name.as_str())) {
+			continue;
+		}
+		let mut queue: Vec<(Statement, bool)> = body.iter().rev().enumerate()
+			.map(|(index, statement)| (statement.clone(), index == 0))
+			.collect();
+		while let Some((statement, tail)) = queue.pop() {
+			let elements = match list_elements(&statement) {
+				Some(elements) => elements,
+				None => continue,
+			};
+			let mut next = vec![];
+			match elements.first().and_then(|head| symbol_name(head)) {
+				Some(head) if head == name => {
+					if !tail {
+						warnings.push((elements[0].0.clone(), format!["non-tail recursive call to {}", name]));
+					}
+					next.extend(elements.iter().skip(1).map(|argument| (argument.clone(), false)));
+				}
+				Some("\"") | Some("@") | Some("_quote") | Some("quote") | Some("function") | Some("closure")
+					| Some("macro") => {}
+				Some("if") => {
+					next.extend(elements.iter().skip(1).enumerate().map(|(index, part)| (part.clone(), index > 0 && tail)));
+				}
+				Some("begin") | Some("program") => {
+					next.extend(last_inherits(&elements[1..], tail));
+				}
+				Some("when") | Some("unless") if elements.len() > 1 => {
+					next.push((elements[1].clone(), false));
+					next.extend(last_inherits(&elements[2..], tail));
+				}
+				Some("let") if elements.len() > 1 => {
+					if symbol_name(&elements[1]).is_some() {
+						if let Some(bindings) = elements.get(2) {
+							next.extend(binding_values(bindings));
+						}
+					} else {
+						next.extend(binding_values(&elements[1]));
+						next.extend(last_inherits(&elements[2..], tail));
+					}
+				}
+				_ => {
+					next.extend(elements.iter().map(|part| (part.clone(), false)));
+				}
+			}
+			queue.extend(next.into_iter().rev());
+		}
+	}
+	warnings
+}
+
+/// Collect every `(define name (function (params) body))` in the program, including nested ones.
+fn recursive_definitions(program: &Program) -> Vec<(String, Vec<Statement>, Vec<Statement>)> {
+	let mut definitions = vec![];
+	let mut queue: Vec<Statement> = program.clone();
+	while let Some(statement) = queue.pop() {
+		let elements = match list_elements(&statement) {
+			Some(elements) => elements,
+			None => continue,
+		};
+		match elements.first().and_then(|head| symbol_name(head)) {
+			Some("\"") | Some("@") | Some("_quote") | Some("quote") => continue,
+			Some("define") if elements.len() == 3 => {
+				if let (Some(name), Some(function)) = (symbol_name(&elements[1]), list_elements(&elements[2])) {
+					if function.len() > 1 && symbol_name(&function[0]) == Some("function") {
+						if let Some(params) = list_elements(&function[1]) {
+							definitions.push((name.to_string(), params, function[2..].to_vec()));
+						}
+					}
+				}
+			}
+			_ => {}
+		}
+		queue.extend(elements.into_iter().rev());
+	}
+	definitions
+}
+
+/// Pair statements with their tail position, only the last one can inherit it.
+fn last_inherits(statements: &[Statement], tail: bool) -> Vec<(Statement, bool)> {
+	statements.iter().enumerate()
+		.map(|(index, statement)| (statement.clone(), tail && index + 1 == statements.len()))
+		.collect()
+}
+
+/// The values of `let` bindings `((name value) ...)`, none of which are in tail position.
+fn binding_values(bindings: &Statement) -> Vec<(Statement, bool)> {
+	list_elements(bindings).unwrap_or_default().iter()
+		.filter_map(list_elements)
+		.flat_map(|binding| binding.into_iter().skip(1))
+		.map(|value| (value, false))
+		.collect()
+}
+
+/// The elements of a list in order, `None` if the data is not a list.
+fn list_elements(data: &Statement) -> Option<Vec<Statement>> {
+	match data.1 {
+		Coredata::Cell(..) | Coredata::Null() => {
+			let mut elements = collect_cell_into_revvec(data);
+			elements.reverse();
+			Some(elements)
+		}
+		_ => None,
+	}
+}
+
+fn symbol_name(data: &Sourcedata) -> Option<&str> {
+	if let Coredata::Symbol(ref symbol) = data.1 {
+		Some(symbol.into())
+	} else {
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use parse::parse_string;
+	fn warnings(code: &str) -> Vec<(usize, usize)> {
+		non_tail_recursion(&parse_string(code).ok().unwrap()).iter()
+			.map(|warning| {
+				let source = warning.0.as_ref().unwrap();
+				(source.line, source.column)
+			})
+			.collect()
+	}
+	#[test]
+	fn non_tail_calls() {
+		assert_eq![warnings("(define self (function (n) (+ 1 (self (- n 1)))))"), vec![(1, 34)]];
+		assert_eq![
+			warnings("(define f (function (n) (if (= n 0) (f 1) (f (f (- n 1))))))\n(define g (function () (g) 1))"),
+			vec![(1, 47), (2, 25)]
+		];
+	}
+	#[test]
+	fn tail_calls() {
+		assert_eq![warnings("(define f (function (n) (if (= n 0) 0 (begin (print n) (f (- n 1))))))"), vec![]];
+		assert_eq![warnings("(define f (function (n) (let ((m (- n 1))) (when (> m 0) (f m)))))"), vec![]];
+		assert_eq![warnings("(define f (function (f) (+ 1 (f 2))))"), vec![]];
+		assert_eq![warnings("(define f (function (n) (function () (+ 1 (f n)))))"), vec![]];
+		assert_eq![warnings("(define f (function (n) (+ 1 (@ (f n)))))"), vec![]];
+	}
+}
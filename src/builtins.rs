@@ -23,8 +23,8 @@
 // //////////////////////////////////////////////////////////
 // std imports
 // //////////////////////////////////////////////////////////
-use std::{char, collections::HashMap, io::{self, Read},
-          time, thread, usize, sync::Arc};
+use std::{char, cmp, collections::HashMap, io::{self, Read},
+          time, thread, usize, sync::{Arc, atomic::{AtomicUsize, Ordering}}};
 
 // //////////////////////////////////////////////////////////
 // Internal data structures used by Teko
@@ -36,7 +36,7 @@ use utilities::*;
 // //////////////////////////////////////////////////////////
 // External libraries
 // //////////////////////////////////////////////////////////
-use num::{BigInt, one, ToPrimitive, zero};
+use num::{BigInt, BigRational, Integer, one, Signed, ToPrimitive, Zero, zero};
 
 // //////////////////////////////////////////////////////////
 // Standard Library Table
@@ -45,6 +45,9 @@ use num::{BigInt, one, ToPrimitive, zero};
 const HELP: &str = "To see all current variables in scope enter: (@variables)
 to exit press CTRL-D, CTRL-C, or: (exit)";
 
+/// Counter used by `gensym`, shared by every `Env` in the process.
+static GENSYM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
 /// Create the builtin library table.
 ///
 /// The table contains mappings from strings to arbitrary data, functions, and macros.
@@ -52,7 +55,8 @@ to exit press CTRL-D, CTRL-C, or: (exit)";
 /// not `fn eval`).
 /// So if you want to create a function "f" you add an entry `Function : "f" => some_name`,
 /// and call it using `(f)` in Teko. You'll also need to declare the function `some_name`
-/// that actually implements your functionality.
+/// that actually implements your functionality. A function entry may end in its least and most
+/// number of arguments, `Function : "f" => some_name (1 => 2)`, which `eval` checks before the call.
 ///
 /// For user-defined functions and types please see `user/mod.rs`.
 pub fn create_builtin_library_table() -> HashMap<Symbol, Program> {
@@ -61,68 +65,181 @@ pub fn create_builtin_library_table() -> HashMap<Symbol, Program> {
 		// constants
 		{
 			"help" => Coredata::String(HELP.into()),
+			"true" => Coredata::Boolean(true),
+			"false" => Coredata::Boolean(false),
 		}
 		// The rest of the table defines functions and macros
 		// Numerics
-		Function : "+" => plus,
-		Function : "-" => subtract,
-		Function : "*" => multiply,
-		Function : "/" => divide,
-		Function : "=" => eq,
-		Function : "<" => lt,
-		Function : ">" => gt,
+		Function : "+" => plus (0 => usize::MAX),
+		Function : "-" => subtract (1 => usize::MAX),
+		Function : "*" => multiply (0 => usize::MAX),
+		Function : "/" => divide (1 => usize::MAX),
+		Function : "mod" => modulo (2 => 2),
+		Function : "quotient" => quotient (2 => 2),
+		Function : "expt" => expt (2 => 2),
+		Function : "factorial" => factorial (1 => 1),
+		Function : "abs" => abs (1 => 1),
+		Function : "exact->inexact" => exact_to_inexact (1 => 1),
+		Function : "inexact->exact" => inexact_to_exact (1 => 1),
+		Function : "exact?" => is_exact (1 => 1),
+		Function : "inexact?" => is_inexact (1 => 1),
+		Function : "gcd" => gcd (0 => usize::MAX),
+		Function : "lcm" => lcm (0 => usize::MAX),
+		Function : "digits" => digits (1 => 2),
+		Function : "from-digits" => from_digits (1 => 2),
+		Function : "range" => range (1 => 3),
+		Function : "=" => eq (0 => usize::MAX),
+		Function : "!=" => not_eq (0 => usize::MAX),
+		Function : "<" => lt (0 => usize::MAX),
+		Function : ">" => gt (0 => usize::MAX),
+		Function : "max" => max (1 => usize::MAX),
+		Function : "min" => min (1 => usize::MAX),
 		// Boolean logic
-		Function : "and" => and,
-		Function : "or" => or,
-		Function : "not" => not,
+		Function : "and" => and (0 => usize::MAX),
+		Function : "or" => or (0 => usize::MAX),
+		Function : "not" => not (1 => 1),
 		// Error handling
-		Function : "error" => error,
-		Function : "error-data" => error_data,
-		Function : "error?" => is_error,
+		Function : "error" => error (1 => usize::MAX),
+		Function : "error-data" => error_data (1 => 1),
+		Function : "error-cause" => error_cause (1 => 1),
+		Function : "error-message" => error_message (1 => 1),
+		Function : "error-source" => error_source (1 => 1),
+		Function : "error?" => is_error (1 => 1),
+		Function : "raise" => error (1 => usize::MAX),
+		Function : "assert" => assert (1 => 2),
+		Function : "assert-equal" => assert_equal (2 => 2),
 		Macro    : "wind" => wind,
 		Function : "unwind" => unwind,
+		Macro    : "try" => try_catch,
 		// Lisp primitives
 		Macro    : "if" => if_conditional,
+		Macro    : "when" => when,
+		Macro    : "unless" => unless,
+		Macro    : "case" => case,
+		Macro    : "do" => do_loop,
+		Macro    : "let" => let_binding,
+		Macro    : "let*" => let_sequential,
+		Macro    : "letrec" => letrec,
+		Macro    : "if-let" => if_let,
 		Macro    : "_quote" => quote,
 		Macro    : "@" => quote2,
-		Function : "same?" => is_data_eq,
-		Function : "symbol?" => is_symbol,
-		Function : "head" => head,
-		Function : "tail" => tail,
-		Function : "cell" => cell,
-		Function : "cell?" => is_cell,
+		Macro    : "quote" => quote2,
+		Function : "same?" => is_data_eq (0 => usize::MAX),
+		Function : "equal?" => is_data_eq (0 => usize::MAX),
+		Function : "eqv?" => is_eqv_data (0 => usize::MAX),
+		Function : "eq?" => is_eq_data (0 => usize::MAX),
+		Function : "symbol?" => is_symbol (1 => 1),
+		Function : "null?" => is_null (1 => 1),
+		Function : "pair?" => is_cell (1 => 1),
+		Function : "integer?" => is_integer (1 => 1),
+		Function : "float?" => is_float (1 => 1),
+		Function : "rational?" => is_rational (1 => 1),
+		Function : "complex?" => is_complex (1 => 1),
+		Function : "string?" => is_string (1 => 1),
+		Function : "boolean?" => is_boolean (1 => 1),
+		Function : "function?" => is_function (1 => 1),
+		Function : "vector?" => is_vector (1 => 1),
+		Function : "table?" => is_table (1 => 1),
+		Function : "char?" => is_char (1 => 1),
+		Function : "type-of" => type_of (1 => 1),
+		Function : "assert-type" => assert_type (2 => 2),
+		Function : "head" => head (1 => 1),
+		Function : "tail" => tail (1 => 1),
+		Function : "cell" => cell (2 => 2),
+		Function : "cons*" => cons_star (1 => usize::MAX),
+		Function : "list*" => cons_star (1 => usize::MAX),
+		Function : "cell?" => is_cell (1 => 1),
 		Macro    : "function" => function,
 		Macro    : "macro" => make_macro,
+		Function : "rec" => rec (1 => 1),
+		Function : "values" => values (0 => usize::MAX),
+		Function : "call-with-values" => call_with_values (2 => 2),
+		// Mutable boxes
+		Function : "make-box" => make_box (1 => 1),
+		Function : "make-error" => make_error (0 => 2),
+		Function : "box-ref" => box_ref (1 => 1),
+		Function : "box-set!" => box_set (2 => 2),
+		// Mutable vectors
+		Function : "vector" => vector (0 => usize::MAX),
+		Function : "vector-ref" => vector_ref (2 => 2),
+		Function : "vector-length" => vector_length (1 => 1),
+		Function : "vector-set!" => vector_set (3 => 3),
+		Function : "char->integer" => char_to_integer (1 => 1),
+		Function : "integer->char" => integer_to_char (1 => 1),
+		// Persistent tables
+		Function : "make-table" => make_table (0 => usize::MAX),
+		Function : "table-get" => table_get (2 => 3),
+		Function : "table-set" => table_set (3 => 3),
+		Function : "table-keys" => table_keys (1 => 1),
 		// Some useful features
 		Macro    : "define" => define,
-		Function : "exists?" => exists,
+		Macro    : "define-macro" => define_macro,
+		Function : "exists?" => exists (1 => 1),
 		Macro    : "local" => local,
 		Macro    : "set!" => set,
 		Macro    : "program" => program,
-		Function : "read" => read,
-		Function : "eval" => eval_expose,
-		Function : "list" => list,
-		Function : "len" => list_length,
-		Function : "->string" => to_string,
-		Function : "symbol->string" => symbol_to_string,
-		Function : "string->symbol" => string_to_symbol,
-		Function : "symbol-append" => symbol_append,
-		Function : "string-append" => string_append,
-		Function : "string-at" => string_at,
-		Function : "write" => write,
-		Function : "print" => print,
-		Function : "doc" => doc,
+		Macro    : "begin" => program,
+		Function : "read" => read (0 => 1),
+		Function : "eval" => eval_expose (1 => 1),
+		Function : "list" => list (0 => usize::MAX),
+		Function : "len" => list_length (1 => 1),
+		Function : "list-ref" => list_ref (2 => 2),
+		Function : "list-tail" => list_tail (2 => 2),
+		Function : "take" => take (2 => 2),
+		Function : "drop" => list_tail (2 => 2),
+		Function : "copy-list" => copy_list (1 => 1),
+		Function : "assoc" => assoc (2 => 2),
+		Function : "member" => member (2 => 2),
+		Function : "deep-reverse" => deep_reverse_list (1 => 1),
+		Function : "subst" => subst (3 => 3),
+		Function : "fold" => fold (3 => 3),
+		Function : "foldr" => fold_right (3 => 3),
+		Function : "filter" => filter (2 => 2),
+		Function : "for-each" => for_each (2 => 2),
+		Function : "sort" => sort (2 => 2),
+		Function : "->string" => to_string (1 => 1),
+		Function : "number->string" => number_to_string (1 => 2),
+		Function : "string->number" => string_to_number (1 => 2),
+		Function : "format" => format (1 => usize::MAX),
+		Function : "serialize" => serialize_data (1 => 1),
+		Function : "deserialize" => deserialize_data (1 => 1),
+		Function : "symbol->string" => symbol_to_string (1 => 1),
+		Function : "string->symbol" => string_to_symbol (1 => 1),
+		Function : "symbol-append" => symbol_append (1 => usize::MAX),
+		Function : "gensym" => gensym (0 => 0),
+		Function : "string-append" => string_append (0 => usize::MAX),
+		Function : "string-at" => string_at (2 => 2),
+		Function : "string-length" => string_length (1 => 1),
+		Function : "string-ref" => string_ref (2 => 2),
+		Function : "substring" => substring (3 => 3),
+		Function : "string-split" => string_split (2 => 2),
+		Function : "string-join" => string_join (2 => 2),
+		Function : "string-for-each" => string_for_each (2 => 2),
+		Function : "string-interpolate" => string_interpolate (1 => 2),
+		Function : "print" => print (1 => usize::MAX),
+		Function : "display" => display (1 => 1),
+		Function : "write" => write (1 => usize::MAX),
+		Function : "newline" => newline (0 => 0),
+		Function : "with-output-to-string" => with_output_to_string (1 => 1),
+		Function : "call-with-output-file" => call_with_output_file (2 => 2),
+		Function : "pp" => pretty_print_data (1 => 2),
+		Function : "pretty-print-to-string" => pretty_print_to_string (1 => 2),
+		Function : "doc" => doc (1 => 1),
 		Macro    : "\"" => string,
-		Function : "exit" => exit,
-		Function : "function-code" => function_code,
-		Function : "function-parameters" => function_parameters,
-		Function : "load" => load,
-		Function : "current-time-milliseconds" => current_time_milliseconds,
-		// Function : "table" => create_table,
+		Function : "exit" => exit (0 => 1),
+		Function : "function-code" => function_code (1 => 1),
+		Function : "function-parameters" => function_parameters (1 => 1),
+		Function : "load" => load (1 => 1),
+		Function : "read-file" => read_file (1 => 1),
+		Function : "read-all" => read_all (1 => 1),
+		Function : "current-time-milliseconds" => current_time_milliseconds (0 => 0),
+		Function : "clock" => clock (0 => 0),
+		Macro    : "time" => time_expression,
 		// Useful builtins
 		Function : "@program-count" => at_program_count,
-		Function : "@msleep" => msleep,
+		Function : "@msleep" => msleep (1 => 1),
 		Function : "@trace" => trace,
+		Function : "backtrace" => backtrace_calls (0 => 0),
 		Function : "@variable-count" => at_variable_count,
 		Function : "@variables" => at_variables,
 		Function : "@fail" => fail,
@@ -199,6 +316,55 @@ macro_rules! extype {
 	};
 }
 
+/// Absolute value of an integer, rational, or float.
+teko_simple_function!(abs args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Integer(ref value) => Ok(rcs(Coredata::Integer(value.abs()))),
+		Coredata::Rational(ref value) => Ok(rcs(Coredata::Rational(value.abs()))),
+		Coredata::Float(ref value) => Ok(rcs(Coredata::Float(Float(value.0.abs())))),
+		_ => Err(extype![args[0].0, Integer or Rational or Float, args[0]]),
+	}
+});
+
+/// Convert a number to a float. `(exact->inexact 1/2)` is `0.5`, floats are returned unchanged.
+teko_simple_function!(exact_to_inexact args : 1 => 1 => {
+	let number = Number::from_data(&args[0])?;
+	Ok(rcs(Coredata::Float(Float(number.to_f64()))))
+});
+
+/// Convert a float to the exact number it holds. `(inexact->exact 0.5)` is `1/2`.
+///
+/// Every finite float is a binary fraction, so the result is an integer or a rational with a
+/// power of two as denominator. Exact numbers are returned unchanged, NaN and infinities unwind.
+teko_simple_function!(inexact_to_exact args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Integer(_) | Coredata::Rational(_) => Ok(args[0].clone()),
+		Coredata::Float(ref value) => match BigRational::from_float(value.0) {
+			Some(number) => Ok(rcs(from_rational(number))),
+			None => Err((args[0].0.clone(), format!["no exact number for {:?}", value.0])),
+		},
+		_ => Err(extype![args[0].0, Integer or Rational or Float, args[0]]),
+	}
+});
+
+/// Check if a number is exact, which integers and rationals are and floats are not.
+teko_simple_function!(is_exact args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Integer(_) | Coredata::Rational(_) => Ok(rcs(Coredata::Boolean(true))),
+		Coredata::Float(_) => Ok(rcs(Coredata::Boolean(false))),
+		_ => Err(extype![args[0].0, Integer or Rational or Float, args[0]]),
+	}
+});
+
+/// Check if a number is inexact, which floats are and integers and rationals are not.
+teko_simple_function!(is_inexact args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Integer(_) | Coredata::Rational(_) => Ok(rcs(Coredata::Boolean(false))),
+		Coredata::Float(_) => Ok(rcs(Coredata::Boolean(true))),
+		_ => Err(extype![args[0].0, Integer or Rational or Float, args[0]]),
+	}
+});
+
 /// Logical AND.
 teko_simple_function!(and args : 0 => usize::MAX => {
 	for arg in args {
@@ -247,10 +413,10 @@ fn at_variable_count(_: &mut Program, env: &mut Env) -> Option<(Option<Source>,
 
 /// Simply return something that indicates failure
 fn fail(_: &mut Program, _: &mut Env) -> Option<(Option<Source>, String)> {
-	Some((Some(Source { line: 1, column: 20, source: "Dude".into() }), "Failure".into()))
+	Some((Some(Source { line: 1, column: 20, source: "Dude".into(), ..Source::default() }), "Failure".into()))
 }
 
-/// Find all active variables in the dynamic scope.
+/// Find all variables visible in the current scope and at the top level.
 fn at_variables(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
 	let mut builder = rcs(Coredata::Null());
 	for key in env.get_variables() {
@@ -263,6 +429,22 @@ fn at_variables(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, Strin
 	None
 }
 
+/// Check if a top level definition of `symbol` would clash with an existing variable.
+///
+/// A builtin bound under its own name does not clash, so `(define factorial ...)` shadows the
+/// builtin like any user definition would.
+fn is_taken(env: &Env, symbol: &Symbol) -> bool {
+	match env.get(symbol) {
+		Some(value) => match value.1 {
+			Coredata::Function(Function::Builtin(_, ref name, _)) | Coredata::Macro(Macro::Builtin(_, ref name)) => {
+				name.as_str() != Into::<&str>::into(symbol)
+			}
+			_ => true,
+		},
+		None => false,
+	}
+}
+
 /// Used by define to perform the final step of assigning.
 fn define_internal(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
 	let (key, value) = if let Some(args) = env.params.last() {
@@ -270,7 +452,7 @@ fn define_internal(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, St
 			match **symbol {
 				Sourcedata(ref source, Coredata::String(ref string)) => {
 					if let Some(rhs) = args.get(1) {
-						if env.does_variable_exist(&Symbol::from(string)) {
+						if is_taken(env, &Symbol::from(string)) {
 							return Some((
 								source.clone(),
 								format!["variable already exists: {}", string],
@@ -302,6 +484,7 @@ fn local(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String
 		let sub = rcs(Coredata::Function(Function::Builtin(
 			local_internal,
 			"@local-internal".into(),
+			None,
 		)));
 		let push = if let Some(ref tail) = args.tail() {
 			match tail.1 {
@@ -347,27 +530,22 @@ fn local(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String
 	None
 }
 
-/// Used by define to perform the final step of assigning.
-fn local_internal(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
-	let (key, value, existed) = if let Some(args) = env.params.last() {
+/// Used by local to perform the final step of assigning.
+///
+/// Binds the variable in the scope of the running call, or at the top level outside of calls.
+fn local_internal(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let (key, value) = if let Some(args) = env.params.last() {
 		if let Some(symbol) = args.first() {
 			match **symbol {
 				Sourcedata(ref source, Coredata::String(ref string)) => {
 					if let Some(rhs) = args.get(1) {
-						// Find earliest Depar
-						// Problem is what if we're inside a new function?
-						// That's fine, since we have a new depar
-						if let Some(depar) = find_earliest_depar(program) {
-							let pre = depar.check_preexistence_and_merge_single(&Symbol::from(string));
-							(Symbol::from(string), rhs.clone(), pre)
-						} else if env.does_variable_exist(&Symbol::from(string)) {
+						if env.scope.is_none() && is_taken(env, &Symbol::from(string)) {
 								return Some((
 									source.clone(),
 									format!["variable already exists: {}", string],
 								));
-						} else {
-							(Symbol::from(string), rhs.clone(), false)
 						}
+						(Symbol::from(string), rhs.clone())
 					} else {
 						return Some((source.clone(), arity_mismatch(2, 2, 1)));
 					}
@@ -382,11 +560,7 @@ fn local_internal(program: &mut Program, env: &mut Env) -> Option<(Option<Source
 	} else {
 		return Some((None, "no arg stack".into()));
 	};
-	if existed {
-		env.set(&key, value);
-	} else {
-		env.push(&key, value);
-	}
+	env.bind(&key, value);
 	None
 }
 
@@ -397,6 +571,7 @@ fn define(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, Strin
 		let sub = rcs(Coredata::Function(Function::Builtin(
 			define_internal,
 			"@define-internal".into(),
+			None,
 		)));
 		let push = if let Some(ref tail) = args.tail() {
 			match tail.1 {
@@ -442,387 +617,2156 @@ fn define(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, Strin
 	None
 }
 
-/// Mathematical division of integers.
-teko_simple_function!(divide args : 1 => usize::MAX => {
-	let mut sum = one();
-	if args.len() == 1 {
-		for arg in args.iter() {
-			match **arg {
-				Sourcedata(ref src, Coredata::Integer(ref value)) => {
-					if value == &zero::<BigInt>() {
-						return Err((src.clone(), "argument is zero".into()));
-					}
-					sum = sum / value;
-				}
-				Sourcedata(ref src, ..) => {
-					return Err(extype![src, Integer, arg]);
-				}
-			}
-		}
-	} else if args.len() > 1 {
-		let mut first = true;
-		for arg in args.iter() {
-			match **arg {
-				Sourcedata(ref src, Coredata::Integer(ref value)) => {
-					if first {
-						sum = value.clone();
-					} else {
-						if value == &zero::<BigInt>() {
-							return Err((src.clone(), "argument is zero".into()));
-						}
-						sum = sum / value;
-					}
-				}
-				Sourcedata(ref src, ..) => {
-					return Err(extype![src, Integer, arg]);
-				}
-			}
-			first = false;
-		}
+/// Define a library macro.
+///
+/// `(define-macro name bound body...)` is the same as `(define name (macro bound body...))`.
+/// The macro has a single bound parameter which receives the unevaluated argument list of each
+/// invocation. The body is evaluated with that binding and its result is then evaluated in place
+/// of the invocation. `bound` may also be written as a one-element list, as in `(args)`.
+fn define_macro(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let args = env.get_result();
+	match args.len() {
+		Some(len) if len >= 3 => {}
+		Some(len) => return Some((args.0.clone(), arity_mismatch(3, usize::MAX, len))),
+		None => return Some((args.0.clone(), "macro: input not Cell or Null()".into())),
 	}
-	Ok(rcs(Coredata::Integer(sum)))
-});
+	let name = args.head().unwrap();
+	let rest = args.tail().unwrap();
+	let bound = rest.head().unwrap();
+	let bound = match bound.1 {
+		Coredata::Symbol(..) => bound,
+		Coredata::Cell(ref head, ref tail) if tail.1 == Coredata::Null() => head.clone(),
+		_ => return Some(extype![bound.0, Symbol, bound]),
+	};
+	let definition = rc(Sourcedata(
+		rest.0.clone(),
+		Coredata::Cell(bound, rest.tail().unwrap()),
+	));
+	let definition = rcs(Coredata::Cell(
+		rcs(Coredata::Macro(Macro::Builtin(make_macro, "macro".into()))),
+		definition,
+	));
+	program.push(rc(Sourcedata(
+		args.0.clone(),
+		Coredata::Cell(
+			rcs(Coredata::Macro(Macro::Builtin(define, "define".into()))),
+			rcs(Coredata::Cell(name, rcs(Coredata::Cell(definition, rcs(Coredata::Null()))))),
+		),
+	)));
+	None
+}
 
-/// Retrieve the first statement of a function or macro.
-teko_simple_function!(doc args : 1 => 1 => {
+/// Read the content of a box.
+teko_simple_function!(box_ref args : 1 => 1 => {
 	let arg = args.first().unwrap();
-	match **arg {
-		Sourcedata(_, Coredata::Function(Function::Library(_, ref stats))) |
-		Sourcedata(_, Coredata::Macro(Macro::Library(_, ref stats))) => {
-			if stats.is_empty() {
-				Ok(rcs(Coredata::Null()))
-			} else {
-				Ok(stats.last().unwrap().clone())
-			}
-		}
-		Sourcedata(ref src, ..) => {
-			Err(extype![src, Function, arg])
-		}
+	match arg.1 {
+		Coredata::Box(ref boxed) => Ok(boxed.get()),
+		_ => Err(extype![arg.0, Box, arg]),
 	}
 });
 
-/// Integer equality comparison.
-teko_simple_function!(eq args : 0 => usize::MAX => {
-	let mut last = None;
-	let mut result = rcs(Coredata::Boolean(true));
-	for arg in args.iter() {
-		match **arg {
-			Sourcedata(_, Coredata::Integer(ref integer)) => {
-				if let Some(previous) = last {
-					if previous == integer {
-						// Do nothing
-					} else {
-						result = rcs(Coredata::Boolean(false));
-						break;
-					}
-					last = Some(integer);
-				} else {
-					last = Some(integer);
-				}
-			}
-			Sourcedata(ref src, ..) => {
-				return Err(extype![src, Integer, arg])
-			}
+/// Replace the content of a box, yielding the new content.
+teko_simple_function!(box_set args : 2 => 2 => {
+	let arg = &args[0];
+	match arg.1 {
+		Coredata::Box(ref boxed) => {
+			boxed.set(args[1].clone());
+			Ok(args[1].clone())
 		}
+		_ => Err(extype![arg.0, Box, arg]),
 	}
-	Ok(result)
 });
 
-/// Error constructor.
-///
-/// Error is its own type in Teko.
-teko_simple_function!(error args : 0 => 1 => {
-	if let Some(arg) = args.first() {
-		Ok(rcs(Coredata::Error(arg.clone())))
-	} else {
-		Ok(rcs(Coredata::Error(rcs(Coredata::Null()))))
-	}
+/// Construct a vector of the arguments.
+teko_simple_function!(vector args : 0 => usize::MAX => {
+	Ok(rcs(Coredata::Vector(Vector::new(args.clone()))))
 });
 
-teko_simple_function!(error_data args : 1 => 1 => {
-	if let Some(arg) = args.first() {
-		if let Sourcedata(_, Coredata::Error(ref err_data)) = **arg {
-			Ok(err_data.clone())
-		} else {
-			Ok(rcs(Coredata::Error(rcs(Coredata::Null()))))
+/// Get the index argument of the vector builtins, unwinding if it is out of bounds.
+fn vector_index(vector: &Vector, index: &Statement) -> Result<usize, (Option<Source>, String)> {
+	match index.1 {
+		Coredata::Integer(ref integer) => {
+			match integer.to_usize() {
+				Some(value) if value < vector.len() => Ok(value),
+				_ => Err((index.0.clone(), format!["index {} out of bounds for vector of length {}", integer, vector.len()])),
+			}
 		}
-	} else {
-		Ok(rcs(Coredata::Error(rcs(Coredata::Null()))))
+		_ => Err(extype![index.0, Integer, index]),
 	}
-});
+}
 
-teko_simple_function!(function_code args : 1 => 1 => {
-	use utilities::program_to_cells;
-	match **args.first().unwrap() {
-		Sourcedata(ref src, Coredata::Function(Function::Builtin(..))) => {
-			Err((src.clone(), format!["expected Function but got {}", data_name(args.first().unwrap())]))
-		}
-		Sourcedata(_, Coredata::Function(Function::Library(_, ref program))) => {
-			Ok(program_to_cells(program))
-		}
-		Sourcedata(ref src, ..) => {
-			Err(extype![src, Function, args.first().unwrap()])
-		}
+/// Get an element of a vector. `(vector-ref vector index)`
+teko_simple_function!(vector_ref args : 2 => 2 => {
+	match args[0].1 {
+		Coredata::Vector(ref vector) => Ok(vector.get(vector_index(vector, &args[1])?).unwrap()),
+		_ => Err(extype![args[0].0, Vector, args[0]]),
 	}
 });
 
-teko_simple_function!(function_parameters args : 1 => 1 => {
-	let mut top = rcs(Coredata::Null());
-	match **args.first().unwrap() {
-		Sourcedata(ref src, Coredata::Function(Function::Builtin(..))) => {
-			return Err((src.clone(), format!["expected Function but got {}", data_name(args.first().unwrap())]));
-		}
-		Sourcedata(_, Coredata::Function(Function::Library(ref params, _))) => {
-			for i in params.iter().rev() {
-				top = rcs(Coredata::Cell(rcs(Coredata::Symbol(i.clone())), top));
-			}
-		}
-		Sourcedata(ref src, ..) => {
-			return Err(extype![src, Function, args.first().unwrap()]);
-		}
+/// Get the number of elements in a vector.
+teko_simple_function!(vector_length args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Vector(ref vector) => Ok(rcs(Coredata::Integer(vector.len().into()))),
+		_ => Err(extype![args[0].0, Vector, args[0]]),
 	}
-	Ok(top)
 });
 
-/// Evals the argument as if it's a program.
-fn eval_expose(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
-	if let Some(args) = env.params.last() {
-		if args.len() != 1 {
-			Some((None, arity_mismatch(1, 1, args.len())))
-		} else if let Some(arg) = args.first() {
-			program.push(arg.clone());
-			None
-		} else {
-			Some((None, arity_mismatch(1, 1, args.len())))
-		}
-	} else {
-		Some((None, "no argument stack".into()))
-	}
-}
-
-/// Exit the entire program.
-teko_simple_function!(exit args : 0 => 1 => {
-	if let Some(arg) = args.last() {
-		match **arg {
-			Sourcedata(ref src, Coredata::Integer(ref value)) => {
-				if let Some(value) = value.to_i32() {
-					::std::process::exit(value);
-				} else {
-					Err((src.clone(), "unable to convert number to value".into()))
-				}
-			}
-			Sourcedata(ref src, ..) => {
-				Err(extype![src, Integer, arg])
-			}
+/// Replace an element of a vector, yielding the new element. `(vector-set! vector index value)`
+teko_simple_function!(vector_set args : 3 => 3 => {
+	match args[0].1 {
+		Coredata::Vector(ref vector) => {
+			vector.set(vector_index(vector, &args[1])?, args[2].clone());
+			Ok(args[2].clone())
 		}
-	} else {
-		::std::process::exit(0);
+		_ => Err(extype![args[0].0, Vector, args[0]]),
 	}
 });
 
-/// Construct a function object with dynamic scope.
-teko_simple_macro!(function args : 2 => usize::MAX => {
-	if let Some(head) = args.head() {
-		let params = if let Some(params) = collect_cell_of_symbols_into_vec(&head) {
-			params
-		} else {
-			return Err((None, "parameter list contains non-symbols".into()));
-		};
-		if let Some(tail) = args.tail() {
-			let code = collect_cell_into_revvec(&tail);
-			Ok(rcs(Coredata::Function(Function::Library(params, code))))
-		} else {
-			Err((None, "tail is empty".into()))
-		}
-	} else {
-		Err((None, "parameter list is not a list".into()))
+/// Get the Unicode code point of a character.
+teko_simple_function!(char_to_integer args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Char(character) => Ok(rcs(Coredata::Integer((character as u32).into()))),
+		_ => Err(extype![args[0].0, Char, args[0]]),
 	}
 });
 
-/// The greater-than function for comparing integers.
-teko_simple_function!(gt args : 0 => usize::MAX => {
-	let mut last = None;
-	let mut result = rcs(Coredata::Boolean(true));
-	for arg in args.iter() {
-		match **arg {
-			Sourcedata(_, Coredata::Integer(ref integer)) => {
-				if let Some(previous) = last {
-					if previous > integer {
-						// Do nothing
-					} else {
-						result = rcs(Coredata::Boolean(false));
-						break;
-					}
-					last = Some(integer);
-				} else {
-					last = Some(integer);
-				}
-			}
-			Sourcedata(ref src, ..) => {
-				return Err(extype![src, Integer, arg]);
+/// Get the character with the given code point, unwinding if it is not a Unicode scalar value.
+teko_simple_function!(integer_to_char args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Integer(ref integer) => {
+			match integer.to_u32().and_then(::std::char::from_u32) {
+				Some(character) => Ok(rcs(Coredata::Char(character))),
+				None => Err((args[0].0.clone(), format!["{} is not a Unicode scalar value", integer])),
 			}
 		}
+		_ => Err(extype![args[0].0, Integer, args[0]]),
 	}
-	Ok(result)
 });
 
-/// Take the head of a cell.
+/// Get a table key, unwinding if the data is not an integer, string, or symbol.
+fn table_key(data: &Statement) -> Result<Statement, (Option<Source>, String)> {
+	Table::key(data).ok_or_else(|| extype![data.0, Integer or String or Symbol, data])
+}
+
+/// Construct a table from alternating keys and values. `(make-table key value ...)`
 ///
-/// If the argument is not a cell then this will unwind with
-/// an error.
-teko_simple_function!(head args : 1 => 1 => {
-	let arg = args.first().unwrap();
-	if let Some(head) = arg.head() {
-		Ok(head.clone())
-	} else {
-		return Err(extype![arg.0, Cell, arg]);
+/// Tables are persistent: `table-set` gives a new table and leaves the original unchanged.
+teko_simple_function!(make_table args : 0 => usize::MAX => {
+	if args.len() % 2 != 0 {
+		return Err((None, "expected an even number of arguments (alternating keys and values)".into()));
+	}
+	let mut table = Table::default();
+	for pair in args.chunks(2) {
+		table = table.insert(table_key(&pair[0])?, pair[1].clone());
 	}
+	Ok(rcs(Coredata::Table(table)))
 });
 
-/// Conditional branching primitive.
-fn if_conditional(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
-	let arg = env.get_result();
-	if let Some(head) = arg.head() {
-		if let Some(tail) = arg.tail() {
-			if let Some(head_of_tail) = tail.head() {
-				if let Some(tail_of_tail) = tail.tail() {
-					if let Some(head_of_tail_of_tail) = tail_of_tail.head() {
-						program.push(rcs(Coredata::Internal(
-							Commands::If(head_of_tail, head_of_tail_of_tail),
-						)));
-						program.push(head);
-						return None;
-					} else {
-						Some((None, arity_mismatch(3, 3, 2)))
-					}
-				} else {
-					Some((None, arity_mismatch(3, 3, 1)))
-				}
+/// Get the value of a key, or the default if the key is missing. `(table-get table key [default])`
+///
+/// Unwinds if the key is missing and no default is given.
+teko_simple_function!(table_get args : 2 => 3 => {
+	match args[0].1 {
+		Coredata::Table(ref table) => {
+			if let Some(value) = table.get(&table_key(&args[1])?) {
+				Ok(value)
+			} else if let Some(default) = args.get(2) {
+				Ok(default.clone())
 			} else {
-				Some((None, arity_mismatch(3, 3, 1)))
+				Err((args[1].0.clone(), format!["key not found: {}", args[1]]))
 			}
-		} else {
-			Some((None, arity_mismatch(3, 3, 1)))
 		}
-	} else {
-		Some((None, arity_mismatch(3, 3, 0)))
+		_ => Err(extype![args[0].0, Table, args[0]]),
 	}
-}
+});
 
-/// Check if data is the same.
-teko_simple_function!(is_data_eq args : 0 => usize::MAX => {
-	let mut last = None;
-	let mut result = rcs(Coredata::Boolean(true));
-	for arg in args.iter() {
-		let data = &arg.1;
-		if let Some(previous) = last {
-			if previous == data {
-				// Do nothing
-			} else {
-				result = rcs(Coredata::Boolean(false));
-				break;
+/// List the keys of a table: integers first, then strings, then symbols, each in order.
+teko_simple_function!(table_keys args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Table(ref table) => {
+			let mut result = rcs(Coredata::Null());
+			for key in table.keys().into_iter().rev() {
+				result = rcs(Coredata::Cell(key, result));
 			}
-			last = Some(data);
-		} else {
-			last = Some(data);
+			Ok(result)
 		}
+		_ => Err(extype![args[0].0, Table, args[0]]),
 	}
-	Ok(result)
 });
 
-/// Check if a value is an error type.
-teko_simple_function!(is_error args : 1 => 1 => {
-	let arg = args.first().unwrap();
-	if let Coredata::Error(_) = arg.1 {
-		Ok(rcs(Coredata::Boolean(true)))
-	} else {
-		Ok(rcs(Coredata::Boolean(false)))
+/// Create a new table with a key set to a value. `(table-set table key value)`
+teko_simple_function!(table_set args : 3 => 3 => {
+	match args[0].1 {
+		Coredata::Table(ref table) => {
+			Ok(rcs(Coredata::Table(table.insert(table_key(&args[1])?, args[2].clone()))))
+		}
+		_ => Err(extype![args[0].0, Table, args[0]]),
 	}
 });
 
-/// Check if the value is a cell type.
-teko_simple_function!(is_cell args : 1 => 1 => {
+/// Reconstruct data from the binary format written by `serialize`.
+teko_simple_function!(deserialize_data args : 1 => 1 => {
 	let arg = args.first().unwrap();
-	if let Coredata::Cell(..) = arg.1 {
-		Ok(rcs(Coredata::Boolean(true)))
-	} else {
-		Ok(rcs(Coredata::Boolean(false)))
+	match arg.1 {
+		Coredata::Bytes(ref bytes) => deserialize(bytes).map_err(|error| (arg.0.clone(), error)),
+		_ => Err(extype![arg.0, Bytes, arg]),
 	}
 });
 
-/// Check if the value is a symbol.
-teko_simple_function!(is_symbol args : 1 => 1 => {
+/// Reverse a list along with all of its sublists.
+teko_simple_function!(deep_reverse_list args : 1 => 1 => {
 	let arg = args.first().unwrap();
-	if let Coredata::Symbol(_) = arg.1 {
-		Ok(rcs(Coredata::Boolean(true)))
-	} else {
-		Ok(rcs(Coredata::Boolean(false)))
+	match arg.1 {
+		Coredata::Cell(..) | Coredata::Null() => Ok(deep_reverse(arg)),
+		_ => Err(extype![arg.0, Cell or Null, arg]),
 	}
 });
 
-/// Compute the length of a list.
-teko_simple_function!(list_length args : 1 => 1 => {
-	let arg = args.first().unwrap();
-	if let Some(len) = arg.len() {
-		Ok(rcs(Coredata::Integer(len.into())))
-	} else {
-		Err(extype![arg.0, String or Cell, arg])
-	}
-});
+/// A number taking part in arithmetic.
+///
+/// Mixing an integer with a rational gives a rational, mixing either with a float gives a float.
+enum Number {
+	Integer(BigInt),
+	Rational(BigRational),
+	Float(f64),
+}
 
-/// Construct a list (nested cell) of items.
-teko_simple_function!(list args : 0 => usize::MAX => {
-	let mut result = rcs(Coredata::Null());
-	for arg in args.iter().rev() {
-		result = rcs(Coredata::Cell(arg.clone(), result));
+impl Number {
+	fn from_data(data: &Statement) -> Result<Number, (Option<Source>, String)> {
+		match data.1 {
+			Coredata::Integer(ref value) => Ok(Number::Integer(value.clone())),
+			Coredata::Rational(ref value) => Ok(Number::Rational(value.clone())),
+			Coredata::Float(ref value) => Ok(Number::Float(value.0)),
+			_ => Err(extype![data.0, Integer or Rational or Float, data]),
+		}
+	}
+	fn to_f64(&self) -> f64 {
+		match *self {
+			Number::Integer(ref value) => value.to_f64().unwrap_or(f64::NAN),
+			Number::Rational(ref value) => rational_to_f64(value),
+			Number::Float(value) => value,
+		}
+	}
+	/// The exact value, `None` for a float.
+	fn to_rational(&self) -> Option<BigRational> {
+		match *self {
+			Number::Integer(ref value) => Some(BigRational::from_integer(value.clone())),
+			Number::Rational(ref value) => Some(value.clone()),
+			Number::Float(_) => None,
+		}
+	}
+	fn into_data(self) -> Statement {
+		match self {
+			Number::Integer(value) => rcs(Coredata::Integer(value)),
+			Number::Rational(value) => rcs(from_rational(value)),
+			Number::Float(value) => rcs(Coredata::Float(Float(value))),
+		}
+	}
+	fn compare(&self, other: &Number) -> Option<cmp::Ordering> {
+		match (self, other) {
+			(Number::Integer(lhs), Number::Integer(rhs)) => Some(lhs.cmp(rhs)),
+			_ => match (self.to_rational(), other.to_rational()) {
+				(Some(lhs), Some(rhs)) => Some(lhs.cmp(&rhs)),
+				_ => self.to_f64().partial_cmp(&other.to_f64()),
+			},
+		}
+	}
+}
+
+/// Convert a rational to the nearest float, also when the numerator or denominator alone does not fit.
+fn rational_to_f64(value: &BigRational) -> f64 {
+	// Keep 64 significant bits in the quotient and scale it back afterwards
+	let shift = value.denom().bits() as i32 - value.numer().bits() as i32 + 64;
+	let quotient = if shift >= 0 {
+		(value.numer() << shift as usize) / value.denom()
+	} else {
+		value.numer() / (value.denom() << -shift as usize)
+	};
+	let scale = 2f64.powi(-shift / 2);
+	quotient.to_f64().unwrap_or(f64::NAN) * scale * scale * 2f64.powi(-shift % 2)
+}
+
+/// Fold the arguments from left to right, a single argument is combined with `identity` first.
+///
+/// Two integers are combined with `integer`, any other exact numbers with `rational`.
+fn arithmetic(
+	args: &[Statement],
+	identity: BigInt,
+	integer: fn(BigInt, &BigInt) -> BigInt,
+	rational: fn(BigRational, &BigRational) -> BigRational,
+	float: fn(f64, f64) -> f64,
+	zero_divisor: bool,
+) -> Result<Statement, (Option<Source>, String)> {
+	let (mut result, rest) = if args.len() > 1 {
+		(Number::from_data(&args[0])?, &args[1..])
+	} else {
+		(Number::Integer(identity), args)
+	};
+	for arg in rest {
+		result = match (result, Number::from_data(arg)?) {
+			(Number::Integer(lhs), Number::Integer(rhs)) => {
+				if zero_divisor && rhs.is_zero() {
+					return Err((arg.0.clone(), "argument is zero".into()));
+				}
+				Number::Integer(integer(lhs, &rhs))
+			}
+			(lhs, rhs) => match (lhs.to_rational(), rhs.to_rational()) {
+				(Some(lhs), Some(rhs)) => {
+					if zero_divisor && rhs.is_zero() {
+						return Err((arg.0.clone(), "argument is zero".into()));
+					}
+					Number::Rational(rational(lhs, &rhs))
+				}
+				_ => Number::Float(float(lhs.to_f64(), rhs.to_f64())),
+			},
+		};
+	}
+	Ok(result.into_data())
+}
+
+/// Check that each argument is related to the next by `accept`, integers are compared exactly.
+fn compare_numbers(
+	args: &[Statement],
+	accept: fn(cmp::Ordering) -> bool,
+) -> Result<Statement, (Option<Source>, String)> {
+	let mut last: Option<Number> = None;
+	for arg in args {
+		let current = Number::from_data(arg)?;
+		if let Some(previous) = last {
+			if !previous.compare(&current).is_some_and(accept) {
+				return Ok(rcs(Coredata::Boolean(false)));
+			}
+		}
+		last = Some(current);
+	}
+	Ok(rcs(Coredata::Boolean(true)))
+}
+
+/// Find the argument that is ordered `wanted` relative to all others.
+///
+/// Exact arguments are compared exactly, so `(min 1/2 1/3)` is `1/3`. The result is a float if
+/// any argument is a float, and NaN if any argument is NaN.
+fn extremum(args: &[Statement], wanted: cmp::Ordering) -> Result<Statement, (Option<Source>, String)> {
+	let mut result = Number::from_data(&args[0])?;
+	let mut inexact = false;
+	for arg in args {
+		let current = Number::from_data(arg)?;
+		if let Number::Float(..) = current {
+			inexact = true;
+		}
+		match current.compare(&result) {
+			Some(ordering) if ordering == wanted => result = current,
+			Some(_) => {}
+			None => result = Number::Float(f64::NAN),
+		}
+	}
+	if inexact {
+		result = Number::Float(result.to_f64());
+	}
+	Ok(result.into_data())
+}
+
+/// The largest of the arguments. `(max number ...)`
+teko_simple_function!(max args : 1 => usize::MAX => {
+	extremum(args, cmp::Ordering::Greater)
+});
+
+/// The smallest of the arguments. `(min number ...)`
+teko_simple_function!(min args : 1 => usize::MAX => {
+	extremum(args, cmp::Ordering::Less)
+});
+
+/// Mathematical division. Integers are divided truncating towards zero, rationals and floats are not.
+teko_simple_function!(divide args : 1 => usize::MAX => {
+	arithmetic(args, one(), |lhs, rhs| lhs / rhs, |lhs, rhs| lhs / rhs, |lhs, rhs| lhs / rhs, true)
+});
+
+/// Read an optional radix argument, defaulting to 10.
+fn radix_argument(arg: Option<&Statement>) -> Result<u32, (Option<Source>, String)> {
+	match arg {
+		Some(arg) => {
+			if let Coredata::Integer(ref radix) = arg.1 {
+				match radix.to_u32() {
+					Some(radix) if (2..=36).contains(&radix) => Ok(radix),
+					_ => Err((arg.0.clone(), format!["radix must be between 2 and 36 but got {}", radix])),
+				}
+			} else {
+				Err(extype![arg.0, Integer, arg])
+			}
+		}
+		None => Ok(10),
+	}
+}
+
+/// Read the optional radix of `number->string` and `string->number`: 2, 8, 10 or 16, defaulting to 10.
+fn number_radix_argument(arg: Option<&Statement>) -> Result<u32, (Option<Source>, String)> {
+	match arg {
+		Some(arg) => match arg.1 {
+			Coredata::Integer(ref radix) => match radix.to_u32() {
+				Some(radix @ 2) | Some(radix @ 8) | Some(radix @ 10) | Some(radix @ 16) => Ok(radix),
+				_ => Err((arg.0.clone(), format!["unsupported radix {}", radix])),
+			},
+			_ => Err(extype![arg.0, Integer, arg]),
+		},
+		None => Ok(10),
+	}
+}
+
+/// List the digits of a non-negative integer, most significant first. `(digits n [radix])`
+teko_simple_function!(digits args : 1 => 2 => {
+	let radix = radix_argument(args.get(1))?;
+	let arg = &args[0];
+	match arg.1 {
+		Coredata::Integer(ref value) => {
+			if value < &zero::<BigInt>() {
+				return Err((arg.0.clone(), "expected a non-negative integer".into()));
+			}
+			let mut result = rcs(Coredata::Null());
+			for digit in value.to_str_radix(radix).chars().rev() {
+				let digit = digit.to_digit(radix).unwrap();
+				result = rcs(Coredata::Cell(rcs(Coredata::Integer(digit.into())), result));
+			}
+			Ok(result)
+		}
+		_ => Err(extype![arg.0, Integer, arg]),
+	}
+});
+
+/// Construct an integer from a list of digits, most significant first. `(from-digits list [radix])`
+teko_simple_function!(from_digits args : 1 => 2 => {
+	let radix = radix_argument(args.get(1))?;
+	let arg = &args[0];
+	match arg.1 {
+		Coredata::Cell(..) | Coredata::Null() => {}
+		_ => return Err(extype![arg.0, Cell or Null, arg]),
+	}
+	let mut result: BigInt = zero();
+	for digit in collect_cell_into_revvec(arg).iter().rev() {
+		match digit.1 {
+			Coredata::Integer(ref value) if value.to_u32().is_some_and(|value| value < radix) => {
+				result = result * BigInt::from(radix) + value;
+			}
+			Coredata::Integer(ref value) => {
+				return Err((digit.0.clone(), format!["{} is not a digit in radix {}", value, radix]));
+			}
+			_ => return Err(extype![digit.0, Integer, digit]),
+		}
+	}
+	Ok(rcs(Coredata::Integer(result)))
+});
+
+/// List numbers from `start` up to but not including `end`. `(range [start] end [step])`
+///
+/// `start` defaults to 0 and `step` to 1. A negative step counts down to `end` instead, and a
+/// zero step unwinds. If `start` or `step` is a float the elements are floats, computed as
+/// `start + index * step` so that errors do not add up.
+teko_simple_function!(range args : 1 => 3 => {
+	let (start, end) = if args.len() == 1 {
+		(Number::Integer(zero()), Number::from_data(&args[0])?)
+	} else {
+		(Number::from_data(&args[0])?, Number::from_data(&args[1])?)
+	};
+	let step = match args.get(2) {
+		Some(step) => Number::from_data(step)?,
+		None => Number::Integer(one()),
+	};
+	let before_end = match step.compare(&Number::Integer(zero())) {
+		Some(cmp::Ordering::Equal) => return Err((args[2].0.clone(), "step is zero".into())),
+		Some(ordering) => Some(ordering.reverse()),
+		None => return Err((args[2].0.clone(), "step is not a number".into())),
+	};
+	let mut elements = vec![];
+	match (start, step) {
+		(Number::Integer(start), Number::Integer(step)) => {
+			let mut current = start;
+			while Number::Integer(current.clone()).compare(&end) == before_end {
+				elements.push(rcs(Coredata::Integer(current.clone())));
+				current = current + &step;
+			}
+		}
+		(start, step) => {
+			let (start, step) = (start.to_f64(), step.to_f64());
+			for index in 0.. {
+				let current = start + index as f64 * step;
+				if Number::Float(current).compare(&end) != before_end {
+					break;
+				}
+				elements.push(rcs(Coredata::Float(Float(current))));
+			}
+		}
+	}
+	Ok(elements.into_iter().rev().fold(rcs(Coredata::Null()), |tail, head| cons(head, tail)))
+});
+
+/// Iterate until a test holds. `(do ((variable init step)...) (test result...) body...)`
+///
+/// Each variable is bound to its init, then while the test is false the body is evaluated and
+/// the variables are bound to their steps, which are evaluated before any variable changes. A
+/// variable without a step keeps its value. Once the test is true the result expressions are
+/// evaluated, the last giving the value of the loop, or `()` if there are none.
+///
+/// The loop is a tail recursive function called by a fresh symbol, so it runs in constant
+/// space. The function is passed to itself rather than bound in its own scope, which would make
+/// the scope and the function hold each other.
+fn do_loop(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let list = |elements: Vec<Statement>| -> Statement { rc(elements.into()) };
+	let args = env.get_result();
+	match args.len() {
+		Some(len) if len >= 2 => {}
+		Some(len) => return Some((args.0.clone(), arity_mismatch(2, usize::MAX, len))),
+		None => return Some((args.0.clone(), "macro: input not Cell or Null()".into())),
+	}
+	let bindings = args.head().unwrap();
+	let rest = args.tail().unwrap();
+	let clause = rest.head().unwrap();
+	let body = collect_cell_into_revvec(&rest.tail().unwrap());
+	let (mut variables, mut inits, mut steps) = (vec![], vec![], vec![]);
+	match bindings.1 {
+		Coredata::Cell(..) | Coredata::Null() => {}
+		_ => return Some(extype![bindings.0, Cell or Null, bindings]),
+	}
+	for binding in collect_cell_into_revvec(&bindings).into_iter().rev() {
+		let parts = collect_cell_into_revvec(&binding);
+		match (binding.len(), parts.last().map(|variable| &variable.1)) {
+			(Some(2), Some(&Coredata::Symbol(..))) | (Some(3), Some(&Coredata::Symbol(..))) => {}
+			_ => {
+				return Some((binding.0.clone(), format!["expected a variable with an init and an optional step but got {}", binding]));
+			}
+		}
+		let variable = parts[parts.len() - 1].clone();
+		inits.push(parts[parts.len() - 2].clone());
+		steps.push(if parts.len() == 3 { parts[0].clone() } else { variable.clone() });
+		variables.push(variable);
+	}
+	if let (Coredata::Cell(..), Some(test), Some(results)) = (&clause.1, clause.head(), clause.tail()) {
+		let name = fresh_symbol();
+		let function = rcs(Coredata::Macro(Macro::Builtin(self::function, "function".into())));
+		let begin = rcs(Coredata::Macro(Macro::Builtin(self::program, "begin".into())));
+		let results = if let Coredata::Null() = results.1 {
+			list(vec![begin.clone(), list(vec![])])
+		} else {
+			rcs(Coredata::Cell(begin.clone(), results))
+		};
+		let mut iteration = vec![begin];
+		iteration.extend(body.into_iter().rev());
+		iteration.push(cons(name.clone(), cons(name.clone(), list(steps))));
+		let if_macro = rcs(Coredata::Macro(Macro::Builtin(if_conditional, "if".into())));
+		let step = list(vec![function.clone(), cons(name.clone(), list(variables)), list(vec![if_macro, test, results, list(iteration)])]);
+		let start = list(vec![function, list(vec![name.clone()]), cons(name.clone(), cons(name, list(inits)))]);
+		program.push(rc(Sourcedata(args.0.clone(), Coredata::Cell(start, list(vec![step])))));
+		None
+	} else {
+		Some((clause.0.clone(), format!["expected a test clause but got {}", clause]))
+	}
+}
+
+/// Write data to `env.output` for people to read, without a trailing newline. `(display data)`
+///
+/// Uses the list notation of the `print` module, but strings and characters are written as their text.
+/// Unlike `write` the output can not always be read back.
+fn display(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let text = match env.params.last() {
+		Some(args) if args.len() == 1 => ::print::display(&args[0]),
+		Some(args) => return Some((None, arity_mismatch(1, 1, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	if write!(env.output, "{}", text).and_then(|_| env.output.flush()).is_err() {
+		return Some((None, "unable to write output".into()));
+	}
+	env.set_result(rcs(Coredata::Null()));
+	None
+}
+
+/// Write each argument on a line of its own to `env.output`. `(write data...)`
+///
+/// Writing is a symmetric operation together with read. Data is written in the list notation
+/// of the `print` module with symbols bare, so `parse_string` reads the output back as equal
+/// data. Strings and characters are written as `(" text)` and `#\x`, which evaluate to them.
+/// The result is the last argument.
+fn write(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let args = match env.params.last() {
+		Some(args) if !args.is_empty() => args.clone(),
+		Some(args) => return Some((None, arity_mismatch(1, usize::MAX, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	for arg in &args {
+		if writeln!(env.output, "{}", ::print::to_string(arg)).is_err() {
+			return Some((None, "unable to write output".into()));
+		}
+	}
+	if env.output.flush().is_err() {
+		return Some((None, "unable to write output".into()));
+	}
+	env.set_result(args.last().unwrap().clone());
+	None
+}
+
+/// Call a function without arguments and get what it wrote. `(with-output-to-string thunk)`
+///
+/// `env.output` is captured until the call returns, so `display`, `write` and `newline` write
+/// into the resulting string. The call is pushed as
+/// `(@with-output-to-string-internal (wind (list (thunk))))`, which puts the output back even
+/// when the call unwinds, and then continues unwinding with the same error. An error value the
+/// call returns is not raised, so it gives the output like any other result.
+fn with_output_to_string(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let thunk = match env.params.last() {
+		Some(args) if args.len() == 1 => args[0].clone(),
+		Some(args) => return Some((None, arity_mismatch(1, 1, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	if let Coredata::Function(..) = thunk.1 {
+	} else {
+		return Some((thunk.0.clone(), format!["expected Function but got {}", data_name(&thunk)]));
+	}
+	env.capture_output();
+	let internal = rcs(Coredata::Function(Function::Builtin(with_output_to_string_internal, "@with-output-to-string-internal".into(), None)));
+	program.push(cons(internal, cons(wind_call(thunk), rcs(Coredata::Null()))));
+	None
+}
+
+/// Call a thunk so that a raised error can be told from a returned value, `(wind (list (thunk)))`.
+///
+/// A returned value comes back in a list of one element. A raised error unwinds past `list` to
+/// the `wind` and comes back bare, see `raised`.
+fn wind_call(thunk: Statement) -> Statement {
+	wind_list(cons(thunk, rcs(Coredata::Null())))
+}
+
+/// Evaluate an expression so that a raised error can be told from a returned value, `(wind (list expression))`.
+fn wind_list(expression: Statement) -> Statement {
+	let null = rcs(Coredata::Null());
+	let wind_macro = rcs(Coredata::Macro(Macro::Builtin(wind, "wind".into())));
+	let list_function = rcs(Coredata::Function(Function::Builtin(list, "list".into(), None)));
+	let call = cons(list_function, cons(expression, null.clone()));
+	cons(wind_macro, cons(call, null))
+}
+
+/// Tell the result of `wind_call` apart, `Err` with the error if one was raised.
+fn raised(value: &Statement) -> Result<Statement, Statement> {
+	value.head().ok_or_else(|| value.clone())
+}
+
+/// Release the output captured by `with-output-to-string`, the argument is the result of the call.
+fn with_output_to_string_internal(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let value = match env.params.last() {
+		Some(args) if args.len() == 1 => args[0].clone(),
+		Some(args) => return Some((None, arity_mismatch(1, 1, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	let written = match env.release_output() {
+		Some(written) => written,
+		None => return Some((None, "output is not captured".into())),
+	};
+	if raised(&value).is_err() {
+		return unwind(program, env);
+	}
+	env.set_result(rcs(Coredata::String(String::from_utf8_lossy(&written).into_owned())));
+	None
+}
+
+/// Call a function without arguments while writing output to a file. `(call-with-output-file path thunk)`
+///
+/// The file is created or truncated, and `env.output` writes to it until the call returns, at
+/// which point the file is flushed and closed. The result is the result of the call. Like
+/// `with-output-to-string` the output is put back when the call unwinds. Unwinds if the file
+/// can not be opened or written.
+fn call_with_output_file(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	use std::fs::File;
+	let (path, thunk) = match env.params.last() {
+		Some(args) if args.len() == 2 => (args[0].clone(), args[1].clone()),
+		Some(args) => return Some((None, arity_mismatch(2, 2, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	let filename = match path.1 {
+		Coredata::String(ref filename) => filename,
+		_ => return Some(extype![path.0, String, path]),
+	};
+	if let Coredata::Function(..) = thunk.1 {
+	} else {
+		return Some((thunk.0.clone(), format!["expected Function but got {}", data_name(&thunk)]));
+	}
+	let file = match File::create(filename) {
+		Ok(file) => file,
+		Err(error) => return Some((path.0.clone(), format!["unable to open {}: {}", filename, error.kind()])),
+	};
+	env.redirect_output(Box::new(io::BufWriter::new(file)));
+	let internal = rcs(Coredata::Function(Function::Builtin(call_with_output_file_internal, "@call-with-output-file-internal".into(), None)));
+	program.push(cons(internal, cons(quoted(&path), cons(wind_call(thunk), rcs(Coredata::Null())))));
+	None
+}
+
+/// Flush and close the file of `call-with-output-file`. `(@call-with-output-file-internal path value)`
+fn call_with_output_file_internal(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let (path, value) = match env.params.last() {
+		Some(args) if args.len() == 2 => (args[0].clone(), args[1].clone()),
+		Some(args) => return Some((None, arity_mismatch(2, 2, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	let flushed = match env.restore_output() {
+		Some(mut file) => file.flush(),
+		None => return Some((None, "output is not redirected".into())),
+	};
+	let value = match raised(&value) {
+		Ok(value) => value,
+		Err(_) => return unwind(program, env),
+	};
+	if let (Err(error), Coredata::String(ref filename)) = (flushed, &path.1) {
+		return Some((path.0.clone(), format!["unable to write {}: {}", filename, error.kind()]));
+	}
+	env.set_result(value);
+	None
+}
+
+/// Retrieve the first statement of a function or macro.
+teko_simple_function!(doc args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	match **arg {
+		Sourcedata(_, Coredata::Function(Function::Library(_, ref stats, _))) |
+		Sourcedata(_, Coredata::Macro(Macro::Library(_, ref stats))) => {
+			if stats.is_empty() {
+				Ok(rcs(Coredata::Null()))
+			} else {
+				Ok(stats.last().unwrap().clone())
+			}
+		}
+		Sourcedata(ref src, ..) => {
+			Err(extype![src, Function, arg])
+		}
+	}
+});
+
+/// Numeric equality comparison.
+teko_simple_function!(eq args : 0 => usize::MAX => {
+	compare_numbers(args, |ordering| ordering == cmp::Ordering::Equal)
+});
+
+/// Numeric inequality, true if no two arguments are equal. `(!= number...)`
+///
+/// Every pair is compared, not only adjacent ones, so `(!= 1 2 1)` is false. Integers and
+/// floats compare by value, so `(!= 1 1.0)` is false as well. NaN differs from everything.
+teko_simple_function!(not_eq args : 0 => usize::MAX => {
+	let numbers = args.iter().map(Number::from_data).collect::<Result<Vec<_>, _>>()?;
+	for (index, number) in numbers.iter().enumerate() {
+		if numbers[index + 1..].iter().any(|other| number.compare(other) == Some(cmp::Ordering::Equal)) {
+			return Ok(rcs(Coredata::Boolean(false)));
+		}
+	}
+	Ok(rcs(Coredata::Boolean(true)))
+});
+
+/// Raise an error, unwinding to the nearest `wind`. `(error message irritant...)`
+///
+/// Bound to both `error` and `raise`. The message is a string or symbol, any further arguments
+/// (irritants) are written after the message separated by spaces. Use `make-error` for an error
+/// value that is not raised.
+teko_simple_function!(error args : 1 => usize::MAX => {
+	let message = args.first().unwrap();
+	let mut message = match message.1 {
+		Coredata::String(ref string) => string.clone(),
+		Coredata::Symbol(ref symbol) => {
+			let symbol: &str = symbol.into();
+			symbol.to_string()
+		}
+		_ => return Err(extype![message.0, String or Symbol, message]),
+	};
+	for irritant in args.iter().skip(1) {
+		message.push_str(&format![" {}", irritant]);
+	}
+	Err((None, message))
+});
+
+teko_simple_function!(error_data args : 1 => 1 => {
+	if let Some(arg) = args.first() {
+		if let Sourcedata(_, Coredata::Error(ref error)) = **arg {
+			Ok(error.data.clone())
+		} else {
+			Ok(rcs(Coredata::Error(EvalError::new(rcs(Coredata::Null())))))
+		}
+	} else {
+		Ok(rcs(Coredata::Error(EvalError::new(rcs(Coredata::Null())))))
+	}
+});
+
+/// Get the message of an error, such as one caught by `wind`. `(error-message error)`
+///
+/// Errors raised by the interpreter or `error` carry their message. An error made by
+/// `make-error` has its data as the message if the data is a string. Otherwise the result is `false`.
+teko_simple_function!(error_message args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Error(EvalError { message: Some(ref message), .. }) => Ok(rcs(Coredata::String(message.clone()))),
+		Coredata::Error(..) => Ok(rcs(Coredata::Boolean(false))),
+		_ => Err(extype![args[0].0, Error, args[0]]),
+	}
+});
+
+/// Get where an error was raised as `(line column source)`. `(error-source error)`
+///
+/// Yields `false` if the error has no location.
+teko_simple_function!(error_source args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Error(EvalError { source: Some(ref source), .. }) => Ok(source.into()),
+		Coredata::Error(..) => Ok(rcs(Coredata::Boolean(false))),
+		_ => Err(extype![args[0].0, Error, args[0]]),
+	}
+});
+
+/// Get the error that led to an error. `(error-cause error)`
+///
+/// Yields `false` if the error has no cause.
+teko_simple_function!(error_cause args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Error(EvalError { cause: Some(ref cause), .. }) => Ok(cause.clone()),
+		Coredata::Error(..) => Ok(rcs(Coredata::Boolean(false))),
+		_ => Err(extype![args[0].0, Error, args[0]]),
+	}
+});
+
+teko_simple_function!(function_code args : 1 => 1 => {
+	use utilities::program_to_cells;
+	match **args.first().unwrap() {
+		Sourcedata(ref src, Coredata::Function(Function::Builtin(..))) => {
+			Err((src.clone(), format!["expected Function but got {}", data_name(args.first().unwrap())]))
+		}
+		Sourcedata(_, Coredata::Function(Function::Library(_, ref program, _))) => {
+			Ok(program_to_cells(program))
+		}
+		Sourcedata(ref src, ..) => {
+			Err(extype![src, Function, args.first().unwrap()])
+		}
+	}
+});
+
+teko_simple_function!(function_parameters args : 1 => 1 => {
+	let mut top = rcs(Coredata::Null());
+	match **args.first().unwrap() {
+		Sourcedata(ref src, Coredata::Function(Function::Builtin(..))) => {
+			return Err((src.clone(), format!["expected Function but got {}", data_name(args.first().unwrap())]));
+		}
+		Sourcedata(_, Coredata::Function(Function::Library(ref params, _, _))) => {
+			for i in params.iter().rev() {
+				top = rcs(Coredata::Cell(rcs(Coredata::Symbol(i.clone())), top));
+			}
+		}
+		Sourcedata(ref src, ..) => {
+			return Err(extype![src, Function, args.first().unwrap()]);
+		}
+	}
+	Ok(top)
+});
+
+/// Evals the argument as if it's a program.
+///
+/// The data is pushed onto the program stack and evaluated in the current environment, so
+/// malformed data unwinds with the same errors as the equivalent source code would.
+fn eval_expose(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	if let Some(args) = env.params.last() {
+		if args.len() != 1 {
+			Some((None, arity_mismatch(1, 1, args.len())))
+		} else if let Some(arg) = args.first() {
+			program.push(arg.clone());
+			None
+		} else {
+			Some((None, arity_mismatch(1, 1, args.len())))
+		}
+	} else {
+		Some((None, "no argument stack".into()))
+	}
+}
+
+/// Check that a projected integer size does not exceed `env.max_integer_bits`.
+fn check_integer_bits(env: &Env, source: &Option<Source>, bits: f64) -> Option<(Option<Source>, String)> {
+	if let Some(max) = env.max_integer_bits {
+		if bits > max as f64 {
+			return Some((source.clone(), format![
+				"result of about {} bits exceeds the limit of {} bits",
+				bits.ceil(),
+				max,
+			]));
+		}
+	}
+	None
+}
+
+/// Integer exponentiation. `(expt base exponent) => Integer`
+///
+/// The exponent must be non-negative. The size of the result is estimated before it is
+/// computed, and if it exceeds `env.max_integer_bits` the computation is aborted.
+fn expt(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let (base, exponent, source) = if let Some(args) = env.params.last() {
+		if args.len() != 2 {
+			return Some((None, arity_mismatch(2, 2, args.len())));
+		}
+		match (&args[0].1, &args[1].1) {
+			(Coredata::Integer(base), Coredata::Integer(exponent)) => {
+				if let Some(exponent) = exponent.to_usize() {
+					(base.clone(), exponent, args[1].0.clone())
+				} else {
+					return Some((args[1].0.clone(), "exponent must be a non-negative machine-sized integer".into()));
+				}
+			}
+			(Coredata::Integer(..), _) => return Some(extype![args[1].0, Integer, args[1]]),
+			_ => return Some(extype![args[0].0, Integer, args[0]]),
+		}
+	} else {
+		return Some((None, "no argument stack".into()));
+	};
+	let magnitude = base.to_f64().map_or(base.bits() as f64, |base| base.abs().log2());
+	if magnitude > 0.0 {
+		if let Some(error) = check_integer_bits(env, &source, exponent as f64 * magnitude + 1.0) {
+			return Some(error);
+		}
+	}
+	env.set_result(rcs(Coredata::Integer(::num::pow(base, exponent))));
+	None
+}
+
+/// Integer factorial. `(factorial n) => Integer`
+///
+/// The size of the result is estimated by Stirling's approximation before it is computed,
+/// and if it exceeds `env.max_integer_bits` the computation is aborted.
+fn factorial(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let (n, source) = if let Some(args) = env.params.last() {
+		if args.len() != 1 {
+			return Some((None, arity_mismatch(1, 1, args.len())));
+		}
+		match args[0].1 {
+			Coredata::Integer(ref n) => {
+				if let Some(n) = n.to_usize() {
+					(n, args[0].0.clone())
+				} else {
+					return Some((args[0].0.clone(), "argument must be a non-negative machine-sized integer".into()));
+				}
+			}
+			_ => return Some(extype![args[0].0, Integer, args[0]]),
+		}
+	} else {
+		return Some((None, "no argument stack".into()));
+	};
+	if n > 1 {
+		let n = n as f64;
+		let bits = n * n.log2() - n * ::std::f64::consts::LOG2_E + 0.5 * (2.0 * ::std::f64::consts::PI * n).log2();
+		if let Some(error) = check_integer_bits(env, &source, bits) {
+			return Some(error);
+		}
+	}
+	let mut product: BigInt = one();
+	for i in 2..(n + 1) {
+		product = product * BigInt::from(i);
+	}
+	env.set_result(rcs(Coredata::Integer(product)));
+	None
+}
+
+/// Combine integer arguments from left to right, starting with `identity`.
+fn integer_fold(
+	args: &[Statement],
+	identity: BigInt,
+	combine: fn(&BigInt, &BigInt) -> BigInt,
+) -> Result<Statement, (Option<Source>, String)> {
+	let mut result = identity;
+	for arg in args {
+		match arg.1 {
+			Coredata::Integer(ref value) => result = combine(&result, value),
+			_ => return Err(extype![arg.0, Integer, arg]),
+		}
+	}
+	Ok(rcs(Coredata::Integer(result)))
+}
+
+/// Greatest common divisor of the arguments, which is never negative. `(gcd)` is `0`.
+teko_simple_function!(gcd args : 0 => usize::MAX => {
+	integer_fold(args, zero(), Integer::gcd)
+});
+
+/// Least common multiple of the arguments, which is never negative. `(lcm)` is `1`.
+teko_simple_function!(lcm args : 0 => usize::MAX => {
+	integer_fold(args, one(), Integer::lcm)
+});
+
+/// Exit the entire program.
+teko_simple_function!(exit args : 0 => 1 => {
+	if let Some(arg) = args.last() {
+		match **arg {
+			Sourcedata(ref src, Coredata::Integer(ref value)) => {
+				if let Some(value) = value.to_i32() {
+					::std::process::exit(value);
+				} else {
+					Err((src.clone(), "unable to convert number to value".into()))
+				}
+			}
+			Sourcedata(ref src, ..) => {
+				Err(extype![src, Integer, arg])
+			}
+		}
+	} else {
+		::std::process::exit(0);
+	}
+});
+
+/// Keep the elements of a list that satisfy a predicate. `(filter predicate list)`
+///
+/// Every value except `false` counts as satisfying the predicate. The predicate is applied by
+/// pushing `(@filter-internal 'e1 (predicate 'e1) 'e2 (predicate 'e2) ...)` onto the program.
+fn filter(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let call = if let Some(args) = env.params.last() {
+		if args.len() != 2 {
+			return Some((None, arity_mismatch(2, 2, args.len())));
+		}
+		if let Coredata::Function(..) = args[0].1 {
+		} else {
+			return Some((args[0].0.clone(), format!["expected Function but got {}", data_name(&args[0])]));
+		}
+		match args[1].1 {
+			Coredata::Cell(..) | Coredata::Null() => {}
+			_ => return Some(extype![args[1].0, Cell or Null, args[1]]),
+		}
+		let mut call = rcs(Coredata::Null());
+		for element in collect_cell_into_revvec(&args[1]) {
+			let test = rcs(Coredata::Cell(
+				args[0].clone(),
+				rcs(Coredata::Cell(quoted(&element), rcs(Coredata::Null()))),
+			));
+			call = rcs(Coredata::Cell(quoted(&element), rcs(Coredata::Cell(test, call))));
+		}
+		rcs(Coredata::Cell(
+			rcs(Coredata::Function(Function::Builtin(filter_internal, "@filter-internal".into(), None))),
+			call,
+		))
+	} else {
+		return Some((None, "no argument stack".into()));
+	};
+	program.push(call);
+	None
+}
+
+/// Collect each element that is followed by a result other than `false`.
+teko_simple_function!(filter_internal args : 0 => usize::MAX => {
+	let mut result = rcs(Coredata::Null());
+	for pair in args.chunks(2).rev() {
+		if let Coredata::Boolean(false) = pair[1].1 {
+		} else {
+			result = rcs(Coredata::Cell(pair[0].clone(), result));
+		}
+	}
+	Ok(result)
+});
+
+/// Apply a function to each element of a list in order for its effects. `(for-each function list)`
+///
+/// The function is applied by pushing `(@for-each-internal (function 'e1) (function 'e2) ...)`
+/// onto the program, which discards the results and returns `Null`.
+fn for_each(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let call = if let Some(args) = env.params.last() {
+		if args.len() != 2 {
+			return Some((None, arity_mismatch(2, 2, args.len())));
+		}
+		if let Coredata::Function(..) = args[0].1 {
+		} else {
+			return Some((args[0].0.clone(), format!["expected Function but got {}", data_name(&args[0])]));
+		}
+		match args[1].1 {
+			Coredata::Cell(..) | Coredata::Null() => {}
+			_ => return Some(extype![args[1].0, Cell or Null, args[1]]),
+		}
+		let null = rcs(Coredata::Null());
+		let calls = collect_cell_into_revvec(&args[1]).into_iter().fold(null.clone(), |tail, element| {
+			cons(cons(args[0].clone(), cons(quoted(&element), null.clone())), tail)
+		});
+		cons(rcs(Coredata::Function(Function::Builtin(for_each_internal, "@for-each-internal".into(), None))), calls)
+	} else {
+		return Some((None, "no argument stack".into()));
+	};
+	program.push(call);
+	None
+}
+
+/// Discard the results of `for-each` and `string-for-each`.
+teko_simple_function!(for_each_internal _args : 0 => usize::MAX => {
+	Ok(rcs(Coredata::Null()))
+});
+
+/// Sort a list, keeping equal elements in their original order. `(sort list less?)`
+///
+/// `less?` is called as `(less? a b)` and every value except `false` means that `a` goes before
+/// `b`. This is a bottom-up merge sort that needs the result of each comparison before it can
+/// continue, so `@sort-internal` carries the state of the sort in its arguments and pushes
+/// `(@sort-internal 'less? 'pending 'merged 'left 'right 'output (less? 'a 'b))` for every comparison.
+fn sort(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let call = if let Some(args) = env.params.last() {
+		if args.len() != 2 {
+			return Some((None, arity_mismatch(2, 2, args.len())));
+		}
+		match args[0].1 {
+			Coredata::Cell(..) | Coredata::Null() => {}
+			_ => return Some(extype![args[0].0, Cell or Null, args[0]]),
+		}
+		if let Coredata::Function(..) = args[1].1 {
+		} else {
+			return Some((args[1].0.clone(), format!["expected Function but got {}", data_name(&args[1])]));
+		}
+		let null = rcs(Coredata::Null());
+		let pending = collect_cell_into_revvec(&args[0]).into_iter().fold(null.clone(), |pending, element| {
+			cons(cons(element, null.clone()), pending)
+		});
+		let state = [&args[1], &pending, &null, &null, &null, &null];
+		let arguments = state.iter().rev().fold(null.clone(), |tail, data| cons(quoted(data), tail));
+		cons(rcs(Coredata::Function(Function::Builtin(sort_internal, "@sort-internal".into(), None))), arguments)
+	} else {
+		return Some((None, "no argument stack".into()));
+	};
+	program.push(call);
+	None
+}
+
+/// Continue a merge sort until it finishes or needs the next comparison.
+///
+/// `pending` and `merged` are lists of sorted runs, `merged` in reverse. Runs are merged in
+/// pairs from `pending` into `merged`, and when `pending` is exhausted `merged` becomes the
+/// next `pending`. `left` and `right` are the rest of the two runs being merged and `output`
+/// is the merged part in reverse. The optional last argument is the result of comparing the
+/// head of `right` with the head of `left`.
+fn sort_internal(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let mut state: Vec<Statement> = match env.params.last() {
+		Some(args) if args.len() == 6 || args.len() == 7 => args.clone(),
+		Some(args) => return Some((None, arity_mismatch(6, 7, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	let mut right_first = if state.len() == 7 {
+		state.pop().map(|less| !matches![less.1, Coredata::Boolean(false)])
+	} else {
+		None
+	};
+	let (comparator, mut pending, mut merged, mut left, mut right, mut output) = (
+		state[0].clone(), state[1].clone(), state[2].clone(), state[3].clone(), state[4].clone(), state[5].clone()
+	);
+	let null = rcs(Coredata::Null());
+	loop {
+		match (split(&left), split(&right)) {
+			(Some((left_head, left_tail)), Some((right_head, right_tail))) => {
+				match right_first.take() {
+					Some(true) => {
+						output = cons(right_head, output);
+						right = right_tail;
+					}
+					Some(false) => {
+						output = cons(left_head, output);
+						left = left_tail;
+					}
+					None => {
+						let compare = cons(comparator.clone(), cons(quoted(&right_head), cons(quoted(&left_head), null.clone())));
+						let state = [&comparator, &pending, &merged, &left, &right, &output];
+						let arguments = state.iter().rev().fold(cons(compare, null.clone()), |tail, data| cons(quoted(data), tail));
+						program.push(cons(rcs(Coredata::Function(Function::Builtin(sort_internal, "@sort-internal".into(), None))), arguments));
+						return None;
+					}
+				}
+			}
+			(Some(_), None) | (None, Some(_)) => {
+				let mut run = if let Coredata::Null() = left.1 { right.clone() } else { left.clone() };
+				while let Some((head, tail)) = split(&output) {
+					run = cons(head, run);
+					output = tail;
+				}
+				merged = cons(run, merged);
+				left = null.clone();
+				right = null.clone();
+			}
+			(None, None) => {
+				if let Some((first, rest)) = split(&pending) {
+					if let Some((second, rest)) = split(&rest) {
+						left = first;
+						right = second;
+						pending = rest;
+					} else {
+						merged = cons(first, merged);
+						pending = rest;
+					}
+				} else if let Some((run, rest)) = split(&merged) {
+					if let Coredata::Null() = rest.1 {
+						env.set_result(run);
+						return None;
+					}
+					while let Some((head, tail)) = split(&merged) {
+						pending = cons(head, pending);
+						merged = tail;
+					}
+				} else {
+					env.set_result(null);
+					return None;
+				}
+			}
+		}
+	}
+}
+
+/// Construct a cell.
+fn cons(head: Statement, tail: Statement) -> Statement {
+	rcs(Coredata::Cell(head, tail))
+}
+
+/// Get the head and tail of a cell.
+fn split(data: &Statement) -> Option<(Statement, Statement)> {
+	if let Coredata::Cell(ref head, ref tail) = data.1 {
+		Some((head.clone(), tail.clone()))
+	} else {
+		None
+	}
+}
+
+/// Return several values at once. `(values data...)`
+///
+/// The values are bundled without building a list, and `call-with-values` passes them as
+/// separate arguments. A single value is returned as itself.
+teko_simple_function!(values args : 0 => usize::MAX => {
+	if args.len() == 1 {
+		Ok(args[0].clone())
+	} else {
+		Ok(rcs(Coredata::Values(args.clone())))
+	}
+});
+
+/// Call the consumer with the values returned by the producer. `(call-with-values producer consumer)`
+///
+/// The producer is called without arguments. Anything other than `values` counts as one value.
+fn call_with_values(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let call = if let Some(args) = env.params.last() {
+		if args.len() != 2 {
+			return Some((None, arity_mismatch(2, 2, args.len())));
+		}
+		for function in args {
+			if let Coredata::Function(..) = function.1 {
+			} else {
+				return Some((function.0.clone(), format!["expected Function but got {}", data_name(function)]));
+			}
+		}
+		let null = rcs(Coredata::Null());
+		let produce = cons(args[0].clone(), null.clone());
+		cons(
+			rcs(Coredata::Function(Function::Builtin(call_with_values_internal, "@call-with-values-internal".into(), None))),
+			cons(quoted(&args[1]), cons(produce, null)),
+		)
+	} else {
+		return Some((None, "no argument stack".into()));
+	};
+	program.push(call);
+	None
+}
+
+/// Call the consumer with each of the produced values quoted. `(@call-with-values-internal consumer values)`
+fn call_with_values_internal(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let (consumer, produced) = match env.params.last() {
+		Some(args) if args.len() == 2 => (args[0].clone(), args[1].clone()),
+		Some(args) => return Some((None, arity_mismatch(2, 2, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	let produced = match produced.1 {
+		Coredata::Values(ref values) => values.clone(),
+		_ => vec![produced.clone()],
+	};
+	let arguments = produced.iter().rev().fold(rcs(Coredata::Null()), |tail, value| cons(quoted(value), tail));
+	program.push(cons(consumer, arguments));
+	None
+}
+
+/// Left fold. `(fold f init list)`
+///
+/// `f` is called as `(f element accumulator)` for each element from left to right, as in
+/// Racket's `foldl`, and the last result is returned. An empty list yields `init`. The calls are
+/// pushed onto the program stack as nested expressions with their arguments quoted.
+fn fold(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	fold_calls(program, env, false)
+}
+
+/// Right fold. `(foldr f init list)`
+///
+/// `(foldr f init (list 1 2 3))` is `(f 1 (f 2 (f 3 init)))`, so `f` is called for each element
+/// from right to left, the last element first. Like `fold` the calls are nested expressions on
+/// the program stack, so long lists do not overflow the native stack.
+fn fold_right(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	fold_calls(program, env, true)
+}
+
+/// Shared implementation of `fold` and `foldr`, which only differ in the order of the elements.
+fn fold_calls(program: &mut Program, env: &mut Env, from_right: bool) -> Option<(Option<Source>, String)> {
+	let call = if let Some(args) = env.params.last() {
+		if args.len() != 3 {
+			return Some((None, arity_mismatch(3, 3, args.len())));
+		}
+		if let Coredata::Function(..) = args[0].1 {
+		} else {
+			return Some((args[0].0.clone(), format!["expected Function but got {}", data_name(&args[0])]));
+		}
+		match args[2].1 {
+			Coredata::Cell(..) | Coredata::Null() => {}
+			_ => return Some(extype![args[2].0, Cell or Null, args[2]]),
+		}
+		let mut elements = collect_cell_into_revvec(&args[2]);
+		if from_right {
+			elements.reverse();
+		}
+		let mut call = quoted(&args[1]);
+		while let Some(element) = elements.pop() {
+			call = rcs(Coredata::Cell(
+				args[0].clone(),
+				rcs(Coredata::Cell(quoted(&element), rcs(Coredata::Cell(call, rcs(Coredata::Null()))))),
+			));
+		}
+		call
+	} else {
+		return Some((None, "no argument stack".into()));
+	};
+	program.push(call);
+	None
+}
+
+/// Construct a function object. `(function (params) body...)`
+///
+/// The function keeps the scope it is made in, so a call binds the parameters inside that
+/// scope and the body sees the variables of an enclosing `let` or function after it returns.
+/// The variables are shared, not copied, so a `set!` of one is seen by every function made in
+/// that scope. Variables that are in no scope, top level definitions among them, are looked up
+/// when the function runs, so it can use definitions made after it.
+fn function(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let args = env.get_result();
+	match args.len() {
+		Some(len) if len >= 2 => {}
+		Some(len) => return Some((None, arity_mismatch(2, usize::MAX, len))),
+		None => return Some((None, "macro: input not Cell or Null()".into())),
+	}
+	let params = match args.head().map(|head| collect_cell_of_symbols_into_vec(&head)) {
+		Some(Some(params)) => params,
+		Some(None) => return Some((None, "parameter list contains non-symbols".into())),
+		None => return Some((None, "parameter list is not a list".into())),
+	};
+	let code = match args.tail() {
+		Some(tail) => collect_cell_into_revvec(&tail),
+		None => return Some((None, "tail is empty".into())),
+	};
+	env.set_result(rcs(Coredata::Function(Function::Library(params, code, env.scope.clone()))));
+	None
+}
+
+/// The greater-than function for comparing numbers.
+teko_simple_function!(gt args : 0 => usize::MAX => {
+	compare_numbers(args, |ordering| ordering == cmp::Ordering::Greater)
+});
+
+/// Take the head of a cell.
+///
+/// If the argument is not a cell then this will unwind with
+/// an error.
+teko_simple_function!(head args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Some(head) = arg.head() {
+		Ok(head.clone())
+	} else {
+		return Err(extype![arg.0, Cell, arg]);
+	}
+});
+
+/// Conditional branching primitive. `(if condition then else)`
+///
+/// Only `false` takes the `else` branch. Every other value, including `0`, the empty string
+/// and the empty list, is true.
+fn if_conditional(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let arg = env.get_result();
+	if let Some(head) = arg.head() {
+		if let Some(tail) = arg.tail() {
+			if let Some(head_of_tail) = tail.head() {
+				if let Some(tail_of_tail) = tail.tail() {
+					if let Some(head_of_tail_of_tail) = tail_of_tail.head() {
+						program.push(rcs(Coredata::Internal(
+							Commands::If(head_of_tail, head_of_tail_of_tail),
+						)));
+						program.push(head);
+						return None;
+					} else {
+						Some((None, arity_mismatch(3, 3, 2)))
+					}
+				} else {
+					Some((None, arity_mismatch(3, 3, 1)))
+				}
+			} else {
+				Some((None, arity_mismatch(3, 3, 1)))
+			}
+		} else {
+			Some((None, arity_mismatch(3, 3, 1)))
+		}
+	} else {
+		Some((None, arity_mismatch(3, 3, 0)))
+	}
+}
+
+/// Conditionally evaluate a body of statements.
+///
+/// Shared implementation of `when` and `unless`. The body is evaluated like `begin` when the
+/// test is truthy (for `when`) or false (for `unless`), otherwise the result is `Null`. Only
+/// `false` is considered false, just like with `if`.
+fn conditional_body(program: &mut Program, env: &mut Env, negate: bool) -> Option<(Option<Source>, String)> {
+	let arg = env.get_result();
+	if let (Some(test), Some(body)) = (arg.head(), arg.tail()) {
+		let body = rcs(Coredata::Cell(
+			rcs(Coredata::Macro(Macro::Builtin(self::program, "begin".into()))),
+			body,
+		));
+		let nothing = rcs(Coredata::Null());
+		let command = if negate {
+			Commands::If(nothing, body)
+		} else {
+			Commands::If(body, nothing)
+		};
+		program.push(rcs(Coredata::Internal(command)));
+		program.push(test);
+		None
+	} else {
+		Some((None, arity_mismatch(1, usize::MAX, 0)))
+	}
+}
+
+/// Evaluate the body if the test is not false, else yield `Null`.
+fn when(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	conditional_body(program, env, false)
+}
+
+/// Evaluate the body if the test is false, else yield `Null`.
+fn unless(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	conditional_body(program, env, true)
+}
+
+/// Dispatch on a key. `(case key ((value...) body...)... (else body...))`
+///
+/// The key is evaluated once and compared with `eqv?` against the values of each clause in
+/// order, the body of the first clause with an equivalent value is evaluated like `begin`.
+/// The values are evaluated, so write `(@ a)` to match a symbol. An `else` clause matches
+/// anything and must come last. Without a match the result is `Null`.
+fn case(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let args = env.get_result();
+	let (key, clauses) = match (args.head(), args.tail()) {
+		(Some(key), Some(clauses)) => (key, clauses),
+		_ => return Some((args.0.clone(), arity_mismatch(1, usize::MAX, 0))),
+	};
+	let clauses = match clauses.1 {
+		Coredata::Cell(..) | Coredata::Null() => collect_cell_into_revvec(&clauses),
+		_ => return Some(extype![clauses.0, Cell or Null, clauses]),
+	};
+	for (index, clause) in clauses.iter().enumerate() {
+		match clause.head() {
+			Some(ref values) if values.len().is_some() => {}
+			Some(ref values) if is_else(values) => {
+				if index != 0 {
+					return Some((clause.0.clone(), "else clause must be last".into()));
+				}
+			}
+			_ => return Some((clause.0.clone(), format!["expected a case clause but got {}", clause])),
+		}
+	}
+	let clauses: Sourcedata = clauses.into_iter().rev().collect::<Vec<_>>().into();
+	let arguments = rcs(Coredata::Cell(key, rcs(Coredata::Cell(quoted(&rc(clauses)), rcs(Coredata::Null())))));
+	program.push(rc(Sourcedata(args.0.clone(), Coredata::Cell(
+		rcs(Coredata::Function(Function::Builtin(case_internal, "@case-internal".into(), None))),
+		arguments,
+	))));
+	None
+}
+
+/// Expand the clauses of `case` into `if` tests of the evaluated key. `(@case-internal key clauses)`
+fn case_internal(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let (key, clauses) = match env.params.last() {
+		Some(args) if args.len() == 2 => (quoted(&args[0]), collect_cell_into_revvec(&args[1])),
+		Some(args) => return Some((None, arity_mismatch(2, 2, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	let list = |elements: Vec<Statement>| -> Statement { rc(elements.into()) };
+	let if_macro = rcs(Coredata::Macro(Macro::Builtin(if_conditional, "if".into())));
+	let eqv = rcs(Coredata::Function(Function::Builtin(is_eqv_data, "eqv?".into(), None)));
+	let mut expansion = rcs(Coredata::Null());
+	for clause in clauses {
+		let (values, body) = (clause.head().unwrap(), clause.tail().unwrap());
+		let body = rc(Sourcedata(clause.0.clone(), Coredata::Cell(
+			rcs(Coredata::Macro(Macro::Builtin(self::program, "begin".into()))),
+			body,
+		)));
+		if is_else(&values) {
+			expansion = body;
+			continue;
+		}
+		for value in collect_cell_into_revvec(&values) {
+			let test = list(vec![eqv.clone(), key.clone(), value]);
+			expansion = list(vec![if_macro.clone(), test, body.clone(), expansion]);
+		}
+	}
+	program.push(expansion);
+	None
+}
+
+/// Check if a `case` clause starts with `else`.
+fn is_else(values: &Sourcedata) -> bool {
+	if let Coredata::Symbol(ref symbol) = values.1 {
+		let name: &str = symbol.into();
+		name == "else"
+	} else {
+		false
+	}
+}
+
+/// Check if data is the same.
+///
+/// Bound to both `same?` and `equal?`. This is deep structural equality: cells are equal when
+/// their heads and tails are equal, and strings, integers, and symbols are compared by value.
+teko_simple_function!(is_data_eq args : 0 => usize::MAX => {
+	let mut last = None;
+	let mut result = rcs(Coredata::Boolean(true));
+	for arg in args.iter() {
+		let data = &arg.1;
+		if let Some(previous) = last {
+			if previous == data {
+				// Do nothing
+			} else {
+				result = rcs(Coredata::Boolean(false));
+				break;
+			}
+			last = Some(data);
+		} else {
+			last = Some(data);
+		}
+	}
+	Ok(result)
+});
+
+/// Check if data is identical, see `utilities::is_eq` for the rules.
+teko_simple_function!(is_eq_data args : 0 => usize::MAX => {
+	for pair in args.windows(2) {
+		if !is_eq(&pair[0], &pair[1]) {
+			return Ok(rcs(Coredata::Boolean(false)));
+		}
+	}
+	Ok(rcs(Coredata::Boolean(true)))
+});
+
+/// Check if data is equivalent, see `utilities::is_eqv` for the rules.
+///
+/// Unlike `same?` this does not look inside structures, so two equal strings or lists
+/// are only equivalent if they are the same object.
+teko_simple_function!(is_eqv_data args : 0 => usize::MAX => {
+	for pair in args.windows(2) {
+		if !is_eqv(&pair[0], &pair[1]) {
+			return Ok(rcs(Coredata::Boolean(false)));
+		}
+	}
+	Ok(rcs(Coredata::Boolean(true)))
+});
+
+/// Check if a value is an error type.
+teko_simple_function!(is_error args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Error(_) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Check if the value is a cell type.
+teko_simple_function!(is_cell args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Cell(..) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Check if the value is a symbol.
+teko_simple_function!(is_symbol args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Symbol(_) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Check if the value is null (the empty list).
+teko_simple_function!(is_null args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Null() = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Check if the value is an exact integer, floats such as `2.0` are not integers.
+teko_simple_function!(is_integer args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Integer(_) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Check if the value is a float.
+teko_simple_function!(is_float args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Float(_) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Check if the value is a rational number, which is any integer, rational, or finite float.
+teko_simple_function!(is_rational args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	match arg.1 {
+		Coredata::Integer(_) | Coredata::Rational(_) => Ok(rcs(Coredata::Boolean(true))),
+		Coredata::Float(ref float) => Ok(rcs(Coredata::Boolean(float.0.is_finite()))),
+		_ => Ok(rcs(Coredata::Boolean(false))),
+	}
+});
+
+/// Check if the value is a complex number. Every number is complex.
+teko_simple_function!(is_complex args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Integer(_) | Coredata::Rational(_) | Coredata::Float(_) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Check if the value is a string.
+teko_simple_function!(is_string args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::String(_) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Check if the value is a boolean.
+teko_simple_function!(is_boolean args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Boolean(_) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Check if the value is a function, either builtin or library.
+teko_simple_function!(is_function args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Function(_) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Generate a fresh symbol, no two calls within a process return the same symbol.
+///
+/// The symbols are of the form `@gensym-N` so macros can bind them without capturing user
+/// variables.
+teko_simple_function!(gensym args : 0 => 0 => {
+	Ok(fresh_symbol())
+});
+
+/// The symbol returned by `gensym`, also used by macros that bind their own variables.
+fn fresh_symbol() -> Statement {
+	let count = GENSYM_COUNTER.fetch_add(1, Ordering::Relaxed);
+	rcs(Coredata::Symbol(Symbol::from(format!["@gensym-{}", count])))
+}
+
+/// Check if the value is a table.
+teko_simple_function!(is_table args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Table(_) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Check if the value is a vector.
+teko_simple_function!(is_vector args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Vector(_) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Check if the value is a character.
+teko_simple_function!(is_char args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Coredata::Char(_) = arg.1 {
+		Ok(rcs(Coredata::Boolean(true)))
+	} else {
+		Ok(rcs(Coredata::Boolean(false)))
+	}
+});
+
+/// Get the type of a value as a symbol, such as `integer` or `cell`.
+teko_simple_function!(type_of args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	Ok(rcs(Coredata::Symbol(Symbol::from(type_name(arg)))))
+});
+
+/// Return the value unchanged if it is of the given type, otherwise error.
+///
+/// The type is a symbol as returned by `type-of`.
+teko_simple_function!(assert_type args : 2 => 2 => {
+	let arg = args.first().unwrap();
+	let expected = args.last().unwrap();
+	if let Coredata::Symbol(ref expected) = expected.1 {
+		let expected: &str = expected.into();
+		let actual = type_name(arg);
+		if expected == actual {
+			Ok(arg.clone())
+		} else {
+			Err((arg.0.clone(), format!["expected {} but got {}", expected, actual]))
+		}
+	} else {
+		Err(extype![expected.0, Symbol, expected])
+	}
+});
+
+/// Unwind with the message if the value is `false`, otherwise yield `Null`. `(assert value [message])`
+///
+/// The message is a string, without one the error is "assertion failed".
+teko_simple_function!(assert args : 1 => 2 => {
+	let message = match args.get(1) {
+		Some(message) => match message.1 {
+			Coredata::String(ref string) => string.clone(),
+			_ => return Err(extype![message.0, String, message]),
+		},
+		None => "assertion failed".into(),
+	};
+	if let Coredata::Boolean(false) = args[0].1 {
+		Err((None, message))
+	} else {
+		Ok(rcs(Coredata::Null()))
+	}
+});
+
+/// Unwind with both values unless they are `equal?`, otherwise yield `Null`. `(assert-equal expected actual)`
+teko_simple_function!(assert_equal args : 2 => 2 => {
+	if args[0].1 == args[1].1 {
+		Ok(rcs(Coredata::Null()))
+	} else {
+		Err((None, format!["expected {} but got {}", args[0], args[1]]))
+	}
+});
+
+/// Compute the length of a list.
+teko_simple_function!(list_length args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	if let Some(len) = arg.len() {
+		Ok(rcs(Coredata::Integer(len.into())))
+	} else {
+		Err(extype![arg.0, String or Cell, arg])
+	}
+});
+
+/// Construct a list (nested cell) of items.
+teko_simple_function!(list args : 0 => usize::MAX => {
+	let mut result = rcs(Coredata::Null());
+	for arg in args.iter().rev() {
+		result = rcs(Coredata::Cell(arg.clone(), result));
+	}
+	Ok(result)
+});
+
+/// Drop the first `index` cells of a list, unwinding if the index is negative or too large.
+///
+/// With `element` set there must be a cell left after dropping, so that its head can be taken.
+fn list_drop(list: &Statement, index: &Statement, element: bool) -> Result<Statement, (Option<Source>, String)> {
+	match list.1 {
+		Coredata::Cell(..) | Coredata::Null() => {}
+		_ => return Err(extype![list.0, Cell or Null, list]),
+	}
+	let integer = match index.1 {
+		Coredata::Integer(ref integer) => integer,
+		_ => return Err(extype![index.0, Integer, index]),
+	};
+	let tail = |data: &Statement| {
+		if let Coredata::Cell(_, ref tail) = data.1 { Some(tail.clone()) } else { None }
+	};
+	let mut current = list.clone();
+	let mut dropped = 0;
+	let count = integer.to_usize();
+	while Some(dropped) != count {
+		match tail(&current) {
+			Some(next) => current = next,
+			None => break,
+		}
+		dropped += 1;
+	}
+	if Some(dropped) == count && (!element || tail(&current).is_some()) {
+		return Ok(current);
+	}
+	let mut length = dropped;
+	while let Some(next) = tail(&current) {
+		current = next;
+		length += 1;
+	}
+	Err((index.0.clone(), format!["index {} out of bounds for list of length {}", integer, length]))
+}
+
+/// Get the element at a 0-based index of a list. `(list-ref list index)`
+teko_simple_function!(list_ref args : 2 => 2 => {
+	match list_drop(&args[0], &args[1], true)?.1 {
+		Coredata::Cell(ref head, _) => Ok(head.clone()),
+		_ => unreachable![],
+	}
+});
+
+/// Get the rest of a list after dropping a number of elements. `(list-tail list count)`
+///
+/// Also bound to `drop`. The rest shares its cells with the list.
+teko_simple_function!(list_tail args : 2 => 2 => {
+	list_drop(&args[0], &args[1], false)
+});
+
+/// Get a new list of the first elements of a list. `(take list count)`
+///
+/// Like `list-tail` and `drop` this unwinds if the count is negative or larger than the length
+/// of the list, rather than giving back fewer elements.
+teko_simple_function!(take args : 2 => 2 => {
+	let rest = list_drop(&args[0], &args[1], false)?;
+	let mut heads = vec![];
+	let mut current = args[0].clone();
+	while !Arc::ptr_eq(&current, &rest) {
+		let next = match current.1 {
+			Coredata::Cell(ref head, ref tail) => {
+				heads.push(head.clone());
+				tail.clone()
+			}
+			_ => unreachable![],
+		};
+		current = next;
+	}
+	Ok(heads.into_iter().rev().fold(rcs(Coredata::Null()), |tail, head| cons(head, tail)))
+});
+
+/// Find the first pair in an association list whose head is `equal?` to the key. `(assoc key alist)`
+///
+/// Yields `false` if there is no such pair. Unwinds if an element of the list is not a pair.
+teko_simple_function!(assoc args : 2 => 2 => {
+	let mut current = args[1].clone();
+	loop {
+		let next = match current.1 {
+			Coredata::Cell(ref pair, ref tail) => {
+				match pair.1 {
+					Coredata::Cell(ref key, _) if key.1 == args[0].1 => return Ok(pair.clone()),
+					Coredata::Cell(..) => tail.clone(),
+					_ => return Err(extype![pair.0, Cell, pair]),
+				}
+			}
+			Coredata::Null() => return Ok(rcs(Coredata::Boolean(false))),
+			_ => return Err(extype![current.0, Cell or Null, current]),
+		};
+		current = next;
+	}
+});
+
+/// Find the rest of a list starting at the first element `equal?` to the data. `(member data list)`
+///
+/// Yields `false` if there is no such element.
+teko_simple_function!(member args : 2 => 2 => {
+	let mut current = args[1].clone();
+	loop {
+		let next = match current.1 {
+			Coredata::Cell(ref head, _) if head.1 == args[0].1 => return Ok(current.clone()),
+			Coredata::Cell(_, ref tail) => tail.clone(),
+			Coredata::Null() => return Ok(rcs(Coredata::Boolean(false))),
+			_ => return Err(extype![current.0, Cell or Null, current]),
+		};
+		current = next;
+	}
+});
+
+/// Copy a list so that mutating the copy does not change the original. `(copy-list list)`
+///
+/// Sublists, vectors, and boxes are copied as well, see `deep_copy`.
+teko_simple_function!(copy_list args : 1 => 1 => {
+	match args[0].1 {
+		Coredata::Cell(..) | Coredata::Null() => Ok(deep_copy(&args[0])),
+		_ => Err(extype![args[0].0, Cell or Null, args[0]]),
+	}
+});
+
+/// Construct a list whose final tail is the last argument. `(cons* 1 2 (list 3))` is `(1 2 3)`.
+///
+/// Unlike `cell` the last argument does not have to be a list, `(cons* 1 2 3)` is the improper
+/// list `(1 2 . 3)`. A single argument is returned unchanged.
+teko_simple_function!(cons_star args : 1 => usize::MAX => {
+	let mut result = args.last().unwrap().clone();
+	for arg in args.iter().rev().skip(1) {
+		result = rcs(Coredata::Cell(arg.clone(), result));
 	}
 	Ok(result)
 });
 
-/// Load a file
+/// Parse a file and evaluate its forms in the current environment. `(load filename)`
+///
+/// A relative filename is resolved against the directory of the file the filename was written
+/// in, so files can load their neighbours wherever the program is started from. Unwinds if the
+/// file can not be read, or with the location of the parse error if it can not be parsed.
 fn load(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
 	use parse::parse_file;
-	let input = &**env.params.last().unwrap().first().unwrap();
-	if let Coredata::String(ref string) = input.1 {
-		let parse = parse_file(string);
-		match parse {
-			Ok(tree) => {
-				program.extend(tree);
-				None
+	use std::path::Path;
+	let input = if let Some(args) = env.params.last() {
+		if args.len() != 1 {
+			return Some((None, arity_mismatch(1, 1, args.len())));
+		}
+		args[0].clone()
+	} else {
+		return Some((None, "no argument stack".into()));
+	};
+	let filename = if let Coredata::String(ref filename) = input.1 {
+		filename
+	} else {
+		return Some(extype![input.0, String, input]);
+	};
+	let loading_file = input.0.as_ref().map(|source| Path::new(&source.source)).filter(|path| path.is_file());
+	let path = match loading_file.and_then(Path::parent) {
+		Some(directory) if Path::new(filename).is_relative() => directory.join(filename),
+		_ => Path::new(filename).to_path_buf(),
+	};
+	if !path.is_file() {
+		return Some((input.0.clone(), format!["unable to load {}: no such file", path.display()]));
+	}
+	match parse_file(&path.to_string_lossy()) {
+		Ok(tree) => {
+			program.extend(tree);
+			None
+		}
+		Err(state) => {
+			let crp = Some(state.current_read_position.clone());
+			if let Some(error) = state.error {
+				Some((crp, format!["parse error: {}", error]))
+			} else {
+				Some((crp, "parse error".into()))
+			}
+		}
+	}
+}
+
+/// Get the contents of a file as a string. `(read-file path)`
+///
+/// Unwinds with the path if the file can not be read or is not valid UTF-8.
+teko_simple_function!(read_file args : 1 => 1 => {
+	let filename = match args[0].1 {
+		Coredata::String(ref filename) => filename,
+		_ => return Err(extype![args[0].0, String, args[0]]),
+	};
+	match ::std::fs::read_to_string(filename) {
+		Ok(contents) => Ok(rcs(Coredata::String(contents))),
+		Err(error) => Err((args[0].0.clone(), format!["unable to read {}: {}", filename, error.kind()])),
+	}
+});
+
+/// Parse a file into a list of its forms without evaluating them. `(read-all path)`
+///
+/// Unwinds with the error of `parse_file`, which names the path if the file can not be read
+/// and is located where parsing failed otherwise.
+teko_simple_function!(read_all args : 1 => 1 => {
+	use parse::parse_file;
+	let filename = match args[0].1 {
+		Coredata::String(ref filename) => filename,
+		_ => return Err(extype![args[0].0, String, args[0]]),
+	};
+	match parse_file(filename) {
+		Ok(forms) => Ok(forms.into_iter().fold(rcs(Coredata::Null()), |tail, form| cons(form, tail))),
+		Err(state) => Err((Some(state.current_read_position), state.error.unwrap_or_else(|| "parse error".into()))),
+	}
+});
+
+/// Bind variables for the body. `(let ((variable value)...) body...)`
+///
+/// The values are evaluated before any variable is bound. The named form
+/// `(let name ((variable value)...) body...)` also binds `name` to a function taking the
+/// variables as parameters and running the body, so the body can loop by calling `name` in tail
+/// position.
+fn let_binding(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let list = |elements: Vec<Statement>| -> Statement { rc(elements.into()) };
+	let args = env.get_result();
+	match args.len() {
+		Some(len) if len >= 2 => {}
+		Some(len) => return Some((args.0.clone(), arity_mismatch(2, usize::MAX, len))),
+		None => return Some((args.0.clone(), "macro: input not Cell or Null()".into())),
+	}
+	let (name, rest) = if let Coredata::Symbol(..) = args.head().unwrap().1 {
+		(args.head(), args.tail().unwrap())
+	} else {
+		(None, args.clone())
+	};
+	let (bindings, body) = match (rest.head(), rest.tail()) {
+		(Some(bindings), Some(body)) => (bindings, body),
+		_ => return Some((args.0.clone(), arity_mismatch(3, usize::MAX, 2))),
+	};
+	match bindings.1 {
+		Coredata::Cell(..) | Coredata::Null() => {}
+		_ => return Some(extype![bindings.0, Cell or Null, bindings]),
+	}
+	let (mut variables, mut values) = (vec![], vec![]);
+	for binding in collect_cell_into_revvec(&bindings).into_iter().rev() {
+		let parts = collect_cell_into_revvec(&binding);
+		match (binding.len(), parts.last().map(|variable| &variable.1)) {
+			(Some(2), Some(&Coredata::Symbol(..))) => {
+				variables.push(parts[1].clone());
+				values.push(parts[0].clone());
 			}
-			Err(e) => { Some((input.0.clone(), format!["{:?}", e])) }
+			_ => return Some((binding.0.clone(), format!["expected a variable and a value but got {}", binding])),
 		}
+	}
+	let function = rcs(Coredata::Macro(Macro::Builtin(self::function, "function".into())));
+	let body = rcs(Coredata::Cell(function.clone(), rcs(Coredata::Cell(list(variables.clone()), body))));
+	let (head, arguments) = if let Some(name) = name {
+		// ((function (g...) (letrec ((name body)) (name g...))) value...), the values are
+		// evaluated before name is bound
+		let temporaries: Vec<Statement> = variables.iter().map(|_| fresh_symbol()).collect();
+		let loop_call = rcs(Coredata::Cell(name.clone(), list(temporaries.clone())));
+		let letrec = rcs(Coredata::Macro(Macro::Builtin(self::letrec, "letrec".into())));
+		let start = list(vec![letrec, list(vec![list(vec![name, body])]), loop_call]);
+		(list(vec![function, list(temporaries), start]), list(values))
 	} else {
-		println!["{}", data_name(&input)];
-		Some((input.0.clone(), "expected String but got X".to_string()))
+		(body, list(values))
+	};
+	program.push(rc(Sourcedata(args.0.clone(), Coredata::Cell(head, arguments))));
+	None
+}
+
+/// Bind variables one after another. `(let* ((variable value)...) body...)`
+///
+/// Each value is evaluated with the variables before it already bound. The form is rewritten
+/// into nested `let` forms of one binding each.
+fn let_sequential(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let list = |elements: Vec<Statement>| -> Statement { rc(elements.into()) };
+	let args = env.get_result();
+	let (bindings, body) = match (args.head(), args.tail()) {
+		(Some(bindings), Some(body)) if args.len().is_some_and(|len| len >= 2) => (bindings, body),
+		_ => return Some((args.0.clone(), arity_mismatch(2, usize::MAX, args.len().unwrap_or(0)))),
+	};
+	match bindings.1 {
+		Coredata::Cell(..) | Coredata::Null() => {}
+		_ => return Some(extype![bindings.0, Cell or Null, bindings]),
 	}
+	let let_macro = rcs(Coredata::Macro(Macro::Builtin(let_binding, "let".into())));
+	let mut bindings = collect_cell_into_revvec(&bindings);
+	let first = bindings.pop();
+	let mut body = body;
+	for binding in bindings {
+		body = list(vec![cons(let_macro.clone(), cons(list(vec![binding]), body))]);
+	}
+	let first = list(first.into_iter().collect());
+	program.push(rc(Sourcedata(args.0.clone(), Coredata::Cell(let_macro, cons(first, body)))));
+	None
 }
 
-/// The less-than function for comparing integers.
-teko_simple_function!(lt args : 0 => usize::MAX => {
-	let mut last = None;
-	let mut result = rcs(Coredata::Boolean(true));
-	for arg in args.iter() {
-		match **arg {
-			Sourcedata(_, Coredata::Integer(ref integer)) => {
-				if let Some(previous) = last {
-					if previous < integer {
-						// Do nothing
-					} else {
-						result = rcs(Coredata::Boolean(false));
-						break;
-					}
-					last = Some(integer);
-				} else {
-					last = Some(integer);
-				}
+/// Branch on a value while binding it. `(if-let ((variable value)) then else)`
+///
+/// The form is rewritten into `(let ((variable value)) (if variable then else))`, so `then` and
+/// `else` both see the variable and only `false` takes the `else` branch.
+fn if_let(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let list = |elements: Vec<Statement>| -> Statement { rc(elements.into()) };
+	let args = env.get_result();
+	let mut parts = collect_cell_into_revvec(&args);
+	if parts.len() != 3 {
+		return Some((args.0.clone(), arity_mismatch(3, 3, parts.len())));
+	}
+	let bindings = parts.pop().unwrap();
+	let binding = match (bindings.len(), bindings.head()) {
+		(Some(1), Some(binding)) => binding,
+		_ => return Some((bindings.0.clone(), format!["expected a single binding but got {}", bindings])),
+	};
+	let variable = match (binding.len(), binding.head()) {
+		(Some(2), Some(variable)) => {
+			if let Coredata::Symbol(..) = variable.1 {
+				variable
+			} else {
+				return Some((binding.0.clone(), format!["expected a variable and a value but got {}", binding]));
 			}
-			_ => {
-				return Err(extype![arg.0, Integer, arg]);
+		}
+		_ => return Some((binding.0.clone(), format!["expected a variable and a value but got {}", binding])),
+	};
+	let let_macro = rcs(Coredata::Macro(Macro::Builtin(let_binding, "let".into())));
+	let if_macro = rcs(Coredata::Macro(Macro::Builtin(if_conditional, "if".into())));
+	let otherwise = parts.remove(0);
+	let then = parts.remove(0);
+	let branch = list(vec![if_macro, variable, then, otherwise]);
+	program.push(rc(Sourcedata(args.0.clone(), Coredata::Cell(let_macro, cons(bindings, cons(branch, rcs(Coredata::Null())))))));
+	None
+}
+
+/// Bind variables whose values can refer to each other. `(letrec ((variable value)...) body...)`
+///
+/// All variables are bound before any value is evaluated, then each value is evaluated in order
+/// and assigned to its variable, so functions in the values can call each other. Using a
+/// variable before its value has been assigned unwinds with "accessed before initialization".
+fn letrec(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let list = |elements: Vec<Statement>| -> Statement { rc(elements.into()) };
+	let args = env.get_result();
+	let (bindings, body) = match (args.head(), args.tail()) {
+		(Some(bindings), Some(body)) if args.len().is_some_and(|len| len >= 2) => (bindings, body),
+		_ => return Some((args.0.clone(), arity_mismatch(2, usize::MAX, args.len().unwrap_or(0)))),
+	};
+	match bindings.1 {
+		Coredata::Cell(..) | Coredata::Null() => {}
+		_ => return Some(extype![bindings.0, Cell or Null, bindings]),
+	}
+	let set = rcs(Coredata::Macro(Macro::Builtin(self::set, "set!".into())));
+	let uninitialized = quoted(&rcs(Coredata::Internal(Commands::Uninitialized)));
+	let (mut variables, mut assignments) = (vec![], vec![]);
+	for binding in collect_cell_into_revvec(&bindings).into_iter().rev() {
+		let parts = collect_cell_into_revvec(&binding);
+		match (binding.len(), parts.last().map(|variable| &variable.1)) {
+			(Some(2), Some(&Coredata::Symbol(..))) => {
+				variables.push(parts[1].clone());
+				assignments.push(rc(Sourcedata(binding.0.clone(), Coredata::Cell(set.clone(), binding.clone()))));
 			}
+			_ => return Some((binding.0.clone(), format!["expected a variable and a value but got {}", binding])),
+		}
+	}
+	let function = rcs(Coredata::Macro(Macro::Builtin(self::function, "function".into())));
+	let code = assignments.into_iter().rev().fold(body, |code, assignment| cons(assignment, code));
+	let head = cons(function, cons(list(variables.clone()), code));
+	let arguments = list(variables.iter().map(|_| uninitialized.clone()).collect());
+	program.push(rc(Sourcedata(args.0.clone(), Coredata::Cell(head, arguments))));
+	None
+}
+
+/// The less-than function for comparing numbers.
+teko_simple_function!(lt args : 0 => usize::MAX => {
+	compare_numbers(args, |ordering| ordering == cmp::Ordering::Less)
+});
+
+/// Create a mutable box holding the argument.
+///
+/// Boxes are shared, not copied, so every holder of a box sees writes made by `box-set!`.
+teko_simple_function!(make_box args : 1 => 1 => {
+	Ok(rcs(Coredata::Box(Boxed::new(args[0].clone()))))
+});
+
+/// Error constructor. `(make-error data cause)`, both optional
+///
+/// Error is its own type in Teko. The cause is an error that led to this one, see `error-cause`.
+/// The error is only made, not raised, so this is how errors are written to be read again.
+teko_simple_function!(make_error args : 0 => 2 => {
+	let mut error = EvalError::new(args.first().cloned().unwrap_or_else(|| rcs(Coredata::Null())));
+	if let Some(cause) = args.get(1) {
+		if let Coredata::Error(..) = cause.1 {
+			error.cause = Some(cause.clone());
+		} else {
+			return Err(extype![cause.0, Error, cause]);
 		}
 	}
-	Ok(result)
+	Ok(rcs(Coredata::Error(error)))
 });
 
 /// The macro value constructor.
@@ -839,22 +2783,43 @@ teko_simple_macro!(make_macro args : 2 => usize::MAX => {
 	Ok(rcs(Coredata::Macro(Macro::Library(params, code))))
 });
 
-/// Integer multiplication.
-teko_simple_function!(multiply args : 0 => usize::MAX => {
-	let mut sum = one();
-	for arg in args.iter() {
-		match **arg {
-			Sourcedata(_, Coredata::Integer(ref value)) => {
-				sum = sum * value;
-			}
-			_ => {
-				return Err(extype![arg.0, Integer, arg]);
+/// Integer modulo.
+///
+/// The result takes the sign of the divisor, matching Scheme's `modulo`, so `(mod -7 3)`
+/// is `2` and `(mod 7 -3)` is `-2`.
+teko_simple_function!(modulo args : 2 => 2 => {
+	match (&args[0].1, &args[1].1) {
+		(Coredata::Integer(lhs), Coredata::Integer(rhs)) => {
+			if rhs == &zero::<BigInt>() {
+				Err((args[1].0.clone(), "argument is zero".into()))
+			} else {
+				Ok(rcs(Coredata::Integer(lhs.mod_floor(rhs))))
 			}
 		}
+		(Coredata::Integer(..), _) => Err(extype![args[1].0, Integer, args[1]]),
+		_ => Err(extype![args[0].0, Integer, args[0]]),
 	}
-	Ok(rcs(Coredata::Integer(sum)))
 });
 
+/// Multiplication. `(* Integer*) => Integer`, any Float makes the result a Float.
+teko_simple_function!(multiply args : 0 => usize::MAX => {
+	arithmetic(args, one(), |lhs, rhs| lhs * rhs, |lhs, rhs| lhs * rhs, |lhs, rhs| lhs * rhs, false)
+});
+
+/// Write a line feed to `env.output`.
+fn newline(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	if let Some(args) = env.params.last() {
+		if !args.is_empty() {
+			return Some((None, arity_mismatch(0, 0, args.len())));
+		}
+	}
+	if writeln!(env.output).and_then(|_| env.output.flush()).is_err() {
+		return Some((None, "unable to write output".into()));
+	}
+	env.set_result(rcs(Coredata::Null()));
+	None
+}
+
 /// Boolean NOT.
 teko_simple_function!(not args : 1 => 1 => {
 	let arg = args.first().unwrap();
@@ -902,38 +2867,148 @@ teko_simple_function!(current_time_milliseconds args : 0 => 0 => {
 	Ok(rcs(Coredata::Integer(millis.to_bigint().unwrap())))
 });
 
-
-/// Integer addition. `(+ Integer*) => Integer`
-teko_simple_function!(plus args : 0 => usize::MAX => {
-	let mut sum = zero();
-	for arg in args.iter() {
-		match **arg {
-			Sourcedata(_, Coredata::Integer(ref value)) => {
-				sum = sum + value;
-			}
-			_ => {
-				return Err(extype![arg.0, Integer, arg]);
-			}
+/// Seconds since the environment was created, as a float. `(clock)`
+///
+/// The clock is monotonic, unlike `current-time-milliseconds` it never goes backwards.
+fn clock(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	if let Some(args) = env.params.last() {
+		if !args.is_empty() {
+			return Some((None, arity_mismatch(0, 0, args.len())));
 		}
 	}
-	Ok(rcs(Coredata::Integer(sum)))
+	let seconds = env.uptime().as_secs_f64();
+	env.set_result(rcs(Coredata::Float(Float(seconds))));
+	None
+}
+
+/// Evaluate an expression and write how long it took to `env.output`. `(time expression)`
+///
+/// The result is the value of the expression. The time is written in seconds as
+/// `time: 0.25 s` followed by a newline.
+fn time_expression(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let arg = env.get_result();
+	let expression = match (arg.len(), arg.head()) {
+		(Some(1), Some(expression)) => expression,
+		(Some(len), _) => return Some((None, arity_mismatch(1, 1, len))),
+		(None, _) => return Some((None, "macro: input not Cell or Null()".into())),
+	};
+	let start = rcs(Coredata::Float(Float(env.uptime().as_secs_f64())));
+	program.push(rc(Sourcedata(arg.0.clone(), Coredata::Cell(
+		rcs(Coredata::Function(Function::Builtin(time_internal, "@time-internal".into(), None))),
+		rcs(Coredata::Cell(start, rcs(Coredata::Cell(expression, rcs(Coredata::Null()))))),
+	))));
+	None
+}
+
+/// Write the time passed since `start` and yield the value. `(@time-internal start value)`
+fn time_internal(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let (start, value) = match env.params.last() {
+		Some(args) if args.len() == 2 => match args[0].1 {
+			Coredata::Float(start) => (start.0, args[1].clone()),
+			_ => return Some(extype![args[0].0, Float, args[0]]),
+		},
+		Some(args) => return Some((None, arity_mismatch(2, 2, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	let elapsed = env.uptime().as_secs_f64() - start;
+	if writeln!(env.output, "time: {} s", elapsed).and_then(|_| env.output.flush()).is_err() {
+		return Some((None, "unable to write output".into()));
+	}
+	env.set_result(value);
+	None
+}
+
+/// Addition. `(+ Integer*) => Integer`, any Float makes the result a Float.
+teko_simple_function!(plus args : 0 => usize::MAX => {
+	arithmetic(args, zero(), |lhs, rhs| lhs + rhs, |lhs, rhs| lhs + rhs, |lhs, rhs| lhs + rhs, false)
 });
 
-/// Print all arguments to standard output.
+/// Print all arguments to `env.output`, each followed by a newline.
 ///
 /// Does not put strings on the write form, however,
 /// strings inside structures are still printed in their written form: (" X).
-teko_simple_function!(print args : 1 => usize::MAX => {
-	for arg in args {
-		if let Coredata::String(ref value) = arg.1 {
-			println!["{}", value];
+fn print(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let args = match env.params.last() {
+		Some(args) if !args.is_empty() => args.clone(),
+		Some(args) => return Some((None, arity_mismatch(1, usize::MAX, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	for arg in &args {
+		let written = if let Coredata::String(ref value) = arg.1 {
+			writeln!(env.output, "{}", value)
 		} else {
-			println!["{}", arg];
+			writeln!(env.output, "{}", arg)
+		};
+		if written.and_then(|_| env.output.flush()).is_err() {
+			return Some((None, "unable to write output".into()));
+		}
+	}
+	env.set_result(args[args.len() - 1].clone());
+	None
+}
+
+/// Integer division truncating towards zero.
+///
+/// Together with `mod` this does not satisfy `n = q*d + r` for negative operands, since
+/// `quotient` truncates while `mod` takes the sign of the divisor (as in Scheme).
+teko_simple_function!(quotient args : 2 => 2 => {
+	match (&args[0].1, &args[1].1) {
+		(Coredata::Integer(lhs), Coredata::Integer(rhs)) => {
+			if rhs == &zero::<BigInt>() {
+				Err((args[1].0.clone(), "argument is zero".into()))
+			} else {
+				Ok(rcs(Coredata::Integer(lhs / rhs)))
+			}
 		}
+		(Coredata::Integer(..), _) => Err(extype![args[1].0, Integer, args[1]]),
+		_ => Err(extype![args[0].0, Integer, args[0]]),
+	}
+});
+
+/// Pretty print the argument to `env.output` with indentation.
+///
+/// An optional second argument gives the line width, which defaults to 80.
+fn pretty_print_data(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let args = match env.params.last() {
+		Some(args) if !args.is_empty() && args.len() <= 2 => args.clone(),
+		Some(args) => return Some((None, arity_mismatch(1, 2, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	let printed = match pretty_print_with_width(&args) {
+		Ok(printed) => printed,
+		Err(error) => return Some(error),
+	};
+	if writeln!(env.output, "{}", printed).and_then(|_| env.output.flush()).is_err() {
+		return Some((None, "unable to write output".into()));
 	}
-	Ok(args.last().unwrap().clone())
+	env.set_result(args[0].clone());
+	None
+}
+
+/// Pretty print the argument to a string, using the same layout as `pp`.
+teko_simple_function!(pretty_print_to_string args : 1 => 2 => {
+	Ok(rcs(Coredata::String(pretty_print_with_width(args)?)))
 });
 
+/// Shared argument handling of `pp` and `pretty-print-to-string`.
+fn pretty_print_with_width(args: &[Statement]) -> Result<String, (Option<Source>, String)> {
+	let width = match args.get(1) {
+		Some(arg) => {
+			if let Coredata::Integer(ref width) = arg.1 {
+				if let Some(width) = width.to_usize() {
+					width
+				} else {
+					return Err((arg.0.clone(), "width is not a valid line width".into()));
+				}
+			} else {
+				return Err(extype![arg.0, Integer, arg]);
+			}
+		}
+		None => 80,
+	};
+	Ok(pretty_print(&args[0], width))
+}
+
 /// Quote elements
 ///
 /// A builtin macro always stores the tail of the invocation inside `env.result`, so this macro is
@@ -942,6 +3017,19 @@ fn quote(_: &mut Program, _: &mut Env) -> Option<(Option<Source>, String)> {
 	None
 }
 
+/// Wrap data in a quote so that evaluating the result yields the data itself.
+///
+/// Used by builtins that construct code containing already evaluated values.
+fn quoted(data: &Statement) -> Statement {
+	rcs(Coredata::Cell(
+		rcs(Coredata::Macro(Macro::Builtin(quote, "_quote".into()))),
+		data.clone(),
+	))
+}
+
+/// Quote a single datum.
+///
+/// `(@ x)` and `(quote x)` return `x` without evaluating it.
 fn quote2(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
 	match *env.get_result() {
 		Sourcedata(ref src, Coredata::Cell(ref head, ref tail)) => {
@@ -961,33 +3049,54 @@ fn quote2(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
 	None
 }
 
+/// Read one datum without evaluating it. `(read)` reads standard input and `(read string)` a string.
+///
+/// Reading stops after the first complete form, so anything after it is left alone. Unwinds on
+/// a parse error or if the input ends before a form is complete.
 fn read(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
-	let mut parser = ParseState::from("tty");
-	for ch in io::stdin().bytes() {
-		if let Ok(ch) = ch {
-			if let Err(state) = parse_character(ch as char, &mut parser) {
-				let crp = Some(state.current_read_position.clone());
-				if let Some(error) = state.error {
-					return Some((crp, format!["parse error: {}", error]));
-				} else {
-					return Some((crp, "parse error".into()));
-				}
-			}
-			if is_ready_to_finish(&parser) {
-				let result = finish_parsing_characters(parser);
-				if let Ok(tree) = result {
-					match tree.first() {
-						Some(tree) => env.set_result(tree.clone()),
-						None => return Some((None, "parse error: ".into())),
-					}
-				}
-				break;
-			}
+	let result = match env.params.last().map(|args| args.as_slice()) {
+		Some([]) => read_form(io::stdin().bytes().map(|byte| byte.map(char::from)), ParseState::from("tty")),
+		Some([string]) => match string.1 {
+			Coredata::String(ref string) => read_form(string.chars().map(Ok), ParseState::from("string")),
+			_ => Err(extype![string.0, String, string]),
+		},
+		Some(args) => Err((None, arity_mismatch(0, 1, args.len()))),
+		None => Err((None, "no argument stack".into())),
+	};
+	match result {
+		Ok(datum) => {
+			env.set_result(datum);
+			None
+		}
+		Err(error) => Some(error),
+	}
+}
+
+/// Parse characters until the first form is complete.
+fn read_form<I: Iterator<Item = io::Result<char>>>(
+	characters: I,
+	mut parser: ParseState,
+) -> Result<Statement, (Option<Source>, String)> {
+	let parse_error = |state: ParseState| {
+		let crp = Some(state.current_read_position.clone());
+		if let Some(error) = state.error {
+			(crp, format!["parse error: {}", error])
 		} else {
-			return Some((None, "unable to read standard input".into()));
+			(crp, "parse error".into())
+		}
+	};
+	for character in characters {
+		let character = character.map_err(|_| (None, "unable to read input".to_string()))?;
+		parse_character(character, &mut parser).map_err(parse_error)?;
+		if is_ready_to_finish(&parser) {
+			break;
 		}
 	}
-	None
+	let position = Some(parser.current_read_position.clone());
+	match finish_parsing_characters(parser).map_err(parse_error)?.into_iter().next() {
+		Some(datum) => Ok(datum),
+		None => Err((position, "input ended before a complete form".into())),
+	}
 }
 
 /// Used by set internal to set variables.
@@ -1018,11 +3127,14 @@ fn set_internal(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, Strin
 	} else {
 		return Some((None, "no arg stack".into()));
 	};
-	env.push(&key, value);
+	env.set(&key, value);
 	None
 }
 
 /// Set a variable in the environment.
+///
+/// Replaces the innermost binding of the variable instead of adding a new one, so the value is
+/// seen by everyone sharing that binding and the outer bindings are left untouched.
 fn set(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
 	{
 		let args = env.get_result();
@@ -1043,7 +3155,7 @@ fn set(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)>
 		}
 
 		let sub = rcs(Coredata::Function(
-			Function::Builtin(set_internal, "@set-internal".into()),
+			Function::Builtin(set_internal, "@set-internal".into(), None),
 		));
 		if let Some(ref tail) = args.tail() {
 			match tail.1 {
@@ -1082,6 +3194,51 @@ fn set(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)>
 	None
 }
 
+/// Serialize data into compact binary `Bytes`.
+///
+/// Only data types can be serialized, see `utilities::serialize` for the format.
+teko_simple_function!(serialize_data args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	match serialize(arg) {
+		Ok(bytes) => Ok(rcs(Coredata::Bytes(bytes))),
+		Err(error) => Err((arg.0.clone(), error)),
+	}
+});
+
+/// Make an anonymous function able to refer to itself.
+///
+/// `(rec (function (n) ... (self ...)))` returns a function that binds `self` to itself
+/// as a local variable whenever it is called, so it may recurse without being named.
+/// The binding is recreated on each call instead of being stored inside the function,
+/// which keeps the data acyclic.
+teko_simple_function!(rec args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	match arg.1 {
+		Coredata::Function(Function::Library(ref params, ref code, ref captures)) => {
+			let binding = rcs(Coredata::Cell(
+				rcs(Coredata::Symbol(Symbol::from("local"))),
+				rcs(Coredata::Cell(
+					rcs(Coredata::Symbol(Symbol::from("self"))),
+					rcs(Coredata::Cell(
+						rcs(Coredata::Cell(
+							rcs(Coredata::Function(Function::Builtin(rec, "rec".into(), None))),
+							rcs(Coredata::Cell(arg.clone(), rcs(Coredata::Null()))),
+						)),
+						rcs(Coredata::Null()),
+					)),
+				)),
+			));
+			let mut code = code.clone();
+			code.push(binding);
+			Ok(rcs(Coredata::Function(Function::Library(params.clone(), code, captures.clone()))))
+		}
+		Coredata::Function(Function::Builtin(..)) => {
+			Err((arg.0.clone(), format!["expected Function but got {}", data_name(arg)]))
+		}
+		_ => Err(extype![arg.0, Function, arg]),
+	}
+});
+
 /// Sleep for a given number of milliseconds.
 teko_simple_function!(msleep args : 1 => 1 => {
 	let arg = args.first().unwrap();
@@ -1100,6 +3257,10 @@ teko_simple_function!(msleep args : 1 => 1 => {
 	Ok(arg.clone())
 });
 
+/// Evaluate a sequence of statements.
+///
+/// Bound to both `program` and `begin`. The statements are evaluated from left to right and the
+/// result of the last one is the result of the whole sequence. An empty sequence yields `Null`.
 fn program(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
 	program.extend(collect_cell_into_revvec(&env.get_result()));
 	None
@@ -1108,6 +3269,7 @@ fn program(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, Stri
 /// Create a string
 ///
 /// Creates a string from the given symbols by inserting single spaces inbetween each symbol.
+/// The string keeps the source of its first symbol, `load` uses this to resolve relative paths.
 teko_simple_macro!(string arg : 0 => usize::MAX => {
 	let data = {
 		let mut data = collect_cell_into_revvec(&arg);
@@ -1171,44 +3333,19 @@ teko_simple_macro!(string arg : 0 => usize::MAX => {
 			}
 		}
 	}
-	Ok(rcs(Coredata::String(ret)))
+	Ok(rc(Sourcedata(arg.0.clone(), Coredata::String(ret))))
+});
+
+/// Substitute data in a structure. `(subst new old data)`
+///
+/// Returns a copy of `data` where everything that is the `same?` as `old` is replaced by `new`.
+teko_simple_function!(subst args : 3 => 3 => {
+	Ok(substitute(&args[2], &args[1], &args[0]))
 });
 
-/// Integer subtraction.
+/// Subtraction, a single argument is negated.
 teko_simple_function!(subtract args : 1 => usize::MAX => {
-	let mut sum = zero();
-	if args.len() == 1 {
-		for arg in args.iter() {
-			match **arg {
-				Sourcedata(_, Coredata::Integer(ref value)) => {
-					sum = sum - value;
-				}
-				_ => {
-					return Err(extype![arg.0, Integer, arg]);
-				}
-			}
-		}
-	} else if args.len() > 1 {
-		let mut first = true;
-		for arg in args.iter() {
-			match **arg {
-				Sourcedata(_, Coredata::Integer(ref value)) => {
-					if first {
-						sum = value.clone();
-					} else {
-						sum = sum - value;
-					}
-				}
-				_ => {
-					return Err(extype![arg.0, Integer, arg]);
-				}
-			}
-			first = false;
-		}
-	} else {
-		return Err((None, arity_mismatch(1, usize::MAX, 0)));
-	}
-	Ok(rcs(Coredata::Integer(sum)))
+	arithmetic(args, zero(), |lhs, rhs| lhs - rhs, |lhs, rhs| lhs - rhs, |lhs, rhs| lhs - rhs, false)
 });
 
 /// Take the tail of a cell.
@@ -1229,6 +3366,86 @@ teko_simple_function!(to_string args : 1 => 1 => {
 	Ok(rcs(Coredata::String(format!["{}", arg])))
 });
 
+/// Write a number in a radix. `(number->string 255 16)` is `ff`.
+///
+/// Integers and rationals can be written in radix 2, 8, 10 or 16, defaulting to 10. Floats are
+/// only written in radix 10. Any other radix unwinds.
+teko_simple_function!(number_to_string args : 1 => 2 => {
+	let radix = number_radix_argument(args.get(1))?;
+	match args[0].1 {
+		Coredata::Integer(ref number) => Ok(rcs(Coredata::String(number.to_str_radix(radix)))),
+		Coredata::Rational(ref number) => Ok(rcs(Coredata::String(
+			format!["{}/{}", number.numer().to_str_radix(radix), number.denom().to_str_radix(radix)],
+		))),
+		Coredata::Float(..) if radix == 10 => Ok(rcs(Coredata::String(format!["{}", args[0]]))),
+		Coredata::Float(..) => Err((args[1].0.clone(), format!["unsupported radix {} for a float", radix])),
+		_ => Err(extype![args[0].0, Integer or Rational or Float, args[0]]),
+	}
+});
+
+/// Read a number from a string. `(string->number (" ff) 16)` is `255`.
+///
+/// The string is read like a number in code, see `parse_number`, so rationals such as `1/2` are
+/// read but there are no complex numbers. Accepts the same radices as `number->string`. Gives `false` if the string
+/// is not a number.
+teko_simple_function!(string_to_number args : 1 => 2 => {
+	let string = match args[0].1 {
+		Coredata::String(ref string) => string,
+		_ => return Err(extype![args[0].0, String, args[0]]),
+	};
+	let radix = number_radix_argument(args.get(1))?;
+	Ok(rcs(parse_number(string, radix).unwrap_or(Coredata::Boolean(false))))
+});
+
+/// Format arguments into a string. `(format (" ~a + ~a = ~a) 1 2 3)` is `1 + 2 = 3`.
+///
+/// `~a` is replaced by the next argument as written by `display` and `~s` as written by `write`.
+/// `~%` is a newline and `~~` a tilde. Unwinds if the number of arguments does not match the
+/// number of `~a` and `~s` directives, or on an unknown directive.
+teko_simple_function!(format args : 1 => usize::MAX => {
+	let template = match args[0].1 {
+		Coredata::String(ref template) => template,
+		_ => return Err(extype![args[0].0, String, args[0]]),
+	};
+	let directives = {
+		let mut count = 0;
+		let mut characters = template.chars();
+		while let Some(character) = characters.next() {
+			if character == '~' {
+				match characters.next() {
+					Some('a') | Some('s') => count += 1,
+					Some('%') | Some('~') => {}
+					Some(other) => return Err((args[0].0.clone(), format!["unknown format directive: ~{}", other])),
+					None => return Err((args[0].0.clone(), "format string ends in a lone ~".into())),
+				}
+			}
+		}
+		count
+	};
+	if directives != args.len() - 1 {
+		return Err((args[0].0.clone(), format![
+			"format string has {} directives but got {} arguments", directives, args.len() - 1
+		]));
+	}
+	let mut output = String::new();
+	let mut arguments = args[1..].iter();
+	let mut characters = template.chars();
+	while let Some(character) = characters.next() {
+		if character != '~' {
+			output.push(character);
+			continue;
+		}
+		match characters.next() {
+			Some('a') => output.push_str(&::print::display(arguments.next().unwrap())),
+			Some('s') => output.push_str(&format!["{}", arguments.next().unwrap()]),
+			Some('%') => output.push('\n'),
+			_ => output.push('~'),
+		}
+	}
+	Ok(rcs(Coredata::String(output)))
+});
+
+/// Convert a symbol into a string with the same characters.
 teko_simple_function!(symbol_to_string args : 1 => 1 => {
 	let arg = args.first().unwrap();
 	match **arg {
@@ -1241,6 +3458,7 @@ teko_simple_function!(symbol_to_string args : 1 => 1 => {
 	}
 });
 
+/// Convert a string into a symbol with the same characters.
 teko_simple_function!(string_to_symbol args : 1 => 1 => {
 	let arg = args.first().unwrap();
 	match **arg {
@@ -1304,7 +3522,8 @@ teko_simple_function!(string_at args : 2 => 2 => {
 	Ok(rcs(Coredata::String(start)))
 });
 
-teko_simple_function!(string_append args : 1 => usize::MAX => {
+/// Concatenate any number of strings into a new string.
+teko_simple_function!(string_append args : 0 => usize::MAX => {
 	let mut state = String::from("");
 	for i in args {
 		match **i {
@@ -1319,6 +3538,265 @@ teko_simple_function!(string_append args : 1 => usize::MAX => {
 	Ok(rcs(Coredata::String(state)))
 });
 
+/// Count the characters (not bytes) of a string.
+teko_simple_function!(string_length args : 1 => 1 => {
+	let arg = args.first().unwrap();
+	match arg.1 {
+		Coredata::String(ref string) => Ok(rcs(Coredata::Integer(string.chars().count().into()))),
+		_ => Err(extype![arg.0, String, arg]),
+	}
+});
+
+/// Get the character at a 0-based index of a string. `(string-ref string index)`
+///
+/// Strings are indexed by Unicode scalar values, not bytes.
+teko_simple_function!(string_ref args : 2 => 2 => {
+	let string = match args[0].1 {
+		Coredata::String(ref string) => string,
+		_ => return Err(extype![args[0].0, String, args[0]]),
+	};
+	let index = match args[1].1 {
+		Coredata::Integer(ref index) => index,
+		_ => return Err(extype![args[1].0, Integer, args[1]]),
+	};
+	match index.to_usize().and_then(|index| string.chars().nth(index)) {
+		Some(character) => Ok(rcs(Coredata::Char(character))),
+		None => Err((args[1].0.clone(), format![
+			"index {} out of bounds for string of length {}", index, string.chars().count()
+		])),
+	}
+});
+
+/// Get the characters from `start` up to but not including `end`. `(substring string start end)`
+///
+/// Strings are indexed by Unicode scalar values, not bytes. Unwinds unless
+/// `0 <= start <= end <= length`.
+teko_simple_function!(substring args : 3 => 3 => {
+	let string = match args[0].1 {
+		Coredata::String(ref string) => string,
+		_ => return Err(extype![args[0].0, String, args[0]]),
+	};
+	let (start, end) = match (&args[1].1, &args[2].1) {
+		(Coredata::Integer(start), Coredata::Integer(end)) => (start, end),
+		(Coredata::Integer(_), _) => return Err(extype![args[2].0, Integer, args[2]]),
+		_ => return Err(extype![args[1].0, Integer, args[1]]),
+	};
+	let length = string.chars().count();
+	match (start.to_usize(), end.to_usize()) {
+		(Some(first), Some(last)) if first <= last && last <= length => {
+			Ok(rcs(Coredata::String(string.chars().skip(first).take(last - first).collect())))
+		}
+		_ => Err((args[1].0.clone(), format![
+			"range {} to {} is not within string of length {}", start, end, length
+		])),
+	}
+});
+
+/// Split a string on every occurrence of a separator. `(string-split string separator)`
+///
+/// Consecutive separators give empty strings in between, and so does a separator at either
+/// end. An empty separator unwinds, use `string-at` to take a string apart into characters.
+teko_simple_function!(string_split args : 2 => 2 => {
+	let string = match args[0].1 {
+		Coredata::String(ref string) => string,
+		_ => return Err(extype![args[0].0, String, args[0]]),
+	};
+	let separator = match args[1].1 {
+		Coredata::String(ref separator) if separator.is_empty() => {
+			return Err((args[1].0.clone(), "separator is empty".into()));
+		}
+		Coredata::String(ref separator) => separator,
+		_ => return Err(extype![args[1].0, String, args[1]]),
+	};
+	let parts: Vec<&str> = string.split(separator.as_str()).collect();
+	Ok(parts.into_iter().rev().fold(rcs(Coredata::Null()), |tail, part| {
+		rcs(Coredata::Cell(rcs(Coredata::String(part.into())), tail))
+	}))
+});
+
+/// Join a list of strings, putting a separator between each. `(string-join list separator)`
+teko_simple_function!(string_join args : 2 => 2 => {
+	match args[0].1 {
+		Coredata::Cell(..) | Coredata::Null() => {}
+		_ => return Err(extype![args[0].0, Cell or Null, args[0]]),
+	}
+	let separator = match args[1].1 {
+		Coredata::String(ref separator) => separator,
+		_ => return Err(extype![args[1].0, String, args[1]]),
+	};
+	let mut parts = vec![];
+	for element in collect_cell_into_revvec(&args[0]).into_iter().rev() {
+		match element.1 {
+			Coredata::String(ref part) => parts.push(part.clone()),
+			_ => return Err(extype![element.0, String, element]),
+		}
+	}
+	Ok(rcs(Coredata::String(parts.join(separator))))
+});
+
+/// Apply a function to each character of a string in order. `(string-for-each function string)`
+///
+/// The string is walked by Unicode scalar and the results are discarded, so the call pushes
+/// `(@for-each-internal (function 'c1) (function 'c2) ...)` which returns `Null`.
+fn string_for_each(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let call = if let Some(args) = env.params.last() {
+		if args.len() != 2 {
+			return Some((None, arity_mismatch(2, 2, args.len())));
+		}
+		if let Coredata::Function(..) = args[0].1 {
+		} else {
+			return Some((args[0].0.clone(), format!["expected Function but got {}", data_name(&args[0])]));
+		}
+		let string = match args[1].1 {
+			Coredata::String(ref string) => string,
+			_ => return Some(extype![args[1].0, String, args[1]]),
+		};
+		let null = rcs(Coredata::Null());
+		let calls = string.chars().rev().fold(null.clone(), |tail, character| {
+			cons(cons(args[0].clone(), cons(quoted(&rcs(Coredata::Char(character))), null.clone())), tail)
+		});
+		cons(rcs(Coredata::Function(Function::Builtin(for_each_internal, "@for-each-internal".into(), None))), calls)
+	} else {
+		return Some((None, "no argument stack".into()));
+	};
+	program.push(call);
+	None
+}
+
+/// Interpolate `${expression}` placeholders in a string.
+///
+/// Each placeholder is read and evaluated in the current environment, and its printed form
+/// replaces the placeholder. Strings are substituted without their written form, like `print`.
+/// The optional second argument is a list of `(symbol value)` lists that are bound while
+/// evaluating the placeholders. Errors in the template report the column in the template.
+fn string_interpolate(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let (template, bindings) = if let Some(args) = env.params.last() {
+		if args.is_empty() || args.len() > 2 {
+			return Some((None, arity_mismatch(1, 2, args.len())));
+		}
+		match *args[0] {
+			Sourcedata(_, Coredata::String(ref string)) => (string.clone(), args.get(1).cloned()),
+			Sourcedata(ref src, ..) => {
+				return Some(extype![src, String, args[0]]);
+			}
+		}
+	} else {
+		return Some((None, "no argument stack".into()));
+	};
+	let position = |index: usize| Some(Source {
+		line: 1,
+		column: index + 1,
+		offset: index,
+		length: 0,
+		source: "string-interpolate".into(),
+	});
+	let mut pieces = vec![rcs(Coredata::Function(Function::Builtin(
+		string_interpolate_internal,
+		"@string-interpolate-internal".into(),
+		None,
+	)))];
+	let characters: Vec<char> = template.chars().collect();
+	let mut literal = String::new();
+	let mut index = 0;
+	while index < characters.len() {
+		match characters[index] {
+			'$' if characters.get(index + 1) == Some(&'{') => {
+				let start = index;
+				let mut depth = 1;
+				let mut expression = String::new();
+				index += 2;
+				while index < characters.len() {
+					match characters[index] {
+						'{' => depth += 1,
+						'}' => depth -= 1,
+						_ => {}
+					}
+					if depth == 0 {
+						break;
+					}
+					expression.push(characters[index]);
+					index += 1;
+				}
+				if depth != 0 {
+					return Some((position(start), "unbalanced brace in template".into()));
+				}
+				let mut state = ParseState::from("string-interpolate");
+				state.current_read_position.column = start + 3;
+				for character in expression.chars() {
+					if let Err(state) = parse_character(character, &mut state) {
+						return Some((position(start), format!["parse error: {:?}", state.error]));
+					}
+				}
+				match finish_parsing_characters(state) {
+					Ok(ref forms) if forms.len() == 1 => {
+						pieces.push(rcs(Coredata::String(literal.clone())));
+						pieces.push(forms[0].clone());
+						literal.clear();
+					}
+					Ok(_) => {
+						return Some((position(start), "placeholder must contain one expression".into()));
+					}
+					Err(state) => {
+						return Some((position(start), format!["parse error: {:?}", state.error]));
+					}
+				}
+			}
+			'}' => {
+				return Some((position(index), "unbalanced brace in template".into()));
+			}
+			character => {
+				literal.push(character);
+			}
+		}
+		index += 1;
+	}
+	pieces.push(rcs(Coredata::String(literal)));
+	let mut call = rcs(Coredata::Null());
+	for piece in pieces.iter().rev() {
+		call = rcs(Coredata::Cell(piece.clone(), call));
+	}
+	if let Some(bindings) = bindings {
+		let mut parameters = vec![];
+		let mut values = vec![];
+		for binding in collect_cell_into_revvec(&bindings) {
+			match (binding.head(), binding.tail().and_then(|tail| tail.head())) {
+				(Some(ref name), Some(ref value)) => {
+					if let Coredata::Symbol(ref symbol) = name.1 {
+						parameters.push(symbol.clone());
+						values.push(quoted(value));
+					} else {
+						return Some(extype![name.0, Symbol, name]);
+					}
+				}
+				_ => {
+					return Some((binding.0.clone(), "binding is not a (symbol value) list".into()));
+				}
+			}
+		}
+		let mut wrapper = rcs(Coredata::Null());
+		for value in values.iter().rev() {
+			wrapper = rcs(Coredata::Cell(value.clone(), wrapper));
+		}
+		let function = rcs(Coredata::Function(Function::Library(parameters, vec![call], env.scope.clone())));
+		call = rcs(Coredata::Cell(function, wrapper));
+	}
+	program.push(call);
+	None
+}
+
+/// Concatenate the printed forms of the interpolated pieces.
+teko_simple_function!(string_interpolate_internal args : 0 => usize::MAX => {
+	let mut result = String::new();
+	for arg in args {
+		if let Coredata::String(ref string) = arg.1 {
+			result.push_str(string);
+		} else {
+			result.push_str(&format!["{}", arg]);
+		}
+	}
+	Ok(rcs(Coredata::String(result)))
+});
+
 /// Return a stack trace.
 ///
 /// The stack trace will not show tail call optimized calls, so there may
@@ -1331,6 +3809,25 @@ fn trace(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String
 	None
 }
 
+/// Get the calls in progress as a list of strings, outermost first.
+///
+/// Each string names the call and where it was made, such as `calling + at 3:5:file.tko`.
+/// Like `@trace` this leaves out tail calls.
+fn backtrace_calls(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	if let Some(args) = env.params.last() {
+		if !args.is_empty() {
+			return Some((None, arity_mismatch(0, 0, args.len())));
+		}
+	}
+	let mut result = rcs(Coredata::Null());
+	for (source, frame) in backtrace(program).into_iter().rev() {
+		let frame = rcs(Coredata::String(format!["{} at {}", frame, source]));
+		result = rcs(Coredata::Cell(frame, result));
+	}
+	env.set_result(result);
+	None
+}
+
 /// Set up a "catch-all" that catches all errors
 fn wind(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
 	let args = env.get_result();
@@ -1340,16 +3837,42 @@ fn wind(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)
 	None
 }
 
-/// Write to standard output.
+/// Evaluate an expression and handle the error if it unwinds. `(try expression handler)`
 ///
-/// Writing is a symmetric operation together with read. This means that
-/// writing an object, and then reading the result will give back the same
-/// object, although it may be necessary to explicitly eval parts of the
-/// object, the representation will always stay intact regardless of how
-/// many reads and writes you apply to it.
-teko_simple_function!(write args : 1 => usize::MAX => {
-	for arg in args {
-		println!["{}", arg];
+/// The handler is evaluated first and called with the error as its only argument when the
+/// expression unwinds, otherwise the value of the expression is the result, even if that value
+/// is an error. The form is pushed as `(@try-internal handler (wind (list expression)))`, so the
+/// handler runs outside of the `wind` and an error inside the handler keeps unwinding.
+fn try_catch(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let args = env.get_result();
+	let mut parts = collect_cell_into_revvec(&args);
+	if parts.len() != 2 {
+		return Some((args.0.clone(), arity_mismatch(2, 2, parts.len())));
 	}
-	Ok(args.last().unwrap().clone())
-});
+	let (expression, handler) = (parts.pop().unwrap(), parts.pop().unwrap());
+	program.push(rc(Sourcedata(args.0.clone(), Coredata::Cell(
+		rcs(Coredata::Function(Function::Builtin(try_internal, "@try-internal".into(), None))),
+		cons(handler, cons(wind_list(expression), rcs(Coredata::Null()))),
+	))));
+	None
+}
+
+/// Call the handler if an error was raised, otherwise yield the value. `(@try-internal handler value)`
+fn try_internal(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+	let (handler, value) = match env.params.last() {
+		Some(args) if args.len() == 2 => (args[0].clone(), args[1].clone()),
+		Some(args) => return Some((None, arity_mismatch(2, 2, args.len()))),
+		None => return Some((None, "no argument stack".into())),
+	};
+	match raised(&value) {
+		Ok(value) => env.set_result(value),
+		Err(error) => {
+			if let Coredata::Function(..) = handler.1 {
+			} else {
+				return Some((handler.0.clone(), format!["expected Function but got {}", data_name(&handler)]));
+			}
+			program.push(rcs(Coredata::Cell(handler, rcs(Coredata::Cell(quoted(&error), rcs(Coredata::Null()))))));
+		}
+	}
+	None
+}
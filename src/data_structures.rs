@@ -2,15 +2,16 @@
 
 use std::collections::HashMap;
 
-use num::BigInt;
+use num::{BigInt, BigRational};
 
-use std::collections::HashSet;
 use std::iter::Iterator;
 use std::convert::Into;
-use std::sync::Arc;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 /// A symbol is a string of characters that contains no whitespace nor parentheses
-#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Symbol {
 	value: String,
 }
@@ -57,42 +58,116 @@ impl From<String> for Symbol {
 	}
 }
 
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
-pub struct Deparize {
-	set: HashSet<Symbol>,
+/// Variables bound by a function call, inside the scope the function was made in.
+///
+/// Scopes are shared rather than copied: a function made during a call keeps the scope of that
+/// call, so it sees the variables of the call after the call returns, as well as any `set!` of
+/// them. Variables that are in no scope are looked up in the top level store when used.
+#[derive(Debug, Default)]
+pub struct Scope {
+	/// Few variables are bound at once, so searching a list is faster than hashing
+	variables: Mutex<Vec<(Symbol, Statement)>>,
+	parent: Option<Arc<Scope>>,
 }
 
-use std::hash::{Hash, Hasher};
-
-impl Hash for Deparize {
-	fn hash<H: Hasher>(&self, state: &mut H) {
-		for i in &self.set {
-			i.hash(state);
+impl Scope {
+	/// Create a scope binding `variables` inside `parent`
+	pub fn new(variables: Vec<(Symbol, Statement)>, parent: Option<Arc<Scope>>) -> Scope {
+		Scope {
+			variables: Mutex::new(variables),
+			parent,
+		}
+	}
+	fn variables(&self) -> MutexGuard<'_, Vec<(Symbol, Statement)>> {
+		self.variables.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+	/// Iterate over this scope and the scopes it is in, innermost first
+	pub fn chain(&self) -> impl Iterator<Item = &Scope> {
+		let mut next = Some(self);
+		::std::iter::from_fn(move || {
+			let current = next?;
+			next = current.parent.as_deref();
+			Some(current)
+		})
+	}
+	/// Get the value of the innermost variable called `symbol`
+	pub fn get(&self, symbol: &Symbol) -> Option<Statement> {
+		self.chain().find_map(|scope| {
+			scope.variables().iter().find(|variable| variable.0 == *symbol).map(|variable| variable.1.clone())
+		})
+	}
+	/// Replace the value of the innermost variable called `symbol`, `false` if there is none
+	pub fn set(&self, symbol: &Symbol, value: Statement) -> bool {
+		for scope in self.chain() {
+			if let Some(variable) = scope.variables().iter_mut().find(|variable| variable.0 == *symbol) {
+				variable.1 = value;
+				return true;
+			}
+		}
+		false
+	}
+	/// Bind `symbol` in this scope, replacing any variable of that name already in it
+	pub fn insert(&self, symbol: Symbol, value: Statement) {
+		let mut variables = self.variables();
+		if let Some(variable) = variables.iter_mut().find(|variable| variable.0 == symbol) {
+			variable.1 = value;
+		} else {
+			variables.push((symbol, value));
+		}
+	}
+	/// Names of the variables of this scope
+	pub fn symbols(&self) -> Vec<Symbol> {
+		self.variables().iter().map(|variable| variable.0.clone()).collect()
+	}
+	/// Number of variables in this scope and the scopes it is in
+	pub fn count(&self) -> usize {
+		self.chain().map(|scope| scope.variables().len()).sum()
+	}
+	/// Check if both are the same scope, which is how functions compare their scopes
+	pub fn same(lhs: &Option<Arc<Scope>>, rhs: &Option<Arc<Scope>>) -> bool {
+		match (lhs, rhs) {
+			(Some(lhs), Some(rhs)) => Arc::ptr_eq(lhs, rhs),
+			(None, None) => true,
+			_ => false,
 		}
 	}
 }
 
-use std::collections;
+/// Marks where a call ends, with the scope of the caller to return to.
+#[derive(Clone, Debug, Default)]
+pub struct Deparize {
+	pub scope: Option<Arc<Scope>>,
+}
 
-impl Deparize {
-	/// Check if the Symbol already exists in this Deparize and then insert it
-	pub fn check_preexistence_and_merge_single(&mut self, symbol: &Symbol) -> bool {
-		!self.set.insert(symbol.clone())
+impl PartialEq for Deparize {
+	fn eq(&self, other: &Deparize) -> bool {
+		Scope::same(&self.scope, &other.scope)
 	}
-	// TODO put into trait IntoIter
-	pub fn iter(&self) -> collections::hash_set::Iter<Symbol> {
-		self.set.iter()
+}
+
+impl Eq for Deparize { }
+
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+
+impl Hash for Deparize {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.scope.as_ref().map(|scope| Arc::as_ptr(scope) as usize).hash(state);
 	}
 }
 
 #[cfg(test)]
 mod tests {
 	#[test]
-	fn test_deparize() {
+	fn test_scope() {
 		use super::*;
-		let mut dep = Deparize::default();
-		assert![!dep.check_preexistence_and_merge_single(&Symbol::from("nice"))];
-		assert![dep.check_preexistence_and_merge_single(&Symbol::from("nice"))];
+		let outer = Arc::new(Scope::default());
+		outer.insert(Symbol::from("a"), Arc::new(Sourcedata(None, Coredata::Null())));
+		let inner = Scope::new(vec![], Some(outer.clone()));
+		assert![inner.set(&Symbol::from("a"), Arc::new(Sourcedata(None, Coredata::Boolean(true))))];
+		assert![!inner.set(&Symbol::from("b"), Arc::new(Sourcedata(None, Coredata::Null())))];
+		assert_eq![outer.get(&Symbol::from("a")).unwrap().1, Coredata::Boolean(true)];
+		assert_eq![inner.count(), 1];
+		assert![inner.symbols().is_empty()];
 	}
 }
 
@@ -110,9 +185,12 @@ pub enum Commands {
 	Prep(Statement),
 	Param,
 	Deparize(Deparize),
+	/// Continue with the first statement unless the result is `false`, then with the second
 	If(Statement, Statement),
 	Wind,
 	Eval,
+	/// Value of a `letrec` variable before its value is computed
+	Uninitialized,
 }
 
 /// Top level data structure used by the parser and interpreter
@@ -134,19 +212,21 @@ pub type Transfer = fn(program: &mut Program, env: &mut Env) -> Option<(Option<S
 
 /// Function types that can be called by the interpreter
 pub enum Function {
-	/// A function written in the implementation language
-	Builtin(Transfer, String),
-	/// Parameter names with a sequence of statements that are inserted into the program when called
-	Library(Vec<Symbol>, Program),
+	/// A function written in the implementation language, with the least and most number of
+	/// arguments it takes if `eval` is to check them before calling, `usize::MAX` for no limit
+	Builtin(Transfer, String, Option<(usize, usize)>),
+	/// Parameter names with a sequence of statements that are inserted into the program when called,
+	/// and the scope the function was made in, which a call binds the parameters inside
+	Library(Vec<Symbol>, Program, Option<Arc<Scope>>),
 }
 
 impl Hash for Function {
 	fn hash<H: Hasher>(&self, state: &mut H) {
 		match *self {
-			Function::Builtin(_, ref name) => {
+			Function::Builtin(_, ref name, _) => {
 				name.hash(state);
 			}
-			Function::Library(ref params, ref code) => {
+			Function::Library(ref params, ref code, _) => {
 				params.hash(state);
 				code.hash(state);
 			}
@@ -157,16 +237,16 @@ impl Hash for Function {
 impl PartialEq for Function {
 	fn eq(&self, other: &Function) -> bool {
 		match *self {
-			Function::Builtin(_, ref lhs) => {
-				if let Function::Builtin(_, ref rhs) = *other {
+			Function::Builtin(_, ref lhs, _) => {
+				if let Function::Builtin(_, ref rhs, _) = *other {
 					lhs == rhs
 				} else {
 					false
 				}
 			}
-			Function::Library(ref params_lhs, ref program_lhs) => {
-				if let Function::Library(ref params_rhs, ref program_rhs) = *other {
-					params_lhs == params_rhs && program_lhs == program_rhs
+			Function::Library(ref params_lhs, ref program_lhs, ref captures_lhs) => {
+				if let Function::Library(ref params_rhs, ref program_rhs, ref captures_rhs) = *other {
+					params_lhs == params_rhs && program_lhs == program_rhs && Scope::same(captures_lhs, captures_rhs)
 				} else {
 					false
 				}
@@ -223,20 +303,222 @@ impl PartialEq for Macro {
 impl Eq for Macro { }
 
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// A persistent map from immediates (integers, strings, and symbols) to arbitrary data
+///
+/// Inserting gives a new table and leaves the original unchanged. Keys are stored without
+/// their source so that equal keys from different places in the code find the same entry.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct Table {
 	table: HashMap<Statement, Statement>
 }
 
 impl Hash for Table {
 	fn hash<H: Hasher>(&self, state: &mut H) {
-		for (k, v) in &self.table {
+		for k in self.keys() {
 			k.hash(state);
-			v.hash(state);
+			self.table[&k].hash(state);
+		}
+	}
+}
+
+impl Table {
+	/// Check if the data can be used as a key, and strip its source if so
+	pub fn key(data: &Sourcedata) -> Option<Statement> {
+		let key = match data.1 {
+			Coredata::Integer(ref integer) => Coredata::Integer(integer.clone()),
+			Coredata::String(ref string) => Coredata::String(string.clone()),
+			Coredata::Symbol(ref symbol) => Coredata::Symbol(symbol.clone()),
+			_ => return None,
+		};
+		Some(Arc::new(Sourcedata(None, key)))
+	}
+	/// Get the value of a key created by `Table::key`
+	pub fn get(&self, key: &Statement) -> Option<Statement> {
+		self.table.get(key).cloned()
+	}
+	/// Create a new table with the key set to the value
+	pub fn insert(&self, key: Statement, value: Statement) -> Table {
+		let mut table = self.clone();
+		table.table.insert(key, value);
+		table
+	}
+	/// All keys, integers first, then strings, then symbols, each in ascending order
+	pub fn keys(&self) -> Vec<Statement> {
+		fn rank(data: &Sourcedata) -> u8 {
+			match data.1 {
+				Coredata::Integer(..) => 0,
+				Coredata::String(..) => 1,
+				_ => 2,
+			}
+		}
+		let mut keys: Vec<Statement> = self.table.keys().cloned().collect();
+		keys.sort_by(|lhs, rhs| {
+			match (&lhs.1, &rhs.1) {
+				(Coredata::Integer(lhs), Coredata::Integer(rhs)) => lhs.cmp(rhs),
+				(Coredata::String(lhs), Coredata::String(rhs)) => lhs.cmp(rhs),
+				(Coredata::Symbol(lhs), Coredata::Symbol(rhs)) => lhs.cmp(rhs),
+				_ => rank(lhs).cmp(&rank(rhs)),
+			}
+		});
+		keys
+	}
+	pub fn len(&self) -> usize {
+		self.table.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.table.is_empty()
+	}
+}
+
+/// A mutable container holding a single datum
+///
+/// Boxes are compared and hashed by identity: two boxes are only equal if they are the same box.
+pub struct Boxed(Mutex<Statement>);
+
+impl Boxed {
+	pub fn new(data: Statement) -> Boxed {
+		Boxed(Mutex::new(data))
+	}
+	pub fn get(&self) -> Statement {
+		match self.0.lock() {
+			Ok(guard) => guard.clone(),
+			Err(poisoned) => poisoned.into_inner().clone(),
+		}
+	}
+	pub fn set(&self, data: Statement) {
+		match self.0.lock() {
+			Ok(mut guard) => *guard = data,
+			Err(poisoned) => *poisoned.into_inner() = data,
+		}
+	}
+}
+
+impl Hash for Boxed {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		(self as *const Boxed).hash(state);
+	}
+}
+
+impl PartialEq for Boxed {
+	fn eq(&self, other: &Boxed) -> bool {
+		::std::ptr::eq(self, other)
+	}
+}
+
+impl Eq for Boxed { }
+
+/// Mutable vector of data with constant time indexing, see `vector-set!`.
+///
+/// Unlike boxes, vectors are compared by their elements.
+pub struct Vector(Mutex<Vec<Statement>>);
+
+impl Vector {
+	pub fn new(data: Vec<Statement>) -> Vector {
+		Vector(Mutex::new(data))
+	}
+	fn lock(&self) -> ::std::sync::MutexGuard<'_, Vec<Statement>> {
+		match self.0.lock() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner(),
+		}
+	}
+	pub fn get(&self, index: usize) -> Option<Statement> {
+		self.lock().get(index).cloned()
+	}
+	pub fn len(&self) -> usize {
+		self.lock().len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.lock().is_empty()
+	}
+	/// Set an element, returns false if the index is out of bounds
+	pub fn set(&self, index: usize, data: Statement) -> bool {
+		if let Some(element) = self.lock().get_mut(index) {
+			*element = data;
+			true
+		} else {
+			false
+		}
+	}
+	pub fn to_vec(&self) -> Vec<Statement> {
+		self.lock().clone()
+	}
+}
+
+/// Hashes only the length and the kind of each element.
+///
+/// Hashing the elements themselves would recurse into a vector that contains itself. Equal
+/// vectors have the same length and kinds, so this agrees with `PartialEq`.
+impl Hash for Vector {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		let elements = self.to_vec();
+		elements.len().hash(state);
+		for element in elements {
+			::std::mem::discriminant(&element.1).hash(state);
+		}
+	}
+}
+
+impl PartialEq for Vector {
+	fn eq(&self, other: &Vector) -> bool {
+		::std::ptr::eq(self, other) || self.to_vec() == other.to_vec()
+	}
+}
+
+impl Eq for Vector { }
+
+/// Inexact floating point number.
+///
+/// Floats compare by value, so `0.0` equals `-0.0` and `NaN` is not equal to itself.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Float(pub f64);
+
+impl Hash for Float {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		// Equal floats must hash equally, and 0.0 == -0.0
+		if self.0 == 0.0 {
+			0u64.hash(state);
+		} else {
+			self.0.to_bits().hash(state);
 		}
 	}
 }
 
+impl Eq for Float { }
+
+/// An error, as raised by the interpreter or `error`, or made by `make-error`.
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub struct EvalError {
+	/// What went wrong, if known
+	pub message: Option<String>,
+	/// Where the error was raised
+	pub source: Option<Source>,
+	/// The error that led to this one, which may have a cause of its own
+	pub cause: Option<Statement>,
+	/// The trace of a raised error, or the data given to `make-error`
+	pub data: Statement,
+}
+
+impl EvalError {
+	/// Create an error holding `data`, with the data as message if it is a string.
+	pub fn new(data: Statement) -> EvalError {
+		let message = if let Coredata::String(ref string) = data.1 { Some(string.clone()) } else { None };
+		EvalError { message, source: None, cause: None, data }
+	}
+	/// Iterate over the causes of this error, nearest first.
+	pub fn causes(&self) -> impl Iterator<Item = &EvalError> {
+		let mut cause = self.cause.as_ref();
+		::std::iter::from_fn(move || {
+			let error = match cause?.1 {
+				Coredata::Error(ref error) => error,
+				_ => return None,
+			};
+			cause = error.cause.as_ref();
+			Some(error)
+		})
+	}
+}
+
 /// Core data types of the Teko machine
 #[derive(Debug, Eq, Hash)]
 pub enum Coredata {
@@ -244,10 +526,19 @@ pub enum Coredata {
 	// TODO Add quoted form for writing out whatever in plain
 	/// Denote true and false
 	Boolean(bool),
+	/// Mutable box, which like `Vector` has interior mutability. A box that (indirectly)
+	/// contains itself forms a cycle that reference counting can not free.
+	Box(Boxed),
+	/// Raw bytes, as produced by `serialize`
+	Bytes(Vec<u8>),
 	/// A pair of data items
 	Cell(Arc<Sourcedata>, Arc<Sourcedata>),
+	/// Unicode scalar value, written as `#\a`, `#\space`, `#\newline`, or `#\tab`
+	Char(char),
 	/// Error type
-	Error(Statement),
+	Error(EvalError),
+	/// Floating point numbers
+	Float(Float),
 	/// Function type
 	Function(Function),
 	/// Integer numbers
@@ -258,12 +549,18 @@ pub enum Coredata {
 	Macro(Macro), 
 	/// Null (an empty list)
 	Null(),
+	/// Exact fractions written as `1/2`, never with a denominator of one, see `from_rational`
+	Rational(BigRational),
 	/// String type
 	String(String),
 	/// Symbol type. Can not contain any whitespace. Is a valid Teko atom.
 	Symbol(Symbol),
 	/// Table type, holds arbitrary data
 	Table(Table),
+	/// Several values returned at once by `values`, received by `call-with-values`
+	Values(Vec<Statement>),
+	/// Mutable vector, can form cycles just like `Box`
+	Vector(Vector),
 }
 
 /// Environment used by the implementation
@@ -274,6 +571,72 @@ pub struct Env {
 	pub params: Vec<Program>,
 	/// Register used to store results of previous computations
 	result: Statement,
+	/// Print the result to `output` when `eval` finishes, off by default
+	pub verbose: bool,
+	/// Largest integer in bits that `expt` and `factorial` may produce, unlimited by default
+	pub max_integer_bits: Option<usize>,
+	/// Statements `eval` may still evaluate before it unwinds with "step limit exceeded", unlimited by default
+	pub max_steps: Option<u64>,
+	/// Scope of the function call being evaluated, `None` at the top level
+	pub scope: Option<Arc<Scope>>,
+	/// Where output builtins such as `display`, `print` and `pp` write, standard output by default.
+	/// Any writer can be used, such as a file, and `capture_output` writes into a buffer instead.
+	pub output: Box<dyn Write + Send>,
+	/// Remember which symbol statements resolve to builtins so `eval` can skip the lookup, on by default
+	pub cache_builtins: bool,
+	/// Symbol statements keyed by address, with the builtin they resolved to
+	builtin_cache: HashMap<usize, (Statement, Statement), BuildHasherDefault<AddressHasher>>,
+	/// When the environment was created, see `uptime`
+	started: Instant,
+	/// Outputs replaced by `redirect_output` or `capture_output`, innermost last, with the buffer
+	/// written instead when captured
+	redirections: Vec<(Box<dyn Write + Send>, Option<Capture>)>,
+}
+
+/// Writer appending to a buffer that `Env::release_output` gives back.
+#[derive(Clone)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Capture {
+	fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+		self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).extend_from_slice(bytes);
+		Ok(bytes.len())
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Hasher for addresses, which are already unique so hashing them does not need to be strong.
+#[derive(Default)]
+struct AddressHasher(u64);
+
+impl Hasher for AddressHasher {
+	fn finish(&self) -> u64 {
+		self.0
+	}
+	fn write(&mut self, bytes: &[u8]) {
+		for byte in bytes {
+			self.0 = (self.0.rotate_left(8) ^ u64::from(*byte)).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+		}
+	}
+	fn write_usize(&mut self, address: usize) {
+		self.0 = (address as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+	}
+}
+
+/// Bindings and parameters of an `Env` at some point, see `Env::snapshot`.
+pub struct EnvSnapshot {
+	store: HashMap<Symbol, Program>,
+	params: Vec<Program>,
+	scope: Option<Arc<Scope>>,
+}
+
+/// Number of symbol statements remembered before the builtin cache starts over.
+const BUILTIN_CACHE_CAPACITY: usize = 4096;
+
+fn is_builtin(data: &Sourcedata) -> bool {
+	matches![data.1, Coredata::Function(Function::Builtin(..)) | Coredata::Macro(Macro::Builtin(..))]
 }
 
 impl Env {
@@ -289,14 +652,37 @@ impl Env {
 			store: create_builtin_library_table(),
 			params: Vec::with_capacity(VEC_CAPACITY),
 			result: rc(Srcdata(None, Core::Null())),
+			verbose: false,
+			max_integer_bits: None,
+			max_steps: None,
+			scope: None,
+			output: Box::new(io::stdout()),
+			cache_builtins: true,
+			builtin_cache: HashMap::default(),
+			started: Instant::now(),
+			redirections: vec![],
 		}
 	}
 	// TODO Should be changed to an iter when stable
-	pub fn get_variables(&self) -> Vec<&Symbol> {
-		self.store.keys().collect()
+	/// Names of the variables visible here, those of the current scope first
+	pub fn get_variables(&self) -> Vec<Symbol> {
+		let mut variables = vec![];
+		for scope in self.scope.iter().flat_map(|scope| scope.chain()) {
+			for symbol in scope.symbols() {
+				if !variables.contains(&symbol) {
+					variables.push(symbol);
+				}
+			}
+		}
+		for symbol in self.store.keys() {
+			if !variables.contains(symbol) {
+				variables.push(symbol.clone());
+			}
+		}
+		variables
 	}
 	pub fn count_variables(&self) -> usize {
-		let mut count = 0;
+		let mut count = self.scope.as_ref().map_or(0, |scope| scope.count());
 		for i in &self.params {
 			count += i.len();
 		}
@@ -305,6 +691,39 @@ impl Env {
 		}
 		count
 	}
+	/// Monotonic time since the environment was created, as used by `clock`.
+	pub fn uptime(&self) -> Duration {
+		self.started.elapsed()
+	}
+	/// Write to `redirected` instead of `output` until the matching `restore_output`.
+	/// Redirections and captures can be nested.
+	pub fn redirect_output(&mut self, redirected: Box<dyn Write + Send>) {
+		let previous = ::std::mem::replace(&mut self.output, redirected);
+		self.redirections.push((previous, None));
+	}
+	/// Put back the output replaced by the innermost redirection and get the redirected writer.
+	pub fn restore_output(&mut self) -> Option<Box<dyn Write + Send>> {
+		let (previous, _) = self.redirections.pop()?;
+		Some(::std::mem::replace(&mut self.output, previous))
+	}
+	/// Write `output` into a buffer until the matching `release_output`.
+	pub fn capture_output(&mut self) {
+		let capture = Capture(Arc::new(Mutex::new(vec![])));
+		let previous = ::std::mem::replace(&mut self.output, Box::new(capture.clone()));
+		self.redirections.push((previous, Some(capture)));
+	}
+	/// Put back the output replaced by the innermost `capture_output` and get what was written.
+	///
+	/// Gives `None` without changing the output if the innermost redirection is not a capture.
+	pub fn release_output(&mut self) -> Option<Vec<u8>> {
+		let capture = match self.redirections.last() {
+			Some(&(_, Some(ref capture))) => capture.clone(),
+			_ => return None,
+		};
+		self.restore_output();
+		let written = capture.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+		Some(written)
+	}
 	pub fn set_result(&mut self, value: Statement) {
 		self.result = value;
 	}
@@ -317,33 +736,111 @@ impl Env {
 	pub fn deparamize(&mut self) {
 		self.params.pop();
 	}
+	/// Check if `symbol` is bound in the current scope or at the top level
 	pub fn does_variable_exist(&self, symbol: &Symbol) -> bool {
-		self.store.contains_key(symbol)
+		self.scope.as_ref().is_some_and(|scope| scope.get(symbol).is_some()) || self.store.contains_key(symbol)
 	}
-	pub fn get(&self, symbol: &Symbol) -> Option<&Arc<Sourcedata>> {
-		if let Some(value) = self.store.get(symbol) {
-			value.last()
+	/// Get the value of `symbol` in the current scope, or at the top level if it is in no scope
+	pub fn get(&self, symbol: &Symbol) -> Option<Statement> {
+		if let Some(value) = self.scope.as_ref().and_then(|scope| scope.get(symbol)) {
+			Some(value)
+		} else if let Some(value) = self.store.get(symbol) {
+			value.last().cloned()
 		} else {
 			None
 		}
 	}
+	/// Get the builtin that a symbol statement resolved to, if it is still bound to it.
+	///
+	/// Statements are remembered by `cache_builtin`. The cache is emptied whenever a top level
+	/// builtin binding is shadowed or removed, which covers `set!` and `register_builtin`, and is
+	/// not used for symbols bound in the current scope, such as parameters.
+	pub fn cached_builtin(&self, statement: &Statement) -> Option<Statement> {
+		if !self.cache_builtins {
+			return None;
+		}
+		if let (Some(scope), Coredata::Symbol(ref symbol)) = (&self.scope, &statement.1) {
+			if scope.get(symbol).is_some() {
+				return None;
+			}
+		}
+		self.builtin_cache.get(&(&**statement as *const Sourcedata as usize)).map(|entry| entry.1.clone())
+	}
+	/// Remember that a symbol statement resolved to `value`, if `value` is a builtin.
+	pub fn cache_builtin(&mut self, statement: &Statement, value: &Statement) {
+		if !self.cache_builtins || !is_builtin(value) {
+			return;
+		}
+		if self.builtin_cache.len() >= BUILTIN_CACHE_CAPACITY {
+			self.builtin_cache.clear();
+		}
+		// The statement is kept alive so its address can not be reused by other data
+		let key = &**statement as *const Sourcedata as usize;
+		self.builtin_cache.insert(key, (statement.clone(), value.clone()));
+	}
+	/// Capture the bindings and parameters so they can be rolled back with `restore`.
+	///
+	/// Values are shared with the environment, so changes made to boxes, vectors and the
+	/// variables of function calls after the snapshot are not undone by `restore`.
+	pub fn snapshot(&self) -> EnvSnapshot {
+		EnvSnapshot {
+			store: self.store.clone(),
+			params: self.params.clone(),
+			scope: self.scope.clone(),
+		}
+	}
+	/// Roll the bindings and parameters back to a snapshot, discarding anything bound since.
+	pub fn restore(&mut self, snapshot: EnvSnapshot) {
+		self.store = snapshot.store;
+		self.params = snapshot.params;
+		self.scope = snapshot.scope;
+		self.builtin_cache.clear();
+	}
 	pub fn push(&mut self, symbol: &Symbol, value: Arc<Sourcedata>) {
-		if self.does_variable_exist(symbol) {
-			self.store.get_mut(symbol).unwrap().push(value);
+		if let Some(values) = self.store.get_mut(symbol) {
+			if values.last().is_some_and(|top| is_builtin(top)) {
+				self.builtin_cache.clear();
+			}
+			values.push(value);
 		} else {
 			self.store.insert(symbol.clone(), vec![value]);
 		}
 	}
+	/// Bind a builtin function to `name` like `define` would, shadowing any existing binding.
+	///
+	/// The arguments are in `params.last()`; a builtin sets its result with `set_result`.
+	pub fn register_builtin(&mut self, name: &str, transfer: Transfer) {
+		use utilities::rcs;
+		let function = rcs(Coredata::Function(Function::Builtin(transfer, name.into(), None)));
+		self.push(&Symbol::from(name), function);
+	}
+	/// Replace the value of the innermost variable called `symbol`, at the top level if it is in no scope
 	pub fn set(&mut self, symbol: &Symbol, value: Statement) {
+		if let Some(ref scope) = self.scope {
+			if scope.set(symbol, value.clone()) {
+				return;
+			}
+		}
 		self.pop(symbol);
 		self.push(symbol, value);
 	}
+	/// Bind `symbol` in the current scope, or push it at the top level if there is none
+	pub fn bind(&mut self, symbol: &Symbol, value: Statement) {
+		if let Some(ref scope) = self.scope {
+			scope.insert(symbol.clone(), value);
+		} else {
+			self.push(symbol, value);
+		}
+	}
 	pub fn pop(&mut self, symbol: &Symbol) -> Option<Arc<Sourcedata>> {
 		let (result, empty) = if let Some(ref mut entry) = self.store.get_mut(symbol) {
 			(entry.pop(), entry.is_empty())
 		} else {
 			(None, false)
 		};
+		if result.as_ref().is_some_and(|value| is_builtin(value)) {
+			self.builtin_cache.clear();
+		}
 		if empty {
 			self.store.remove(symbol);
 		}
@@ -366,6 +863,10 @@ pub struct ParseState {
 	pub stack: Vec<Program>,
 	/// Error container, set to Some if the parser fails
 	pub error: Option<String>,
+	/// Columns a tab advances to the next multiple of, 1 counts a tab as a single column
+	pub tab_width: usize,
+	/// Set after a carriage return so a following line feed does not start another line
+	pub after_carriage_return: bool,
 }
 
 /// Information about the source of data.
@@ -375,6 +876,10 @@ pub struct Source {
 	pub line: usize,
 	/// Column number of the input, starts at 1
 	pub column: usize,
+	/// Byte offset into the input, starts at 0
+	pub offset: usize,
+	/// Number of bytes of the input spanned by the datum, 0 if the datum was not parsed
+	pub length: usize,
 	/// Free-form string describing the source ("tty" from terminal, filename from file,..)
 	pub source: String, // Change to Rc<String>?
 }
@@ -22,8 +22,9 @@ use data_structures::Sourcedata as Srcdata;
 use data_structures::Coredata as Core;
 use data_structures::Commands as Cmds;
 use utilities::*;
+use print;
 
-use num::BigInt;
+use std::{fs, io::{self, Write}};
 
 /// Evals a program with a given environment.
 ///
@@ -31,6 +32,15 @@ use num::BigInt;
 /// is stored using `env.set_result`. This function is mainly used to evaluate a program in some
 /// environment context.
 ///
+/// The last element of `program` is evaluated first. The parser already returns programs in
+/// this reversed order, so the output of `parse_string` or `parse_file` evaluates from the
+/// first form to the last without any further reversing. The result is the value of the last
+/// form.
+///
+/// Apart from output builtins, nothing is written to `env.output` unless `env.verbose` is set,
+/// in which case the result is printed in the list notation of `print` once the program has
+/// been evaluated.
+///
 /// ```
 /// extern crate teko;
 /// extern crate num_traits;
@@ -72,22 +82,31 @@ pub fn eval(mut program: Program, mut env: Env) -> Env {
 		// A change to the variable a from expression b or c DOES NOT MATTER, because
 		// call(a) stores the actual function, not a variable referencing the function!
 		//
-		// deparam removes the references from the store. Now the store is actually a
-		// Map<String, Vec<_>>, where _ is any data. So when we call a function, we push
-		// a variable onto the Vec in the store, and when we do a deparam we remove it.
+		// deparam ends the call. The parameters are bound in a new scope inside the scope
+		// the function was made in, which becomes the current scope while the code runs, and
+		// deparam puts back the scope of the caller. Variables found in no scope are looked
+		// up in the store, which is a Map<String, Vec<_>> of the top level definitions.
 		//
 		// TCO is implemented by looking at the top when expanding a call:
 		// suppose a calls (d b e) at its tail:
 		//
 		// b param e param call(d) deparam(b e) deparam(b c)
 		//
-		// Note how the deparams can be merged:
+		// Nothing is left to do in the scope of a once d is called, so the first deparam is
+		// not needed:
 		//
-		// b param e param call(d) deparam(b c e)
+		// b param e param call(d) deparam(b c)
 		//
-		// This is the method by which TCO is implemented. Note that merging ensures that
-		// the correct number of variables are popped from the store.
+		// This is the method by which TCO is implemented. The deparam already on the stack
+		// returns to the caller of a, so tail calls use no more stack however many follow.
 		let src = &top.0;
+		if let Some(steps) = env.max_steps {
+			if steps == 0 {
+				err(src, &Some((src.clone(), "step limit exceeded".into())), &mut program, &mut env);
+				continue;
+			}
+			env.max_steps = Some(steps - 1);
+		}
 		match top.1 {
 			// This is where a call of a function happens, remember (a b c) => b param c param call(a) deparam(b c)
 			// Right now we're at the call stage: call(a) deparam(b c)
@@ -96,27 +115,35 @@ pub fn eval(mut program: Program, mut env: Env) -> Env {
 				// This nesting should not be necessary, make call hold valid data!
 				let source = &statement.0;
 				match statement.1 {
-					Core::Function(Function::Builtin(ref transfer, ..)) => {
-						let maybe_error = transfer(&mut program, &mut env);
+					Core::Function(Function::Builtin(ref transfer, ref name, arity)) => {
+						let count = env.params.last().map_or(0, |args| args.len());
+						let maybe_error = match arity {
+							Some((low, high)) if count < low || count > high => {
+								Some((None, format!["{} for {}", arity_mismatch(low, high, count), name]))
+							}
+							_ => transfer(&mut program, &mut env),
+						};
 						env.deparamize();
 						err(src, &maybe_error, &mut program, &mut env);
 					}
-					Core::Function(Function::Library(ref parameters, ref transfer)) => {
+					Core::Function(Function::Library(ref parameters, ref transfer, ref captures)) => {
 						if let Some(args) = env.params.pop() {
 							if args.len() != parameters.len() {
 								let params = parameters.len();
-								err(src, &Some((source.clone(),
-									arity_mismatch(params, params, args.len()))), &mut program, &mut env);
-							} else {
-								// TODO perhaps make this part of optimizer
-								let cmd = Cmds::Deparize(optimize_tail_call(&mut program, &mut env, parameters));
-								ppush![src, Core::Internal(cmd)];
-								// END (of todo)
-								for (counter, parameter) in parameters.iter().enumerate() {
-									env.push(parameter, args[counter].clone());
+								let mut message = arity_mismatch(params, params, args.len());
+								if !parameters.is_empty() {
+									let names: Vec<&str> = parameters.iter().map(|parameter| parameter.into()).collect();
+									message = format!["{} for parameters {}", message, names.join(" ")];
 								}
+								err(src, &Some((source.clone(), message)), &mut program, &mut env);
+							} else {
+								let variables = parameters.iter().cloned().zip(args).collect();
+								enter_scope(&mut program, &mut env, src, Scope::new(variables, captures.clone()));
 								program.extend(transfer.iter().cloned());
 							}
+						} else {
+							err(src, &Some((source.clone(), "parameter stack is empty".into())),
+								&mut program, &mut env);
 						}
 					}
 					// Can't actually happen, prepare checks it
@@ -132,8 +159,8 @@ pub fn eval(mut program: Program, mut env: Env) -> Env {
 					}
 				}
 			}
-			Core::Internal(Cmds::Deparize(ref arguments)) => {
-				pop_parameters(&mut program, &mut env, arguments);
+			Core::Internal(Cmds::Deparize(ref frame)) => {
+				env.scope = frame.scope.clone();
 			}
 			Core::Internal(Cmds::Eval) => {
 				program.push(env.get_result());
@@ -169,12 +196,8 @@ pub fn eval(mut program: Program, mut env: Env) -> Env {
 					}
 					Core::Macro(Macro::Library(ref bound, ref code)) => {
 						ppush![None, Core::Internal(Cmds::Eval)];
-						let command = optimize_tail_call(&mut program, &mut env, &[bound.clone()]);
-						env.push(bound, arguments.clone());
-						ppush![
-							src,
-							Core::Internal(Cmds::Deparize(command)),
-						];
+						let variables = vec![(bound.clone(), arguments.clone())];
+						enter_scope(&mut program, &mut env, src, Scope::new(variables, None));
 						program.extend(code.iter().cloned());
 					}
 					_ => {
@@ -199,19 +222,28 @@ pub fn eval(mut program: Program, mut env: Env) -> Env {
 			}
 			Core::Symbol(ref symbol) => {
 				let string: &str = symbol.into();
-				if let Some(number) = BigInt::parse_bytes(string.as_bytes(), 10) {
-					env.set_result(rc(Srcdata(src.clone(), Core::Integer(number))));
-				} else if string == "true" {
+				if let Some(builtin) = env.cached_builtin(&top) {
+					env.set_result(builtin);
+				} else if let Some(number) = parse_number(string, 10) {
+					env.set_result(rc(Srcdata(src.clone(), number)));
+				} else if let Some(character) = parse_char(string) {
+					env.set_result(rc(Srcdata(src.clone(), Core::Char(character))));
+				} else if string == "true" || string == "#t" || string == "#true" {
 					env.set_result(true_obj.clone());
-				} else if string == "false" {
+				} else if string == "false" || string == "#f" || string == "#false" {
 					env.set_result(false_obj.clone());
 				} else {
-					let (error, result) = if let Some(value) = env.get(&Symbol::from(string)) {
-						(None, Some(value.clone()))
+					let (error, result) = if let Some(value) = env.get(symbol) {
+						if let Core::Internal(Cmds::Uninitialized) = value.1 {
+							(Some((src.clone(), format!["accessed before initialization: {}", string])), None)
+						} else {
+							(None, Some(value))
+						}
 					} else {
 						(Some((src.clone(), not_found(string))), None)
 					};
 					if let Some(result) = result {
+						env.cache_builtin(&top, &result);
 						env.set_result(result);
 					} else {
 						err(src, &error, &mut program, &mut env);
@@ -223,6 +255,10 @@ pub fn eval(mut program: Program, mut env: Env) -> Env {
 			}
 		}
 	}
+	if env.verbose {
+		let result = print::to_string(&env.get_result());
+		let _ = writeln!(env.output, "{}", result).and_then(|_| env.output.flush());
+	}
 	env
 }
 
@@ -263,6 +299,151 @@ pub fn interpret(program: Program) -> Env {
 	eval(program, env)
 }
 
+/// Parse and evaluate code with the standard library and get the result.
+///
+/// Unlike `interpret` this does not leave errors in the environment: a parse error, or a
+/// program evaluating to an error, is returned as `Err` with the written error.
+///
+/// ```
+/// extern crate teko;
+/// let result = teko::interpret::eval_str("(+ 1 2)").unwrap();
+/// assert_eq![result.1, teko::data_structures::Coredata::Integer(3.into())];
+/// assert![teko::interpret::eval_str("(head ())").is_err()];
+/// ```
+pub fn eval_str(code: &str) -> Result<Statement, String> {
+	use parse::parse_string;
+	let program = parse_string(code).map_err(|state| {
+		format!["parse error at {}: {}", state.current_read_position, state.error.unwrap_or_default()]
+	})?;
+	let result = interpret(program).get_result();
+	if let Core::Error(..) = result.1 {
+		Err(format!["{}", result])
+	} else {
+		Ok(result)
+	}
+}
+
+/// Save the bindings made on top of the standard library, so `load_environment` can restore them.
+///
+/// Only the innermost value of each variable is saved, bindings still holding their builtin
+/// are left out since a fresh environment has them. The file is Teko code of
+/// `(define name value)` forms, or `set!` for a builtin that was replaced, with the values
+/// written so that evaluating them gives back equal data. Data shared between variables is
+/// written once per variable, so it is no longer shared after loading.
+///
+/// Some values can not be read back from their written form: functions that keep local
+/// variables, raised errors, bytes, and data that contains itself. Saving any of them fails
+/// with `InvalidData` before the file is written.
+///
+/// ```
+/// extern crate teko;
+/// let program = teko::parse::parse_string("(define x 3)").ok().unwrap();
+/// let env = teko::interpret::interpret(program);
+/// let path = std::env::temp_dir().join(format!["teko-save-environment-doctest-{}.tko", std::process::id()]);
+/// teko::interpret::save_environment(&env, path.to_str().unwrap()).unwrap();
+/// let env = teko::interpret::load_environment(path.to_str().unwrap()).unwrap();
+/// std::fs::remove_file(path).unwrap();
+/// assert![env.does_variable_exist(&"x".into())];
+/// ```
+pub fn save_environment(env: &Env, path: &str) -> io::Result<()> {
+	use builtins::create_builtin_library_table;
+	let builtins = create_builtin_library_table();
+	let mut variables = env.get_variables();
+	variables.sort();
+	let mut code = String::new();
+	for variable in variables {
+		let value = match env.get(&variable) {
+			Some(value) => value,
+			None => continue,
+		};
+		let name: &str = (&variable).into();
+		let builtin = builtins.get(&variable).and_then(|values| values.last());
+		if builtin == Some(&value) {
+			continue;
+		}
+		if let Some(reason) = unreadable(&value) {
+			let message = format!["unable to save {}: {} can not be read back", name, reason];
+			return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+		}
+		match builtin {
+			Some(_) => code.push_str(&format!["(set! {} {})\n", name, value]),
+			None => code.push_str(&format!["(define {} {})\n", name, value]),
+		}
+	}
+	fs::write(path, code)
+}
+
+/// Find what in a value would not be read back from its written form, see `save_environment`.
+fn unreadable(value: &Statement) -> Option<&'static str> {
+	enum Visit {
+		Data(Statement),
+		Leave(usize),
+	}
+	// Addresses of the boxes and vectors being visited, one that contains itself is a cycle
+	let mut open = std::collections::HashSet::new();
+	let mut pending = vec![Visit::Data(value.clone())];
+	while let Some(visit) = pending.pop() {
+		let data = match visit {
+			Visit::Data(data) => data,
+			Visit::Leave(address) => {
+				open.remove(&address);
+				continue;
+			}
+		};
+		let (address, children) = match data.1 {
+			Core::Box(ref boxed) => (boxed as *const _ as usize, vec![boxed.get()]),
+			Core::Vector(ref vector) => (vector as *const _ as usize, vector.to_vec()),
+			Core::Cell(ref head, ref tail) => {
+				pending.push(Visit::Data(tail.clone()));
+				pending.push(Visit::Data(head.clone()));
+				continue;
+			}
+			Core::Table(ref table) => {
+				for key in table.keys() {
+					pending.extend(table.get(&key).map(Visit::Data));
+				}
+				continue;
+			}
+			Core::Error(ref error) => {
+				if data.0.is_some() {
+					return Some("a raised error");
+				}
+				pending.push(Visit::Data(error.data.clone()));
+				pending.extend(error.cause.clone().map(Visit::Data));
+				continue;
+			}
+			Core::Function(Function::Library(_, _, Some(_))) => {
+				return Some("a function that keeps local variables");
+			}
+			Core::Bytes(..) => return Some("bytes"),
+			Core::Internal(..) => return Some("internal data"),
+			_ => continue,
+		};
+		if !open.insert(address) {
+			return Some("data that contains itself");
+		}
+		pending.push(Visit::Leave(address));
+		pending.extend(children.into_iter().map(Visit::Data));
+	}
+	None
+}
+
+/// Create a standard environment with the bindings saved by `save_environment`.
+///
+/// Gives the written error if the file can not be parsed or evaluating it unwinds.
+pub fn load_environment(path: &str) -> Result<Env, String> {
+	use parse::parse_file;
+	let program = parse_file(path).map_err(|state| {
+		format!["parse error at {}: {}", state.current_read_position, state.error.unwrap_or_default()]
+	})?;
+	let env = interpret(program);
+	if let Core::Error(..) = env.get_result().1 {
+		Err(format!["{}", env.get_result()])
+	} else {
+		Ok(env)
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -272,4 +453,28 @@ mod tests {
 		let p = parse_file("examples/basic.tko").ok().unwrap();
 		interpret(p);
 	}
+	#[test]
+	fn eval_str_results() {
+		assert_eq![eval_str("(+ 1 2)").unwrap().1, Coredata::Integer(3.into())];
+		assert![eval_str("(").unwrap_err().starts_with("parse error at ")];
+		assert![eval_str("(+ 1 (@ a))").unwrap_err().starts_with("(make-error ")];
+	}
+	#[test]
+	fn forms_evaluate_in_source_order() {
+		use parse::parse_string;
+		let program = parse_string("(define x 1) (set! x (+ x 1)) (set! x (* x 3)) x").ok().unwrap();
+		let env = interpret(program);
+		assert_eq![env.get_result().1, Coredata::Integer(6.into())];
+	}
+	#[test]
+	fn evaluating_again_does_not_reverse() {
+		use parse::parse_string;
+		let program = parse_string("(+ 1 2 4) (+ 1 2)").ok().unwrap();
+		let env = eval(program.clone(), initialize_environment_with_standard_library());
+		assert_eq![env.get_result().1, Coredata::Integer(3.into())];
+		let env = eval(vec![], env);
+		assert_eq![env.get_result().1, Coredata::Integer(3.into())];
+		let env = eval(program, env);
+		assert_eq![env.get_result().1, Coredata::Integer(3.into())];
+	}
 }
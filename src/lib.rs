@@ -2,18 +2,21 @@
 //!
 //! # About #
 //! This implementation provides parsing and evaluation utilities of the Teko programming language.
-//! Teko belongs to the family of **Lisp-1** languages and features **dynamic scoping**,
+//! Teko belongs to the family of **Lisp-1** languages and features **lexical scoping**
+//! (functions keep the variables of the scope they are made in),
 //! **first-class macros**, and **tail call optimization**.
 //!
 //! Teko is made to be used as an everyday scripting language. The language was designed to be
 //! easy to implement yet useful. Comparing Teko to other Lisps reveals the core motivation:
 //! to implement a super-minimal Lisp capable of being a fully fledged programming language.
 //!
-//! Teko has the property that it's **strictly evaluated** yet lacks **interior mutability**.
+//! Teko has the property that it's **strictly evaluated** yet lacks **interior mutability**
+//! (except for explicit boxes and vectors, see `make-box` and `vector-set!`).
 //! This allows
 //! the implementation to opt for **reference counted** garbage collection - because cycles can't
-//! be created - which is desirable in real-time applications as it doesn't cause unforeseen
-//! pauses in execution.
+//! be created unless a box or vector is made to contain itself, or a function is bound in the
+//! scope it is made in as `letrec` and named `let` do - which is desirable in real-time
+//! applications as it doesn't cause unforeseen pauses in execution.
 //!
 //! # Why Lisp? #
 //! Here's my favorite excerpt that words it perfectly, from
@@ -100,7 +103,7 @@
 // ✓ Improve error unwinding (do we need to pop params?), add formal errors
 // ✓ transfer -> Option<String> for consistent error handling
 // ✗ ` quasiquote                            - Can be built from primitives
-// ✗ Test different TCO strategies (HashSet, sorted Vec,..)  - Not important
+// ✓ Test different TCO strategies (HashSet, sorted Vec,..)
 // ✗ Implement powers for numbers                            - Implemented using primitives
 // ✓ <, >, =, <=, >=, != number comparison   - Only < and == builtin, others derived
 // ✓ Boolean not, and, or
@@ -132,8 +135,8 @@
 //   Pretty printing
 //   Create extension interface (not sure if feasible atm)
 //   Channels
-//   Table data type
-//   Rational parsing + promotion
+// ✓ Table data type
+// ✓ Rational parsing + promotion
 //   Complex parsing + promotion
 //
 // //////////////////////////////////////////////////////////
@@ -147,10 +150,13 @@ mod macros;
 externs![num time];
 
 pubmods![
+	analysis
 	builtins
 	data_structures
 	interpret
 	parse
+	print
+	repl
 	user
 	utilities
 ];
@@ -1,10 +1,10 @@
 /// Macro to construct the library table
 macro_rules! construct_builtins {
-	({$($c:expr => $x:expr),*,} $($t:ident: $e:expr => $i:ident),*,) => {
+	({$($c:expr => $x:expr),*,} $($t:ident: $e:expr => $i:ident $(($low:expr => $high:expr))?),*,) => {
 		{
 			let mut functions_and_macros : HashMap<Symbol, Program> = [
 				$(
-					($e.into(), vec![Arc::new(Sourcedata(None, Coredata::$t($t::Builtin($i, $e.into()))))])
+					($e.into(), vec![Arc::new(Sourcedata(None, Coredata::$t(builtin_entry!($t, $i, $e $(, $low, $high)?))))])
 				),*
 			].iter().cloned().collect();
 			let constants : HashMap<Symbol, Program> = [
@@ -18,6 +18,13 @@ macro_rules! construct_builtins {
 	};
 }
 
+/// Builds the builtin of one `construct_builtins` entry, a function may have an arity for `eval` to check
+macro_rules! builtin_entry {
+	(Function, $i:ident, $e:expr) => { Function::Builtin($i, $e.into(), None) };
+	(Function, $i:ident, $e:expr, $low:expr, $high:expr) => { Function::Builtin($i, $e.into(), Some(($low, $high))) };
+	(Macro, $i:ident, $e:expr) => { Macro::Builtin($i, $e.into()) };
+}
+
 /// Allows us to specify externs as a list instead of tediously repeating extern crate
 macro_rules! externs {
 	($($i:ident)*) => {
@@ -6,8 +6,10 @@
 //! extern crate teko;
 //! assert![teko::parse::parse_string("(+ 1 2 3) (' a (b) c)").is_ok()];
 //! ```
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, ErrorKind, Read};
+use std::str;
 use std::sync::Arc;
 
 use data_structures::*;
@@ -44,8 +46,20 @@ use data_structures::*;
 // Finally, a right parenthesis moves the last substack into the end of the second to last substack:
 // ("", [["a"], ["b", "c"]]) :):-> ("", [["a", ["b", "c"]]])
 //
+// The token "#" directly followed by a left parenthesis starts a substack containing
+// the symbol "vector", so #(a b) is read as (vector a b).
+//
+// The character directly following "#\" always belongs to the token, so
+// #\( and #\) are tokens rather than parentheses. Such tokens are character
+// literals, evaluated the same way numbers are.
+//
+// A lone "." before the last element of a substack makes that element the tail of the
+// list instead of Null, so (a . b) is a single cell. A "." anywhere else in a list is an
+// error, outside of lists it is an ordinary symbol.
+//
 // Additionally, the parser keeps track of the location of the parsed code in the
-// source using line and column numbers.
+// source using line and column numbers, as well as byte offsets. Every datum records
+// the number of bytes it spans, a list from its opening to its closing parenthesis.
 //
 // ## Error Handling ##
 //
@@ -58,25 +72,17 @@ use data_structures::*;
 
 /// Parse a `File` into a `Program`
 ///
-/// Utility function to easily parse a `File`.
+/// Utility function to easily parse a `File`. If the file can not be read the error of the
+/// state names the file, as in `unable to read a.tko: entity not found`.
 pub fn parse_file(filename: &str) -> Result<Program, ParseState> {
-	let mut file = match File::open(filename) {
-		Ok(f) => f,
-		Err(e) => {
-			let mut state = ParseState::from(filename);
-			state.error = Some(e.to_string());
-			return Err(state);
-		}
+	let unreadable = |e: io::Error| {
+		let mut state = ParseState::from(filename);
+		state.error = Some(format!["unable to read {}: {}", filename, e.kind()]);
+		state
 	};
+	let mut file = File::open(filename).map_err(unreadable)?;
 	let mut contents = String::new();
-	match file.read_to_string(&mut contents) {
-		Ok(_) => {},
-		Err(e) => {
-			let mut state = ParseState::from(filename);
-			state.error = Some(e.to_string());
-			return Err(state);
-		}
-	}
+	file.read_to_string(&mut contents).map_err(unreadable)?;
 	parse_string_with_state(&contents, ParseState::from(filename))
 }
 
@@ -97,6 +103,109 @@ pub fn parse_string(string: &str) -> Result<Program, ParseState> {
 
 // //////////////////////////////////////////////////////////
 
+// //////////////////////////////////////////////////////////
+
+/// Parse top-level forms one at a time from anything that implements `Read`
+///
+/// Input is read in small chunks only when another form is needed, so the whole input is
+/// never buffered. A form ending in a closing parenthesis is yielded as soon as that
+/// parenthesis is read, while a top-level symbol has to be followed by whitespace, a
+/// parenthesis, or the end of input. Unmatched opening parentheses are only reported once
+/// the input ends. After an error the iterator yields nothing more.
+///
+/// ```
+/// extern crate teko;
+/// let forms: Vec<_> = teko::parse::parse_reader("(+ 1 2) (a b)".as_bytes()).collect();
+/// assert_eq![forms.len(), 2];
+/// assert![forms.iter().all(|form| form.is_ok())];
+/// ```
+pub fn parse_reader<R: Read>(input: R) -> Forms<R> {
+	Forms {
+		input,
+		state: ParseState::default(),
+		bytes: vec![],
+		forms: VecDeque::new(),
+		done: false,
+	}
+}
+
+/// Iterator over the top-level forms of a `Read`, created by `parse_reader`
+pub struct Forms<R: Read> {
+	input: R,
+	state: ParseState,
+	// Bytes read but not yet decoded, at most an incomplete UTF-8 sequence
+	bytes: Vec<u8>,
+	forms: VecDeque<Statement>,
+	done: bool,
+}
+
+impl<R: Read> Forms<R> {
+	fn fail(&mut self, message: &str) -> Option<Result<Statement, ParseState>> {
+		self.done = true;
+		Some(Err(set_error(&mut self.state, message)))
+	}
+	// Parse the decodable bytes, returns the parse state on error
+	fn feed(&mut self) -> Option<ParseState> {
+		let valid = match str::from_utf8(&self.bytes) {
+			Ok(_) => self.bytes.len(),
+			Err(error) => {
+				if error.error_len().is_some() {
+					return Some(set_error(&mut self.state, "Invalid UTF-8"));
+				}
+				error.valid_up_to()
+			}
+		};
+		let text = str::from_utf8(&self.bytes[..valid]).unwrap().to_string();
+		self.bytes.drain(..valid);
+		for character in text.chars() {
+			if let Err(state) = parse_character(character, &mut self.state) {
+				return Some(state);
+			}
+			if self.state.stack.len() == 1 {
+				self.forms.extend(self.state.stack[0].drain(..));
+			}
+		}
+		None
+	}
+}
+
+impl<R: Read> Iterator for Forms<R> {
+	type Item = Result<Statement, ParseState>;
+	fn next(&mut self) -> Option<Self::Item> {
+		let mut chunk = [0; 1024];
+		loop {
+			if let Some(form) = self.forms.pop_front() {
+				return Some(Ok(form));
+			} else if self.done {
+				return None;
+			}
+			match self.input.read(&mut chunk) {
+				Ok(0) => {
+					if !self.bytes.is_empty() {
+						return self.fail("Invalid UTF-8");
+					}
+					self.done = true;
+					match finish_parsing_characters(self.state.clone()) {
+						Ok(program) => self.forms.extend(program.into_iter().rev()),
+						Err(state) => return Some(Err(state)),
+					}
+				}
+				Ok(count) => {
+					self.bytes.extend(&chunk[..count]);
+					if let Some(state) = self.feed() {
+						self.done = true;
+						return Some(Err(state));
+					}
+				}
+				Err(ref error) if error.kind() == ErrorKind::Interrupted => {}
+				Err(error) => {
+					return self.fail(&error.to_string());
+				}
+			}
+		}
+	}
+}
+
 fn parse_string_with_state(string: &str, mut state: ParseState) -> Result<Program, ParseState> {
 	for character in string.chars() {
 		parse_character(character, &mut state)?;
@@ -113,6 +222,8 @@ fn parse_string_with_state(string: &str, mut state: ParseState) -> Result<Progra
 ///
 /// This function should be called after a series of calls to `parse_character`.
 /// It takes a `state` and finalizes it into a program.
+/// The forms are in the order `eval` expects them, the last element being the first form,
+/// so popping from the program yields the forms from left to right.
 /// See `parse_character` for an example.
 ///
 /// ```
@@ -145,6 +256,22 @@ pub fn is_ready_to_finish(state: &ParseState) -> bool {
 		!state.stack.last().unwrap().is_empty()
 }
 
+/// Check if the parser is inside a form and needs more input to complete it.
+///
+/// Unlike a parse error this is not a problem when reading interactively: the form continues
+/// on the next line. Only an unmatched opening parenthesis makes a form incomplete, since a
+/// token at the top level ends with the input.
+///
+/// ```
+/// extern crate teko;
+/// let mut state = teko::data_structures::ParseState::default();
+/// "(+ 1\n".chars().for_each(|ch| teko::parse::parse_character(ch, &mut state).unwrap());
+/// assert![teko::parse::is_incomplete(&state)];
+/// ```
+pub fn is_incomplete(state: &ParseState) -> bool {
+	!state.unmatched_opening_parentheses.is_empty()
+}
+
 /// Check if the parser is empty.
 pub fn is_empty(state: &ParseState) -> bool {
 	state.stack.last().unwrap().is_empty()
@@ -172,17 +299,30 @@ pub fn parse_character(character: char, state: &mut ParseState) -> Result<(), Pa
 // Internal                                                //
 // //////////////////////////////////////////////////////////
 
+/// Advance the read position past a character.
+///
+/// `\n`, `\r`, and `\r\n` each end a line. A tab advances the column to the next multiple of
+/// `tab_width` (plus one, as columns start at 1).
 fn count_characters_and_lines(character: char, state: &mut ParseState) {
-	if character == '\n' {
-		state.current_read_position.line += 1;
-		state.current_read_position.column = 1;
+	let position = &mut state.current_read_position;
+	position.offset += character.len_utf8();
+	if character == '\n' && state.after_carriage_return {
+	} else if character == '\n' || character == '\r' {
+		position.line += 1;
+		position.column = 1;
+	} else if character == '\t' && state.tab_width > 1 {
+		position.column = (position.column - 1) / state.tab_width * state.tab_width + state.tab_width + 1;
 	} else {
-		state.current_read_position.column += 1;
+		position.column += 1;
 	}
+	state.after_carriage_return = character == '\r';
 }
 
 fn parse_internal(character: char, state: &mut ParseState) -> Result<(), ParseState> {
-	if character.is_whitespace() {
+	if state.token == "#\\" {
+		// #\( and #\) are characters, and so is #\ followed by whitespace
+		otherwise(character, state);
+	} else if character.is_whitespace() {
 		whitespace(state);
 	} else if character == '(' {
 		left_parenthesis(state);
@@ -201,6 +341,16 @@ fn whitespace(state: &mut ParseState) {
 }
 
 fn left_parenthesis(state: &mut ParseState) {
+	if state.token == "#" {
+		// #(a b c) is read as (vector a b c)
+		clear_token(state);
+		copy_current_read_position_to_unmatched_opening_parentheses(state);
+		let source = state.start_of_current_lexeme.clone();
+		state.stack.push(vec![
+			Arc::new(Sourcedata(Some(source), Coredata::Symbol(Symbol::from("vector")))),
+		]);
+		return;
+	}
 	move_token_to_stack_if_nonempty(state);
 	copy_current_read_position_to_unmatched_opening_parentheses(state);
 	state.stack.push(vec![]);
@@ -208,18 +358,38 @@ fn left_parenthesis(state: &mut ParseState) {
 
 fn right_parenthesis(state: &mut ParseState) -> Result<(), ParseState> {
 	move_token_to_stack_if_nonempty(state);
-	let source = pop_previous_opening_parenthesis(state)?;
+	let mut source = pop_previous_opening_parenthesis(state)?;
+	source.length = state.current_read_position.offset + 1 - source.offset;
 	let top = if let Some(mut top) = state.stack.pop() {
 		top
 	} else {
 		return Err(state.clone());
 	};
-	let mut active = Arc::new(Sourcedata(Some(source), Coredata::Null()));
-	for top in top.iter().rev() {
-		active = Arc::new(Sourcedata(
-			top.0.clone(),
-			Coredata::Cell(top.clone(), active),
-		));
+	let is_dot = |data: &Statement| {
+		if let Coredata::Symbol(ref symbol) = data.1 {
+			Into::<&str>::into(symbol) == "."
+		} else {
+			false
+		}
+	};
+	// A dot inside the string form (" a . b) is text, not a dotted pair
+	let is_string = top.first().is_some_and(|head| {
+		matches![head.1, Coredata::Symbol(ref symbol) if Into::<&str>::into(symbol) == "\""]
+	});
+	let (top, mut active) = match (top.iter().position(is_dot), top.iter().rposition(is_dot)) {
+		(None, _) => (&top[..], Arc::new(Sourcedata(Some(source.clone()), Coredata::Null()))),
+		_ if is_string => (&top[..], Arc::new(Sourcedata(Some(source.clone()), Coredata::Null()))),
+		(Some(first), Some(last)) if first == last && first > 0 && first + 2 == top.len() => {
+			(&top[..first], top[first + 1].clone())
+		}
+		_ => {
+			return Err(set_error(state, "Misplaced dot in list"));
+		}
+	};
+	for (index, element) in top.iter().enumerate().rev() {
+		// The list itself spans from the opening to the closing parenthesis
+		let source = if index == 0 { Some(source.clone()) } else { element.0.clone() };
+		active = Arc::new(Sourcedata(source, Coredata::Cell(element.clone(), active)));
 	}
 
 	if let Some(ref mut stack) = state.stack.last_mut() {
@@ -240,7 +410,8 @@ fn otherwise(character: char, state: &mut ParseState) {
 
 fn move_token_to_stack_if_nonempty(state: &mut ParseState) {
 	if !state.token.is_empty() {
-		let currlex = state.start_of_current_lexeme.clone();
+		let mut currlex = state.start_of_current_lexeme.clone();
+		currlex.length = state.current_read_position.offset - currlex.offset;
 		let currtok = state.token.clone();
 		if let Some(ref mut stack) = state.stack.last_mut() {
 			stack.push(Arc::new(
@@ -343,6 +514,106 @@ mod tests {
 			"(test1 (test2)",
 			"(((((((()))))))",
 			"(((((()))))))",
+			"(.)",
+			"(. 1)",
+			"(1 .)",
+			"(1 . 2 3)",
+			"(1 . . 2)",
 		];
 	}
+
+	#[test]
+	fn reader_in_chunks() {
+		let whole = "(+ 1 2) (a (Æ b)) word";
+		let (first, second) = whole.as_bytes().split_at(13);
+		let forms: Vec<Statement> = parse_reader(first.chain(second)).map(|form| form.ok().unwrap()).collect();
+		let mut expected = parse_string(whole).ok().unwrap();
+		expected.reverse();
+		assert_eq![forms, expected];
+		let mut forms = parse_reader("(a) (b".as_bytes());
+		assert![forms.next().unwrap().is_ok()];
+		assert![forms.next().unwrap().is_err()];
+		assert![forms.next().is_none()];
+	}
+
+	#[test]
+	fn vector_literals() {
+		use utilities::*;
+		let symbol = |name: &str| rcs(Coredata::Symbol(Symbol::from(name)));
+		let program = parse_string("#(1 #(2)) # (3)").ok().unwrap();
+		let expected = parse_string("(vector 1 (vector 2)) # (3)").ok().unwrap();
+		assert_eq![program, expected];
+		assert_eq![program[1], symbol("#")];
+	}
+
+	#[test]
+	fn byte_offsets() {
+		let span = |data: &Statement| {
+			let source = data.0.clone().unwrap();
+			(source.offset, source.length)
+		};
+		let program = parse_string("a b").ok().unwrap();
+		assert_eq![span(&program[1]), (0, 1)];
+		assert_eq![span(&program[0]), (2, 1)];
+		let program = parse_string("(x yz)\nÆb ()").ok().unwrap();
+		assert_eq![span(&program[2]), (0, 6)];
+		if let Coredata::Cell(ref head, ref tail) = program[2].1 {
+			assert_eq![span(head), (1, 1)];
+			assert_eq![span(tail), (3, 2)];
+		} else {
+			panic!["expected a list"];
+		}
+		assert_eq![span(&program[1]), (7, 3)];
+		assert_eq![program[1].0.clone().unwrap().line, 2];
+		assert_eq![span(&program[0]), (11, 2)];
+	}
+
+	#[test]
+	fn line_endings_and_tabs() {
+		let position = |data: &Statement| {
+			let source = data.0.clone().unwrap();
+			(source.line, source.column)
+		};
+		let program = parse_string("(a)\r\n(b)\r\n\r\n  c").ok().unwrap();
+		assert_eq![position(&program[2]), (1, 1)];
+		assert_eq![position(&program[1]), (2, 1)];
+		assert_eq![position(&program[0]), (4, 3)];
+		let program = parse_string("a\rb").ok().unwrap();
+		assert_eq![position(&program[0]), (2, 1)];
+		let program = parse_string("\ta\n\ta").ok().unwrap();
+		assert_eq![position(&program[1]), (1, 2)];
+		let mut state = ParseState::default();
+		state.tab_width = 4;
+		let program = parse_string_with_state("\ta   \tb\n \tc", state).ok().unwrap();
+		assert_eq![position(&program[2]), (1, 5)];
+		assert_eq![position(&program[1]), (1, 13)];
+		assert_eq![position(&program[0]), (2, 5)];
+	}
+
+	#[test]
+	fn char_literals() {
+		use utilities::*;
+		let symbol = |name: &str| rcs(Coredata::Symbol(Symbol::from(name)));
+		let program = parse_string("(#\\( #\\) #\\  #\\a)").ok().unwrap();
+		assert_eq![program.len(), 1];
+		let elements = collect_cell_into_revvec(&program[0]);
+		assert_eq![elements.len(), 4];
+		assert_eq![elements[3], symbol("#\\(")];
+		assert_eq![elements[2], symbol("#\\)")];
+		assert_eq![elements[1], symbol("#\\ ")];
+		assert_eq![elements[0], symbol("#\\a")];
+	}
+
+	#[test]
+	fn dotted_pairs() {
+		use utilities::*;
+		let symbol = |name: &str| rcs(Coredata::Symbol(Symbol::from(name)));
+		let cell = |head, tail| rcs(Coredata::Cell(head, tail));
+		let program = parse_string("(1 . 2) (1 2 . 3) (1 . (2)) .").ok().unwrap();
+		assert_eq![program.len(), 4];
+		assert_eq![program[3], cell(symbol("1"), symbol("2"))];
+		assert_eq![program[2], cell(symbol("1"), cell(symbol("2"), symbol("3")))];
+		assert_eq![program[1], cell(symbol("1"), cell(symbol("2"), rcs(Coredata::Null())))];
+		assert_eq![program[0], symbol(".")];
+	}
 }
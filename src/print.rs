@@ -0,0 +1,149 @@
+//! Printing data for people rather than for `read`.
+//!
+//! `Display` for `Sourcedata` writes data so that it can be read back, so a list comes out as
+//! `(list 1 2 3)` and a symbol as `(@ a)`. The printer here writes proper lists (cells ending
+//! in `Null`) as `(1 2 3)` and symbols bare. Cells ending in anything else are written with a
+//! dot: `(1 2 . 3)`. Vectors are written as `#(1 2 3)`, other data the same as `Display`.
+//! `display` is the same except that strings and characters are written as their plain text.
+//!
+//! ```
+//! extern crate teko;
+//! let program = teko::parse::parse_string("(list 1 (@ a) (list 2 3))").ok().unwrap();
+//! let env = teko::interpret::interpret(program);
+//! assert_eq![teko::print::to_string(&env.get_result()), "(1 a (2 3))"];
+//! ```
+use std::collections::HashSet;
+use data_structures::*;
+
+/// Write data in list notation.
+pub fn to_string(data: &Sourcedata) -> String {
+	write(data, false)
+}
+
+/// Write data in list notation with strings and characters as plain text, as used by `display`.
+pub fn display(data: &Sourcedata) -> String {
+	write(data, true)
+}
+
+fn write(data: &Sourcedata, plain: bool) -> String {
+	enum Queue<'a> {
+		Close,
+		Data(&'a Sourcedata),
+		Element(Statement),
+		Leave(usize),
+		Text(&'static str),
+	}
+	let mut output = String::new();
+	let mut queue = vec![Queue::Data(data)];
+	// Addresses of the vectors being written, one that contains itself is a cycle
+	let mut open = HashSet::new();
+	while let Some(elem) = queue.pop() {
+		let owned;
+		let data = match elem {
+			Queue::Close => {
+				output.push(')');
+				continue;
+			}
+			Queue::Data(data) => data,
+			Queue::Element(data) => {
+				owned = data;
+				&*owned
+			}
+			Queue::Leave(address) => {
+				open.remove(&address);
+				continue;
+			}
+			Queue::Text(text) => {
+				output.push_str(text);
+				continue;
+			}
+		};
+		match data.1 {
+			Coredata::Cell(..) => {
+				output.push('(');
+				let mut elements = vec![];
+				let mut current = data;
+				let mut last = None;
+				while let Coredata::Cell(ref head, ref tail) = current.1 {
+					if !elements.is_empty() {
+						elements.push(Queue::Text(" "));
+					}
+					elements.push(Queue::Element(head.clone()));
+					current = tail;
+					last = Some(tail);
+				}
+				if let Coredata::Null() = current.1 {
+				} else if let Some(tail) = last {
+					elements.push(Queue::Text(" . "));
+					elements.push(Queue::Element(tail.clone()));
+				}
+				queue.push(Queue::Close);
+				queue.extend(elements.into_iter().rev());
+			}
+			Coredata::Symbol(ref symbol) => {
+				output.push_str(symbol.into());
+			}
+			Coredata::String(ref string) if plain => {
+				output.push_str(string);
+			}
+			Coredata::Char(character) if plain => {
+				output.push(character);
+			}
+			Coredata::Vector(ref vector) => {
+				let address = vector as *const Vector as usize;
+				if open.insert(address) {
+					output.push_str("#(");
+					let mut elements = vec![];
+					for element in vector.to_vec() {
+						if !elements.is_empty() {
+							elements.push(Queue::Text(" "));
+						}
+						elements.push(Queue::Element(element));
+					}
+					queue.push(Queue::Leave(address));
+					queue.push(Queue::Close);
+					queue.extend(elements.into_iter().rev());
+				} else {
+					output.push_str("#<cycle>");
+				}
+			}
+			_ => {
+				output.push_str(&format!["{}", data]);
+			}
+		}
+	}
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use utilities::*;
+	#[test]
+	fn list_notation() {
+		let list = |elements: Vec<Statement>| {
+			elements.into_iter().rev().fold(rcs(Coredata::Null()), |tail, head| {
+				rcs(Coredata::Cell(head, tail))
+			})
+		};
+		let integer = |value: i32| rcs(Coredata::Integer(value.into()));
+		assert_eq![to_string(&list(vec![integer(1), integer(2), integer(3)])), "(1 2 3)"];
+		assert_eq![to_string(&rcs(Coredata::Cell(integer(1), integer(2)))), "(1 . 2)"];
+		assert_eq![
+			to_string(&rcs(Coredata::Cell(integer(1), rcs(Coredata::Cell(integer(2), integer(3)))))),
+			"(1 2 . 3)"
+		];
+		assert_eq![to_string(&list(vec![list(vec![]), list(vec![integer(1)])])), "(() (1))"];
+		assert_eq![to_string(&rcs(Coredata::Symbol(Symbol::from("a")))), "a"];
+		assert_eq![to_string(&rcs(Coredata::String("a b".into()))), "(\" a b)"];
+		assert_eq![to_string(&rcs(Coredata::Vector(Vector::new(vec![integer(1), list(vec![integer(2)])])))), "#(1 (2))"];
+	}
+	#[test]
+	fn plain_text() {
+		let string = |value: &str| rcs(Coredata::String(value.into()));
+		let list = rcs(Coredata::Cell(string("a b"), rcs(Coredata::Cell(rcs(Coredata::Char('c')), rcs(Coredata::Null())))));
+		assert_eq![display(&string("hi there")), "hi there"];
+		assert_eq![display(&list), "(a b c)"];
+		assert_eq![to_string(&list), "((\" a b) #\\c)"];
+	}
+}
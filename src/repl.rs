@@ -0,0 +1,86 @@
+//! Read-eval-print loop for interactive use.
+//!
+//! Forms are read one at a time using the incremental `parse_character` interface, so an
+//! incomplete form (unmatched opening parentheses, see `parse::is_incomplete`) continues on the
+//! next line instead of being a parse error. Each complete
+//! form is evaluated in a persistent environment and its result is written to the output using
+//! the list notation of `print`.
+//! Line editing is left to the terminal or the command line interface wrapping this library.
+//!
+//! ```
+//! extern crate teko;
+//! let mut output = vec![];
+//! teko::repl::run("(+ 1 2)\n".as_bytes(), &mut output);
+//! assert_eq![String::from_utf8(output).unwrap(), "3\n"];
+//! ```
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use data_structures::*;
+use interpret::{eval, initialize_environment_with_standard_library};
+use parse::*;
+use print;
+
+/// Run the read-eval-print loop on standard input and standard output.
+///
+/// Prompts with `> `, or `. ` while a form continues on the next line.
+pub fn repl() -> Env {
+	let stdin = io::stdin();
+	let stdout = io::stdout();
+	run_with_prompts(stdin.lock(), &mut stdout.lock(), "> ", ". ")
+}
+
+/// Run the read-eval-print loop on arbitrary input and output.
+///
+/// Every form writes its result, also when several are on one line. Evaluation errors are
+/// written to the output and the loop continues. Returns the environment once the input is
+/// exhausted. No prompts are written, see `run_with_prompts`.
+pub fn run<R: Read, W: Write>(input: R, output: &mut W) -> Env {
+	run_with_prompts(input, output, "", "")
+}
+
+/// Run the read-eval-print loop, writing a prompt before each line of input.
+///
+/// `prompt` is written when a new form starts and `continuation` when the line continues an
+/// incomplete form. A form is evaluated and its result written as soon as it is complete,
+/// before the rest of its line is read.
+///
+/// ```
+/// extern crate teko;
+/// let mut output = vec![];
+/// teko::repl::run_with_prompts("(+ 1\n2)\n".as_bytes(), &mut output, "> ", ". ");
+/// assert_eq![String::from_utf8(output).unwrap(), "> . 3\n> "];
+/// ```
+pub fn run_with_prompts<R: Read, W: Write>(input: R, output: &mut W, prompt: &str, continuation: &str) -> Env {
+	let mut env = initialize_environment_with_standard_library();
+	let mut state = ParseState::from("tty");
+	let mut lines = BufReader::new(input).lines();
+	loop {
+		let _ = write![output, "{}", if is_incomplete(&state) { continuation } else { prompt }];
+		let _ = output.flush();
+		let line = match lines.next() {
+			Some(Ok(line)) => line,
+			Some(Err(error)) => {
+				let _ = writeln![output, "unable to read input: {}", error];
+				break;
+			}
+			None => break,
+		};
+		for character in line.chars().chain(Some('\n')) {
+			if let Err(error) = parse_character(character, &mut state) {
+				let _ = writeln![output, "parse error: {}", error.error.unwrap_or_default()];
+				state = ParseState::from("tty");
+				state.current_read_position = error.current_read_position;
+				break;
+			}
+			// Top level forms are completed here, evaluate them before reading any further
+			if state.stack.len() == 1 {
+				let forms: Program = state.stack[0].drain(..).collect();
+				for form in forms {
+					env = eval(vec![form], env);
+					let _ = writeln![output, "{}", print::to_string(&env.get_result())];
+				}
+			}
+		}
+	}
+	env
+}
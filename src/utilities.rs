@@ -1,6 +1,6 @@
 //! Utilities used by the implementation.
 
-use std::{cmp, convert, fmt, sync::Arc, usize};
+use std::{cmp, collections::{HashMap, HashSet}, convert, fmt, mem, sync::{Arc, OnceLock}, usize};
 
 use data_structures::*;
 use super::VEC_CAPACITY;
@@ -17,109 +17,224 @@ pub fn program_to_cells(program: &Program) -> Statement {
 // Impls
 // //////////////////////////////////////////////////////////
 
+/// Deep equality without recursion.
+///
+/// Data holding other data, such as cells, vectors, tables, errors, and the code of functions
+/// and macros, push their children onto a heap allocated stack instead of comparing them
+/// recursively, so long lists do not overflow the stack. Vectors are mutable and can
+/// contain themselves, so a pair of vectors that is already being compared is assumed equal.
+/// This makes two cyclic structures of the same shape equal and lets the comparison end.
 impl cmp::PartialEq for Coredata {
 	fn eq(&self, other: &Self) -> bool {
-		if self as *const Coredata == other as *const Coredata {
-			return true;
+		let mut compared = HashSet::new();
+		let mut pending = vec![];
+		if !eq_step(self, other, &mut pending, &mut compared) {
+			return false;
 		}
-		match *self {
-			Coredata::Boolean(true) => {
-				if let Coredata::Boolean(true) = *other {
-					true
-				} else {
-					false
-				}
+		while let Some((lhs, rhs)) = pending.pop() {
+			if !eq_step(&lhs.1, &rhs.1, &mut pending, &mut compared) {
+				return false;
 			}
-			Coredata::Boolean(false) => {
-				if let Coredata::Boolean(false) = *other {
-					true
-				} else {
-					false
-				}
+		}
+		true
+	}
+}
+
+/// Compare the top of two data items, pushing their children to `pending`.
+fn eq_step(
+	data: &Coredata,
+	other: &Coredata,
+	pending: &mut Vec<(Statement, Statement)>,
+	compared: &mut HashSet<(usize, usize)>,
+) -> bool {
+	if data as *const Coredata == other as *const Coredata {
+		return true;
+	}
+	match *data {
+		Coredata::Boolean(true) => {
+			if let Coredata::Boolean(true) = *other {
+				true
+			} else {
+				false
 			}
-			Coredata::Error(ref lhs) => {
-				if let Coredata::Error(ref rhs) = *other {
-					lhs == rhs
-				} else {
-					false
-				}
+		}
+		Coredata::Boolean(false) => {
+			if let Coredata::Boolean(false) = *other {
+				true
+			} else {
+				false
 			}
-			Coredata::Function(Function::Builtin(_, ref lhs)) => {
-				if let Coredata::Function(Function::Builtin(_, ref rhs)) = *other {
-					lhs == rhs
-				} else {
-					false
-				}
+		}
+		Coredata::Box(ref lhs) => {
+			if let Coredata::Box(ref rhs) = *other {
+				lhs == rhs
+			} else {
+				false
 			}
-			Coredata::Function(Function::Library(ref lhsparams, ref lhscode)) => {
-				if let Coredata::Function(Function::Library(ref rhsparams, ref rhscode)) = *other {
-					lhsparams == rhsparams && lhscode == rhscode
-				} else {
-					false
-				}
+		}
+		Coredata::Bytes(ref lhs) => {
+			if let Coredata::Bytes(ref rhs) = *other {
+				lhs == rhs
+			} else {
+				false
 			}
-			Coredata::Integer(ref lhs) => {
-				if let Coredata::Integer(ref rhs) = *other {
-					lhs == rhs
-				} else {
-					false
-				}
+		}
+		Coredata::Char(ref lhs) => {
+			if let Coredata::Char(ref rhs) = *other {
+				lhs == rhs
+			} else {
+				false
 			}
-			Coredata::Macro(Macro::Builtin(_, ref lhs)) => {
-				if let Coredata::Macro(Macro::Builtin(_, ref rhs)) = *other {
-					lhs == rhs
-				} else {
-					false
+		}
+		Coredata::Error(ref lhs) => {
+			if let Coredata::Error(ref rhs) = *other {
+				if lhs.message != rhs.message || lhs.source != rhs.source {
+					return false;
 				}
-			}
-			Coredata::Macro(Macro::Library(ref lhsparam, ref lhscode)) => {
-				if let Coredata::Macro(Macro::Library(ref rhsparam, ref rhscode)) = *other {
-					lhsparam == rhsparam && lhscode == rhscode
-				} else {
-					false
+				match (&lhs.cause, &rhs.cause) {
+					(Some(lhs), Some(rhs)) => pending.push((lhs.clone(), rhs.clone())),
+					(None, None) => {}
+					_ => return false,
 				}
+				pending.push((lhs.data.clone(), rhs.data.clone()));
+				true
+			} else {
+				false
 			}
-			Coredata::Internal(ref lhs) => {
-				if let Coredata::Internal(ref rhs) = *other {
-					lhs == rhs
-				} else {
-					false
-				}
+		}
+		Coredata::Float(ref lhs) => {
+			if let Coredata::Float(ref rhs) = *other {
+				lhs == rhs
+			} else {
+				false
 			}
-			Coredata::Null() => {
-				if let Coredata::Null() = *other {
-					true
-				} else {
-					false
+		}
+		Coredata::Function(Function::Builtin(_, ref lhs, _)) => {
+			if let Coredata::Function(Function::Builtin(_, ref rhs, _)) = *other {
+				lhs == rhs
+			} else {
+				false
+			}
+		}
+		Coredata::Function(Function::Library(ref lhsparams, ref lhscode, ref lhscaptures)) => {
+			if let Coredata::Function(Function::Library(ref rhsparams, ref rhscode, ref rhscaptures)) = *other {
+				if lhsparams != rhsparams || lhscode.len() != rhscode.len() || !Scope::same(lhscaptures, rhscaptures) {
+					return false;
 				}
+				pending.extend(lhscode.iter().cloned().zip(rhscode.iter().cloned()).rev());
+				true
+			} else {
+				false
 			}
-			Coredata::Cell(ref lhshead, ref lhstail) => {
-				if let Coredata::Cell(ref rhshead, ref rhstail) = *other {
-					lhshead == rhshead && lhstail == rhstail
-				} else {
-					false
+		}
+		Coredata::Integer(ref lhs) => {
+			if let Coredata::Integer(ref rhs) = *other {
+				lhs == rhs
+			} else {
+				false
+			}
+		}
+		Coredata::Rational(ref lhs) => {
+			if let Coredata::Rational(ref rhs) = *other {
+				lhs == rhs
+			} else {
+				false
+			}
+		}
+		Coredata::Macro(Macro::Builtin(_, ref lhs)) => {
+			if let Coredata::Macro(Macro::Builtin(_, ref rhs)) = *other {
+				lhs == rhs
+			} else {
+				false
+			}
+		}
+		Coredata::Macro(Macro::Library(ref lhsparam, ref lhscode)) => {
+			if let Coredata::Macro(Macro::Library(ref rhsparam, ref rhscode)) = *other {
+				if lhsparam != rhsparam || lhscode.len() != rhscode.len() {
+					return false;
 				}
+				pending.extend(lhscode.iter().cloned().zip(rhscode.iter().cloned()).rev());
+				true
+			} else {
+				false
 			}
-			Coredata::String(ref lhs) => {
-				if let Coredata::String(ref rhs) = *other {
-					lhs == rhs
-				} else {
-					false
+		}
+		Coredata::Internal(ref lhs) => {
+			if let Coredata::Internal(ref rhs) = *other {
+				lhs == rhs
+			} else {
+				false
+			}
+		}
+		Coredata::Null() => {
+			if let Coredata::Null() = *other {
+				true
+			} else {
+				false
+			}
+		}
+		Coredata::Cell(ref lhshead, ref lhstail) => {
+			if let Coredata::Cell(ref rhshead, ref rhstail) = *other {
+				pending.push((lhstail.clone(), rhstail.clone()));
+				pending.push((lhshead.clone(), rhshead.clone()));
+				true
+			} else {
+				false
+			}
+		}
+		Coredata::String(ref lhs) => {
+			if let Coredata::String(ref rhs) = *other {
+				lhs == rhs
+			} else {
+				false
+			}
+		}
+		Coredata::Symbol(ref lhs) => {
+			if let Coredata::Symbol(ref rhs) = *other {
+				lhs == rhs
+			} else {
+				false
+			}
+		}
+		Coredata::Table(ref lhs) => {
+			if let Coredata::Table(ref rhs) = *other {
+				// Keys are immediates, so only the values can hold further data
+				let (lhskeys, rhskeys) = (lhs.keys(), rhs.keys());
+				if lhskeys != rhskeys {
+					return false;
 				}
+				for key in lhskeys.iter().rev() {
+					pending.push((lhs.get(key).unwrap(), rhs.get(key).unwrap()));
+				}
+				true
+			} else {
+				false
 			}
-			Coredata::Symbol(ref lhs) => {
-				if let Coredata::Symbol(ref rhs) = *other {
-					lhs == rhs
-				} else {
-					false
+		}
+		Coredata::Values(ref lhs) => {
+			if let Coredata::Values(ref rhs) = *other {
+				if lhs.len() != rhs.len() {
+					return false;
 				}
+				pending.extend(lhs.iter().cloned().zip(rhs.iter().cloned()).rev());
+				true
+			} else {
+				false
 			}
-			Coredata::Table(ref lhs) => {
-				if let Coredata::Table(ref rhs) = *other {
-					lhs == rhs
-				} else {
-					false
+		}
+		Coredata::Vector(ref lhs) => {
+			if let Coredata::Vector(ref rhs) = *other {
+				if !compared.insert((lhs as *const Vector as usize, rhs as *const Vector as usize)) {
+					return true;
+				}
+				let (lhs, rhs) = (lhs.to_vec(), rhs.to_vec());
+				if lhs.len() != rhs.len() {
+					return false;
 				}
+				pending.extend(lhs.into_iter().zip(rhs).rev());
+				true
+			} else {
+				false
 			}
 		}
 	}
@@ -131,13 +246,145 @@ impl cmp::PartialEq for Sourcedata {
 	}
 }
 
+/// Drop without recursion.
+///
+/// Dropping the head of a list would otherwise drop its tail recursively, overflowing the
+/// stack for long lists. Instead the children are moved onto a heap allocated stack and only
+/// dropped there if this was their last reference.
+impl Drop for Sourcedata {
+	fn drop(&mut self) {
+		fn take_children(data: &mut Coredata, pending: &mut Vec<Statement>) {
+			let mut take = |statement: &mut Statement| {
+				if let Coredata::Null() = statement.1 {
+				} else {
+					pending.push(mem::replace(statement, null()));
+				}
+			};
+			match *data {
+				Coredata::Cell(ref mut head, ref mut tail) => {
+					take(head);
+					take(tail);
+				}
+				Coredata::Error(ref mut content) => {
+					take(&mut content.data);
+					if let Some(ref mut cause) = content.cause {
+						take(cause);
+					}
+				}
+				Coredata::Function(Function::Library(_, ref mut code, _)) |
+				Coredata::Macro(Macro::Library(_, ref mut code)) |
+				Coredata::Values(ref mut code) => {
+					pending.append(code);
+				}
+				Coredata::Internal(Commands::Call(ref mut statement)) |
+				Coredata::Internal(Commands::Prep(ref mut statement)) => take(statement),
+				Coredata::Internal(Commands::If(ref mut first, ref mut second)) => {
+					take(first);
+					take(second);
+				}
+				_ => {}
+			}
+		}
+		fn null() -> Statement {
+			static NULL: OnceLock<Statement> = OnceLock::new();
+			NULL.get_or_init(|| rcs(Coredata::Null())).clone()
+		}
+		if let Coredata::Null() = self.1 {
+			return;
+		}
+		let mut pending = vec![];
+		take_children(&mut self.1, &mut pending);
+		while let Some(statement) = pending.pop() {
+			if let Ok(mut data) = Arc::try_unwrap(statement) {
+				take_children(&mut data.1, &mut pending);
+			}
+		}
+	}
+}
+
+/// Data from native values, without a source.
+///
+/// Rust strings become Teko strings and vectors become proper lists. Use `rc` to get a
+/// `Statement`.
+///
+/// ```
+/// extern crate teko;
+/// use teko::data_structures::Sourcedata;
+/// use teko::utilities::rc;
+/// let list = Sourcedata::from(vec![rc(1.into()), rc(true.into()), rc("a".into())]);
+/// assert_eq![format!["{}", list], "(list 1 true (\" a))"];
+/// ```
+impl convert::From<i64> for Sourcedata {
+	fn from(integer: i64) -> Sourcedata {
+		Sourcedata(None, Coredata::Integer(integer.into()))
+	}
+}
+
+impl convert::From<bool> for Sourcedata {
+	fn from(boolean: bool) -> Sourcedata {
+		Sourcedata(None, Coredata::Boolean(boolean))
+	}
+}
+
+impl<'a> convert::From<&'a str> for Sourcedata {
+	fn from(string: &'a str) -> Sourcedata {
+		Sourcedata(None, Coredata::String(string.into()))
+	}
+}
+
+impl convert::From<Vec<Statement>> for Sourcedata {
+	fn from(elements: Vec<Statement>) -> Sourcedata {
+		elements.into_iter().rev().fold(Sourcedata(None, Coredata::Null()), |tail, head| {
+			Sourcedata(None, Coredata::Cell(head, rc(tail)))
+		})
+	}
+}
+
+/// Native values for embedding Teko.
+///
+/// Each method returns an error naming the expected and the actual type if the data is not of
+/// the expected type.
+///
+/// ```
+/// extern crate teko;
+/// let result = teko::interpret::eval_str("(+ 1 2)").unwrap();
+/// assert_eq![result.as_i64(), Ok(3)];
+/// assert![result.as_str().is_err()];
+/// ```
+impl Sourcedata {
+	/// Get an integer as `i64`, failing if it is out of range.
+	pub fn as_i64(&self) -> Result<i64, String> {
+		use num::ToPrimitive;
+		match self.1 {
+			Coredata::Integer(ref integer) => {
+				integer.to_i64().ok_or_else(|| format!["integer {} does not fit in an i64", integer])
+			}
+			_ => Err(format!["expected Integer but got {}", data_name(self)]),
+		}
+	}
+	/// Get the content of a string.
+	pub fn as_str(&self) -> Result<&str, String> {
+		match self.1 {
+			Coredata::String(ref string) => Ok(string),
+			_ => Err(format!["expected String but got {}", data_name(self)]),
+		}
+	}
+	/// Get a boolean.
+	pub fn as_bool(&self) -> Result<bool, String> {
+		match self.1 {
+			Coredata::Boolean(boolean) => Ok(boolean),
+			_ => Err(format!["expected Boolean but got {}", data_name(self)]),
+		}
+	}
+}
+
 impl fmt::Debug for Function {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
-			Function::Builtin(.., ref name) => {
+			Function::Builtin(_, ref name, _) => {
 				write![f, "{}", name]?;
 			}
-			Function::Library(ref params, ref code) => {
+			Function::Library(ref params, ref code, _) => {
 				write![f, "(fn ("]?;
 				let mut first = true;
 				for i in params.iter() {
@@ -159,6 +406,40 @@ impl fmt::Debug for Function {
 	}
 }
 
+thread_local! {
+	// Addresses of the vectors and boxes being written by `Debug` on this thread
+	static DEBUGGING: ::std::cell::RefCell<HashSet<usize>> = ::std::cell::RefCell::new(HashSet::new());
+}
+
+/// Run `body` unless the container at `address` is already being written, which is a cycle.
+fn debug_once<F>(address: usize, f: &mut fmt::Formatter, body: F) -> fmt::Result
+	where F: FnOnce(&mut fmt::Formatter) -> fmt::Result {
+	if !DEBUGGING.with(|open| open.borrow_mut().insert(address)) {
+		return write![f, "#<cycle>"];
+	}
+	let result = body(f);
+	DEBUGGING.with(|open| open.borrow_mut().remove(&address));
+	result
+}
+
+impl fmt::Debug for Boxed {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		debug_once(self as *const Boxed as usize, f, |f| write![f, "(make-box {:?})", self.get()])
+	}
+}
+
+impl fmt::Debug for Vector {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		debug_once(self as *const Vector as usize, f, |f| {
+			write![f, "(vector"]?;
+			for element in self.to_vec() {
+				write![f, " {:?}", element]?;
+			}
+			write![f, ")"]
+		})
+	}
+}
+
 impl fmt::Debug for Macro {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
@@ -179,14 +460,23 @@ impl fmt::Debug for Macro {
 
 /// Display for Sourcedata.
 ///
-/// All Sourcedata can be written in a form such that it can be read again.
+/// All Sourcedata can be written in a form such that it can be read again. The one exception
+/// is the location an error was raised at, which is written after the error as ` at line:column:source`
+/// for people to read.
 impl fmt::Display for Sourcedata {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		use data_structures::{Coredata::*, Function, Macro};
 		// What needs to be printed
 		enum Queue<'a> {
 			Close,
-			Data(&'a Sourcedata, Context),
+			Data(Item<'a>, Context),
+			Leave(usize),
+			Location(Source),
+		}
+		// Children are cloned out so that vector elements, which sit behind a lock, can be queued
+		enum Item<'a> {
+			Borrowed(&'a Sourcedata),
+			Owned(Statement),
 		}
 		#[derive(Clone, Copy)]
 		enum Context {
@@ -200,20 +490,65 @@ impl fmt::Display for Sourcedata {
 		// Is it ONLY used for symbols? It should be... let me think...
 		// Yes. Let's try it. We just store false/true
 		let mut spacer = false;
-		queue.push(Queue::Data(self, Context::TopLevel));
+		// Addresses of the vectors and boxes being written, one that contains itself is a cycle
+		let mut open: HashSet<usize> = HashSet::new();
+		queue.push(Queue::Data(Item::Borrowed(self), Context::TopLevel));
 		while let Some(elem) = queue.pop() {
 			macro_rules! spacify { () => { if spacer { write![f, " "]?; } }; }
 			match elem {
 				Queue::Close => {
 					write![f, ")"]?;
 				}
-				Queue::Data(ref data, ref context) => {
+				Queue::Leave(address) => {
+					open.remove(&address);
+				}
+				Queue::Location(source) => {
+					write![f, " at {}", source]?;
+				}
+				Queue::Data(item, ref context) => {
+					let owned;
+					let data: &Sourcedata = match item {
+						Item::Borrowed(data) => data,
+						Item::Owned(data) => {
+							owned = data;
+							&owned
+						}
+					};
+					macro_rules! enter {
+						($container:expr, $open:expr, $elements:expr) => {
+							let address = $container as *const _ as usize;
+							if open.insert(address) {
+								write![f, $open]?;
+								queue.push(Queue::Leave(address));
+								queue.push(Queue::Close);
+								for element in $elements.into_iter().rev() {
+									queue.push(Queue::Data(Item::Owned(element), Context::TopLevel));
+								}
+							} else {
+								write![f, "(@ #<cycle>)"]?;
+							}
+						};
+					}
 					match data.1 {
 						Boolean(state) => {
 							spacify![];
 							write![f, "{}", state]?;
 							spacer = true;
 						}
+						Box(ref boxed) => {
+							spacify![];
+							enter![boxed, "(make-box", vec![boxed.get()]];
+							spacer = true;
+						}
+						Bytes(ref bytes) => {
+							spacify![];
+							write![f, "(bytes"]?;
+							for byte in bytes {
+								write![f, " {}", byte]?;
+							}
+							write![f, ")"]?;
+							spacer = true;
+						}
 						Cell(ref head, ref tail) => {
 							spacify![];
 							match *context {
@@ -228,33 +563,48 @@ impl fmt::Display for Sourcedata {
 									queue.push(Queue::Close);
 								}
 							}
-							queue.push(Queue::Data(tail,
+							queue.push(Queue::Data(Item::Owned(tail.clone()),
 								if let Context::Run = *context { Context::Runnable }
 								else if let Context::Runnable = *context { Context::Runnable }
 								else { Context::Cell }));
-							queue.push(Queue::Data(head,
+							queue.push(Queue::Data(Item::Owned(head.clone()),
 								if let Context::Run = *context { Context::Run }
 								else if let Context::Runnable = *context { Context::Run }
 								else { Context::TopLevel }));
 							spacer = false;
 						}
-						Error(ref arg) => {
+						Char(arg) => {
 							spacify![];
-							write![f, "(error"]?;
-							if let Coredata::Null() = arg.1 {
-								write![f, ")"]?;
+							write![f, "{}", char_literal(arg)]?;
+							spacer = true;
+						}
+						Error(ref error) => {
+							spacify![];
+							write![f, "(make-error"]?;
+							if let Some(ref source) = data.0 {
+								queue.push(Queue::Location(source.clone()));
+							}
+							queue.push(Queue::Close);
+							if let Some(ref cause) = error.cause {
+								queue.push(Queue::Data(Item::Owned(cause.clone()), Context::TopLevel));
+								queue.push(Queue::Data(Item::Owned(error.data.clone()), Context::TopLevel));
+							} else if let Coredata::Null() = error.data.1 {
 							} else {
-								queue.push(Queue::Close);
-								queue.push(Queue::Data(arg, Context::TopLevel));
+								queue.push(Queue::Data(Item::Owned(error.data.clone()), Context::TopLevel));
 							}
 							spacer = true;
 						}
-						Function(Function::Builtin(.., ref name)) => {
+						Float(ref arg) => {
+							spacify![];
+							write![f, "{:?}", arg.0]?;
+							spacer = true;
+						}
+						Function(Function::Builtin(_, ref name, _)) => {
 							spacify![];
 							write![f, "{}", name]?;
 							spacer = true;
 						}
-						Function(Function::Library(ref params, ref code)) => {
+						Function(Function::Library(ref params, ref code, _)) => {
 							spacify![];
 							// HEADER and PARAMETER LIST
 							write![f, "(function ("]?;
@@ -270,7 +620,7 @@ impl fmt::Display for Sourcedata {
 							// QUEUE code
 							queue.push(Queue::Close);
 							for i in code.iter() {
-								queue.push(Queue::Data(i, Context::Run));
+								queue.push(Queue::Data(Item::Owned(i.clone()), Context::Run));
 							}
 							spacer = true;
 						}
@@ -279,6 +629,11 @@ impl fmt::Display for Sourcedata {
 							write![f, "{}", arg]?;
 							spacer = true;
 						}
+						Rational(ref arg) => {
+							spacify![];
+							write![f, "{}", arg]?;
+							spacer = true;
+						}
 						Macro(Macro::Builtin(.., ref name)) => {
 							spacify![];
 							write![f, "{}", name]?;
@@ -290,7 +645,7 @@ impl fmt::Display for Sourcedata {
 							// QUEUE code
 							queue.push(Queue::Close);
 							for i in code.iter() {
-								queue.push(Queue::Data(i, Context::Run));
+								queue.push(Queue::Data(Item::Owned(i.clone()), Context::Run));
 							}
 							spacer = true;
 						}
@@ -361,7 +716,35 @@ impl fmt::Display for Sourcedata {
 							}
 							spacer = true;
 						}
-						_ => {}
+						Table(ref table) => {
+							spacify![];
+							write![f, "(make-table"]?;
+							queue.push(Queue::Close);
+							for key in table.keys().into_iter().rev() {
+								queue.push(Queue::Data(Item::Owned(table.get(&key).unwrap()), Context::TopLevel));
+								queue.push(Queue::Data(Item::Owned(key), Context::TopLevel));
+							}
+							spacer = true;
+						}
+						Vector(ref vector) => {
+							spacify![];
+							enter![vector, "(vector", vector.to_vec()];
+							spacer = true;
+						}
+						Values(ref values) => {
+							spacify![];
+							write![f, "(values"]?;
+							queue.push(Queue::Close);
+							for value in values.iter().rev() {
+								queue.push(Queue::Data(Item::Owned(value.clone()), Context::TopLevel));
+							}
+							spacer = true;
+						}
+						Internal(ref command) => {
+							spacify![];
+							write![f, "(@ #<internal {:?}>)", command]?;
+							spacer = true;
+						}
 					}
 				}
 			}
@@ -370,6 +753,56 @@ impl fmt::Display for Sourcedata {
 	}
 }
 
+/// Pretty print data with indentation.
+///
+/// Data whose written form fits within `width` columns is printed on a single line. Lists that
+/// do not fit are broken up with one element per line, each indented by two spaces relative to
+/// the list containing it. The output can be read back just like the output of `Display`.
+pub fn pretty_print(data: &Sourcedata, width: usize) -> String {
+	enum Queue<'a> {
+		Close,
+		Data(&'a Sourcedata, usize),
+		Newline(usize),
+	}
+	let mut output = String::new();
+	let mut queue = vec![Queue::Data(data, 0)];
+	while let Some(elem) = queue.pop() {
+		match elem {
+			Queue::Close => {
+				output.push(')');
+			}
+			Queue::Data(data, indent) => {
+				let flat = format!["{}", data];
+				if let Coredata::Cell(..) = data.1 {
+					if indent + flat.chars().count() > width {
+						output.push_str("(list");
+						queue.push(Queue::Close);
+						let mut elements = vec![];
+						let mut current = data;
+						while let Coredata::Cell(ref head, ref tail) = current.1 {
+							elements.push(&**head);
+							current = tail;
+						}
+						for element in elements.into_iter().rev() {
+							queue.push(Queue::Data(element, indent + 2));
+							queue.push(Queue::Newline(indent + 2));
+						}
+						continue;
+					}
+				}
+				output.push_str(&flat);
+			}
+			Queue::Newline(indent) => {
+				output.push('\n');
+				for _ in 0..indent {
+					output.push(' ');
+				}
+			}
+		}
+	}
+	output
+}
+
 impl Sourcedata {
 	/// Return the head of a cell, unwind if not a cell.
 	pub fn head(&self) -> Option<Arc<Sourcedata>> {
@@ -416,6 +849,8 @@ impl Default for Source {
 		Source {
 			line: 1,
 			column: 1,
+			offset: 0,
+			length: 0,
 			source: "unknown".into(),
 		}
 	}
@@ -449,6 +884,8 @@ impl Default for ParseState {
 			token: String::from(""),
 			stack: vec![vec![]],
 			error: None,
+			tab_width: 1,
+			after_carriage_return: false,
 		}
 	}
 }
@@ -457,9 +894,8 @@ impl ParseState {
 	pub fn from(source: &str) -> ParseState {
 		let mut state = ParseState::default();
 		state.current_read_position = Source {
-			line: 1,
-			column: 1,
 			source: source.into(),
+			..Source::default()
 		};
 		state
 	}
@@ -469,28 +905,183 @@ impl ParseState {
 // Utility functions
 // //////////////////////////////////////////////////////////
 
+/// Describe a wrong number of arguments, `expected_max` is `usize::MAX` when there is no upper bound.
 pub fn arity_mismatch(expected_min: usize, expected_max: usize, got: usize) -> String {
 	if expected_min == expected_max {
-		format!["arity mismatch: expected {} but got {}", expected_min, got]
+		format!["arity mismatch: expected {}, got {}", expected_min, got]
 	} else if expected_min < expected_max && expected_min == 0 {
-		format![
-			"arity mismatch: expected <={} but got {}",
-			expected_max,
-			got,
-		]
+		format!["arity mismatch: expected <={}, got {}", expected_max, got]
 	} else if expected_min < expected_max && expected_max == usize::MAX {
-		format![
-			"arity mismatch: expected >={} but got {}",
-			expected_min,
-			got,
-		]
+		format!["arity mismatch: expected >={}, got {}", expected_min, got]
 	} else {
-		format![
-			"arity mismatch: expected >={} and <={} but got {}",
-			expected_min,
-			expected_max,
-			got,
-		]
+		format!["arity mismatch: expected >={} and <={}, got {}", expected_min, expected_max, got]
+	}
+}
+
+// Tags used by the binary format of `serialize` and `deserialize`
+const TAG_NULL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_SYMBOL: u8 = 5;
+const TAG_CELL: u8 = 6;
+const TAG_BYTES: u8 = 7;
+const TAG_ERROR: u8 = 8;
+const TAG_FLOAT: u8 = 9;
+
+/// Serialize data into a compact binary format.
+///
+/// Every datum starts with a tag byte. Integers, strings, symbols, and bytes follow the tag by a
+/// 4 byte big-endian length and that many bytes (integers as big-endian two's complement, text
+/// as UTF-8). A float is followed by the 8 big-endian bytes of its IEEE 754 representation.
+/// A cell is followed by its head and then its tail, an error by its content.
+/// Shared structure is written out once per occurrence. Functions, macros, and other
+/// non-data types can not be serialized.
+pub fn serialize(data: &Statement) -> Result<Vec<u8>, String> {
+	fn length_prefixed(output: &mut Vec<u8>, tag: u8, bytes: &[u8]) -> Result<(), String> {
+		if bytes.len() > u32::MAX as usize {
+			return Err("datum too large to serialize".into());
+		}
+		let length = bytes.len() as u32;
+		output.push(tag);
+		output.extend(&[(length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8]);
+		output.extend(bytes);
+		Ok(())
+	}
+	let mut output = vec![];
+	let mut queue = vec![data.clone()];
+	while let Some(top) = queue.pop() {
+		match top.1 {
+			Coredata::Boolean(false) => output.push(TAG_FALSE),
+			Coredata::Boolean(true) => output.push(TAG_TRUE),
+			Coredata::Bytes(ref bytes) => length_prefixed(&mut output, TAG_BYTES, bytes)?,
+			Coredata::Cell(ref head, ref tail) => {
+				output.push(TAG_CELL);
+				queue.push(tail.clone());
+				queue.push(head.clone());
+			}
+			Coredata::Error(ref content) => {
+				output.push(TAG_ERROR);
+				queue.push(content.data.clone());
+			}
+			Coredata::Float(ref float) => {
+				output.push(TAG_FLOAT);
+				let bits = float.0.to_bits();
+				output.extend((0..8).rev().map(|byte| (bits >> (byte * 8)) as u8));
+			}
+			Coredata::Integer(ref integer) => {
+				length_prefixed(&mut output, TAG_INTEGER, &integer.to_signed_bytes_be())?
+			}
+			Coredata::Null() => output.push(TAG_NULL),
+			Coredata::String(ref string) => length_prefixed(&mut output, TAG_STRING, string.as_bytes())?,
+			Coredata::Symbol(ref symbol) => {
+				length_prefixed(&mut output, TAG_SYMBOL, Into::<&str>::into(symbol).as_bytes())?
+			}
+			_ => {
+				return Err(format!["unable to serialize {}", data_name(&top)]);
+			}
+		}
+	}
+	Ok(output)
+}
+
+/// Deserialize data written by `serialize`.
+///
+/// The input must contain exactly one datum.
+pub fn deserialize(input: &[u8]) -> Result<Statement, String> {
+	use num::BigInt;
+	enum Pending {
+		Head,
+		Tail(Statement),
+		Error,
+	}
+	let mut position = 0;
+	let mut pending = vec![];
+	loop {
+		let tag = if let Some(tag) = input.get(position) {
+			*tag
+		} else {
+			return Err(format!["unexpected end of input at byte {}", position]);
+		};
+		position += 1;
+		let mut value = match tag {
+			TAG_NULL => rcs(Coredata::Null()),
+			TAG_FALSE => rcs(Coredata::Boolean(false)),
+			TAG_TRUE => rcs(Coredata::Boolean(true)),
+			TAG_CELL => {
+				pending.push(Pending::Head);
+				continue;
+			}
+			TAG_ERROR => {
+				pending.push(Pending::Error);
+				continue;
+			}
+			TAG_FLOAT => {
+				if position + 8 > input.len() {
+					return Err(format!["unexpected end of input at byte {}", position]);
+				}
+				let bits = input[position..position + 8]
+					.iter()
+					.fold(0u64, |bits, byte| (bits << 8) | u64::from(*byte));
+				position += 8;
+				rcs(Coredata::Float(Float(f64::from_bits(bits))))
+			}
+			TAG_INTEGER | TAG_STRING | TAG_SYMBOL | TAG_BYTES => {
+				if position + 4 > input.len() {
+					return Err(format!["unexpected end of input at byte {}", position]);
+				}
+				let length = input[position..position + 4]
+					.iter()
+					.fold(0usize, |length, byte| (length << 8) | *byte as usize);
+				position += 4;
+				if position + length > input.len() {
+					return Err(format!["unexpected end of input at byte {}", position]);
+				}
+				let bytes = &input[position..position + length];
+				position += length;
+				match tag {
+					TAG_INTEGER => rcs(Coredata::Integer(BigInt::from_signed_bytes_be(bytes))),
+					TAG_BYTES => rcs(Coredata::Bytes(bytes.to_vec())),
+					_ => {
+						let string = match String::from_utf8(bytes.to_vec()) {
+							Ok(string) => string,
+							Err(_) => {
+								return Err(format!["invalid UTF-8 before byte {}", position]);
+							}
+						};
+						if tag == TAG_STRING {
+							rcs(Coredata::String(string))
+						} else {
+							rcs(Coredata::Symbol(Symbol::from(string)))
+						}
+					}
+				}
+			}
+			_ => {
+				return Err(format!["unknown tag {} at byte {}", tag, position - 1]);
+			}
+		};
+		loop {
+			match pending.pop() {
+				Some(Pending::Head) => {
+					pending.push(Pending::Tail(value));
+					break;
+				}
+				Some(Pending::Tail(head)) => {
+					value = rcs(Coredata::Cell(head, value));
+				}
+				Some(Pending::Error) => {
+					value = rcs(Coredata::Error(EvalError::new(value)));
+				}
+				None => {
+					if position != input.len() {
+						return Err(format!["trailing data at byte {}", position]);
+					}
+					return Ok(value);
+				}
+			}
+		}
 	}
 }
 
@@ -499,6 +1090,9 @@ pub fn not_found(string: &str) -> String {
 }
 
 /// Maps a linked list of data into a vector of data.
+///
+/// The list is walked iteratively, so its length is only bounded by memory. Cells can not be
+/// mutated, so a list can not be cyclic; cycles through boxes and vectors are not followed.
 pub fn collect_cell_into_revvec(data: &Arc<Sourcedata>) -> Vec<Arc<Sourcedata>> {
 	let mut to_return = vec![];
 	let mut current = data.clone();
@@ -565,6 +1159,241 @@ pub fn collect_cell_of_symbols_into_vec(data: &Arc<Sourcedata>) -> Option<Vec<Sy
 /* 	Some(ret) */
 /* } */
 
+/// Reverses a list and all of its sublists.
+///
+/// Uses an explicit stack so deeply nested lists do not overflow the native stack. Sublists
+/// that are shared within the input are reversed once and stay shared in the output.
+pub fn deep_reverse(data: &Statement) -> Statement {
+	struct Frame {
+		source: Statement,
+		remaining: Vec<Statement>,
+		reversed: Statement,
+	}
+	let frame = |data: &Statement| Frame {
+		source: data.clone(),
+		remaining: collect_cell_into_revvec(data),
+		reversed: rcs(Coredata::Null()),
+	};
+	let mut memo: HashMap<*const Sourcedata, Statement> = HashMap::new();
+	let mut stack = vec![frame(data)];
+	loop {
+		let next = if let Some(top) = stack.last_mut() {
+			match top.remaining.pop() {
+				Some(element) => {
+					if let Coredata::Cell(..) = element.1 {
+						if let Some(done) = memo.get(&(&*element as *const Sourcedata)) {
+							top.reversed = rcs(Coredata::Cell(done.clone(), top.reversed.clone()));
+							None
+						} else {
+							Some(element)
+						}
+					} else {
+						top.reversed = rcs(Coredata::Cell(element, top.reversed.clone()));
+						None
+					}
+				}
+				None => {
+					let done = stack.pop().unwrap();
+					memo.insert(&*done.source as *const Sourcedata, done.reversed.clone());
+					if let Some(parent) = stack.last_mut() {
+						parent.reversed = rcs(Coredata::Cell(done.reversed, parent.reversed.clone()));
+					} else {
+						return done.reversed;
+					}
+					None
+				}
+			}
+		} else {
+			return rcs(Coredata::Null());
+		};
+		if let Some(element) = next {
+			stack.push(frame(&element));
+		}
+	}
+}
+
+/// Replaces every occurrence of `old` inside `data` by `new`.
+///
+/// Occurrences are found using structural equality, like `same?`, and are not searched for
+/// inside the replacement. Uses an explicit stack so deeply nested data does not overflow the
+/// native stack. Subtrees without any occurrence are shared with the input.
+pub fn substitute(data: &Statement, old: &Statement, new: &Statement) -> Statement {
+	struct Frame {
+		source: Statement,
+		remaining: Vec<Statement>,
+		done: Vec<Statement>,
+		end: Statement,
+		changed: bool,
+	}
+	let frame = |data: &Statement| {
+		let mut remaining = vec![];
+		let mut current = data.clone();
+		while let Some((head, tail)) = current.head().and_then(|head| current.tail().map(|tail| (head, tail))) {
+			remaining.push(head);
+			current = tail;
+		}
+		remaining.reverse();
+		let (end, changed) = if current == *old {
+			(new.clone(), true)
+		} else {
+			(current, false)
+		};
+		Frame { source: data.clone(), remaining, done: vec![], end, changed }
+	};
+	if data == old {
+		return new.clone();
+	}
+	if let Coredata::Cell(..) = data.1 {
+	} else {
+		return data.clone();
+	}
+	let mut stack = vec![frame(data)];
+	loop {
+		let next = {
+			let top = stack.last_mut().unwrap();
+			match top.remaining.pop() {
+				Some(element) => {
+					if element == *old {
+						top.done.push(new.clone());
+						top.changed = true;
+						None
+					} else if let Coredata::Cell(..) = element.1 {
+						Some(element)
+					} else {
+						top.done.push(element);
+						None
+					}
+				}
+				None => {
+					let done = stack.pop().unwrap();
+					let result = if done.changed {
+						let mut result = done.end;
+						for element in done.done.into_iter().rev() {
+							result = rcs(Coredata::Cell(element, result));
+						}
+						result
+					} else {
+						done.source
+					};
+					if let Some(parent) = stack.last_mut() {
+						parent.changed |= done.changed;
+						parent.done.push(result);
+					} else {
+						return result;
+					}
+					None
+				}
+			}
+		};
+		if let Some(element) = next {
+			stack.push(frame(&element));
+		}
+	}
+}
+
+/// Copies lists, vectors, and boxes so that mutating the copy does not affect the original.
+///
+/// The improper tail of a list is kept as it is. Other data is immutable and is shared, tables
+/// included since they are persistent. Uses an explicit stack so deeply nested data does not
+/// overflow the native stack. Data that is shared or cyclic in the input is copied once, so the
+/// copy keeps the same shape.
+pub fn deep_copy(data: &Statement) -> Statement {
+	enum Container {
+		List(Statement),
+		Vector(Statement),
+		Box(Statement),
+	}
+	struct Frame {
+		source: Statement,
+		copy: Container,
+		remaining: Vec<Statement>,
+		done: Vec<Statement>,
+	}
+	// Either the finished copy or a frame copying the children
+	fn start(data: &Statement, memo: &mut HashMap<*const Sourcedata, Statement>) -> Result<Statement, Frame> {
+		if let Some(done) = memo.get(&(&**data as *const Sourcedata)) {
+			return Ok(done.clone());
+		}
+		let null = rcs(Coredata::Null());
+		let (copy, mut remaining) = match data.1 {
+			Coredata::Cell(..) => {
+				let mut remaining = vec![];
+				let mut current = data.clone();
+				while let Coredata::Cell(ref head, ref tail) = current.clone().1 {
+					remaining.push(head.clone());
+					current = tail.clone();
+				}
+				(Container::List(current), remaining)
+			}
+			Coredata::Vector(ref vector) => {
+				let elements = vector.to_vec();
+				let copy = rcs(Coredata::Vector(Vector::new(vec![null; elements.len()])));
+				memo.insert(&**data as *const Sourcedata, copy.clone());
+				(Container::Vector(copy), elements)
+			}
+			Coredata::Box(ref boxed) => {
+				let copy = rcs(Coredata::Box(Boxed::new(null)));
+				memo.insert(&**data as *const Sourcedata, copy.clone());
+				(Container::Box(copy), vec![boxed.get()])
+			}
+			_ => return Ok(data.clone()),
+		};
+		remaining.reverse();
+		Err(Frame { source: data.clone(), copy, remaining, done: vec![] })
+	}
+	let mut memo = HashMap::new();
+	let mut stack = match start(data, &mut memo) {
+		Ok(done) => return done,
+		Err(frame) => vec![frame],
+	};
+	loop {
+		let next = {
+			let top = stack.last_mut().unwrap();
+			match top.remaining.pop() {
+				Some(element) => Some(element),
+				None => {
+					let done = stack.pop().unwrap();
+					let result = match done.copy {
+						Container::List(end) => {
+							let result = done.done.into_iter().rev().fold(end, |tail, head| {
+								rcs(Coredata::Cell(head, tail))
+							});
+							memo.insert(&*done.source as *const Sourcedata, result.clone());
+							result
+						}
+						Container::Vector(copy) => {
+							if let Coredata::Vector(ref vector) = copy.1 {
+								for (index, element) in done.done.into_iter().enumerate() {
+									vector.set(index, element);
+								}
+							}
+							copy
+						}
+						Container::Box(copy) => {
+							if let (Coredata::Box(ref boxed), Some(content)) = (&copy.1, done.done.into_iter().next()) {
+								boxed.set(content);
+							}
+							copy
+						}
+					};
+					if let Some(parent) = stack.last_mut() {
+						parent.done.push(result);
+					} else {
+						return result;
+					}
+					None
+				}
+			}
+		};
+		if let Some(element) = next {
+			match start(&element, &mut memo) {
+				Ok(done) => stack.last_mut().unwrap().done.push(done),
+				Err(frame) => stack.push(frame),
+			}
+		}
+	}
+}
+
 /// Takes the intersection of two sets.
 pub fn compute_intersection<'a>(a: &'a [String], b: &'a [String]) -> Vec<&'a String> {
 	let mut intersection: Vec<&'a String> = Vec::with_capacity(VEC_CAPACITY);
@@ -587,29 +1416,197 @@ pub fn compute_union(a: &[String], b: &[String]) -> Vec<String> {
 	c
 }
 
+/// Identity as used by `eq?`.
+///
+/// Data is identical if it is the very same object. Because immediates are created anew
+/// each time they are evaluated, booleans, `Null`, symbols, and integers that fit in 64 bits
+/// are compared by value instead. Larger integers are only identical if they are the same
+/// object; use `eqv?` to compare those by value.
+pub fn is_eq(lhs: &Statement, rhs: &Statement) -> bool {
+	use num::ToPrimitive;
+	if Arc::ptr_eq(lhs, rhs) {
+		return true;
+	}
+	match (&lhs.1, &rhs.1) {
+		(Coredata::Boolean(lhs), Coredata::Boolean(rhs)) => lhs == rhs,
+		(Coredata::Char(lhs), Coredata::Char(rhs)) => lhs == rhs,
+		(Coredata::Integer(lhs), Coredata::Integer(rhs)) => {
+			lhs.to_i64().is_some() && lhs == rhs
+		}
+		(Coredata::Null(), Coredata::Null()) => true,
+		(Coredata::Symbol(lhs), Coredata::Symbol(rhs)) => lhs == rhs,
+		_ => false,
+	}
+}
+
+/// Equivalence as used by `eqv?`.
+///
+/// Numbers, characters, and booleans are compared by value, symbols by name, and builtin functions and
+/// macros by their name. `Null` is always equivalent to `Null`. All other data - strings,
+/// cells, errors, bytes, tables, and library functions and macros - are equivalent only if
+/// they are the very same object.
+pub fn is_eqv(lhs: &Statement, rhs: &Statement) -> bool {
+	if Arc::ptr_eq(lhs, rhs) {
+		return true;
+	}
+	match (&lhs.1, &rhs.1) {
+		(Coredata::Boolean(lhs), Coredata::Boolean(rhs)) => lhs == rhs,
+		(Coredata::Char(lhs), Coredata::Char(rhs)) => lhs == rhs,
+		(Coredata::Float(lhs), Coredata::Float(rhs)) => lhs == rhs,
+		(Coredata::Integer(lhs), Coredata::Integer(rhs)) => lhs == rhs,
+		(Coredata::Null(), Coredata::Null()) => true,
+		(Coredata::Rational(lhs), Coredata::Rational(rhs)) => lhs == rhs,
+		(Coredata::Symbol(lhs), Coredata::Symbol(rhs)) => lhs == rhs,
+		(Coredata::Function(Function::Builtin(_, lhs, _)), Coredata::Function(Function::Builtin(_, rhs, _))) |
+		(Coredata::Macro(Macro::Builtin(_, lhs)), Coredata::Macro(Macro::Builtin(_, rhs))) => lhs == rhs,
+		_ => false,
+	}
+}
+
 /// Get the name associated with the data type.
 pub fn data_name(data: &Sourcedata) -> String {
 	match data.1 {
 		Coredata::Boolean(..) => "Boolean",
+		Coredata::Box(..) => "Box",
+		Coredata::Bytes(..) => "Bytes",
 		Coredata::Cell(..) => "Cell",
+		Coredata::Char(..) => "Char",
 		Coredata::Error(..) => "Error",
+		Coredata::Float(..) => "Float",
 		Coredata::Function(Function::Builtin(..)) => "Builtin Function",
 		Coredata::Function(Function::Library(..)) => "Function",
 		Coredata::Integer(..) => "Integer",
 		Coredata::Internal(..) => "Internal",
 		Coredata::Macro(..) => "Macro",
 		Coredata::Null(..) => "Null",
+		Coredata::Rational(..) => "Rational",
 		Coredata::String(..) => "String",
 		Coredata::Symbol(..) => "Symbol",
 		Coredata::Table(..) => "Table",
+		Coredata::Values(..) => "Values",
+		Coredata::Vector(..) => "Vector",
 	}.into()
 }
 
+/// Parse a number literal the way `eval` reads symbols, `None` if it is not a number.
+///
+/// Integers and rationals such as `-1/2` are read in the given radix. Floats are only read in
+/// radix 10, see `parse_float`.
+pub fn parse_number(string: &str, radix: u32) -> Option<Coredata> {
+	use num::{BigInt, BigRational, Zero};
+	if let Some(number) = BigInt::parse_bytes(string.as_bytes(), radix) {
+		Some(Coredata::Integer(number))
+	} else if let Some((numerator, denominator)) = string.split_once('/') {
+		if denominator.starts_with(['+', '-']) {
+			return None;
+		}
+		let numerator = BigInt::parse_bytes(numerator.as_bytes(), radix)?;
+		let denominator = BigInt::parse_bytes(denominator.as_bytes(), radix).filter(|value| !value.is_zero())?;
+		Some(from_rational(BigRational::new(numerator, denominator)))
+	} else if radix == 10 {
+		parse_float(string).map(|number| Coredata::Float(Float(number)))
+	} else {
+		None
+	}
+}
+
+/// Make a rational number, which is an integer if the denominator is one.
+pub fn from_rational(number: num::BigRational) -> Coredata {
+	if number.is_integer() {
+		Coredata::Integer(number.to_integer())
+	} else {
+		Coredata::Rational(number)
+	}
+}
+
+/// Parse a float literal such as `3.14`, `-0.5`, or `1e-3`.
+///
+/// Only digits, signs, periods, and exponents are accepted, so symbols like `inf` and `nan`
+/// are not floats. Integer literals are parsed as `BigInt` before this is tried.
+pub fn parse_float(string: &str) -> Option<f64> {
+	let is_float_character = |ch: char| ch.is_ascii_digit() || "+-.eE".contains(ch);
+	if string.starts_with(|ch: char| ch.is_ascii_digit() || "+-.".contains(ch))
+		&& string.chars().any(|ch| ch.is_ascii_digit())
+		&& string.chars().all(is_float_character)
+	{
+		string.parse().ok()
+	} else {
+		None
+	}
+}
+
+/// Parse a character literal such as `#\a`, `#\space`, `#\newline`, `#\tab`, or `#\u3bb`.
+///
+/// A `u` followed by one to six hexadecimal digits names a character by its code point.
+pub fn parse_char(string: &str) -> Option<char> {
+	let name = string.strip_prefix("#\\")?;
+	let mut characters = name.chars();
+	if let (Some(character), None) = (characters.next(), characters.next()) {
+		return Some(character);
+	}
+	match name {
+		"space" => Some(' '),
+		"newline" => Some('\n'),
+		"tab" => Some('\t'),
+		_ => {
+			let digits = name.strip_prefix('u')?;
+			if (1..=6).contains(&digits.len()) && digits.chars().all(|ch| ch.is_ascii_hexdigit()) {
+				u32::from_str_radix(digits, 16).ok().and_then(std::char::from_u32)
+			} else {
+				None
+			}
+		}
+	}
+}
+
+/// Write a character the way `parse_char` reads it.
+pub fn char_literal(character: char) -> String {
+	match character {
+		' ' => "#\\space".into(),
+		'\n' => "#\\newline".into(),
+		'\t' => "#\\tab".into(),
+		_ if character.is_control() || character.is_whitespace() => {
+			format!["#\\u{:x}", character as u32]
+		}
+		_ => format!["#\\{}", character],
+	}
+}
+
+/// Get the type of data as used by `type-of` and `assert-type`.
+///
+/// Unlike `data_name` this does not distinguish builtin from library functions.
+pub fn type_name(data: &Sourcedata) -> &'static str {
+	match data.1 {
+		Coredata::Boolean(..) => "boolean",
+		Coredata::Box(..) => "box",
+		Coredata::Bytes(..) => "bytes",
+		Coredata::Cell(..) => "cell",
+		Coredata::Char(..) => "char",
+		Coredata::Error(..) => "error",
+		Coredata::Float(..) => "float",
+		Coredata::Function(..) => "function",
+		Coredata::Integer(..) => "integer",
+		Coredata::Internal(..) => "internal",
+		Coredata::Macro(..) => "macro",
+		Coredata::Null(..) => "null",
+		Coredata::Rational(..) => "rational",
+		Coredata::String(..) => "string",
+		Coredata::Symbol(..) => "symbol",
+		Coredata::Table(..) => "table",
+		Coredata::Values(..) => "values",
+		Coredata::Vector(..) => "vector",
+	}
+}
+
 /// Unwind and trace with an error message if it is Some.
 ///
 /// Mixes unwind and tracing from an error's invocation. Any time an unwind
 /// happens `env.result` will contain an error with a string containing the stack
 /// trace an addition to the error provided.
+///
+/// The error is located at its own source if it has one, otherwise at `source`. When neither is
+/// known `Source::default` is used so every error carries a line and column. The error is
+/// preceded by the `backtrace` of calls that led to it.
 pub fn err(
 	source: &Option<Source>,
 	error: &Option<(Option<Source>, String)>,
@@ -617,27 +1614,26 @@ pub fn err(
 	env: &mut Env,
 ) {
 	let error = if let Some((ref src, ref error)) = *error {
-		let mut temp = vec![];
-		if src.is_none() {
-			temp.push(rc(
-				Sourcedata(source.clone(), Coredata::String(error.clone())),
-			));
-		} else {
-			if source != src {
-				temp.push(rc(Sourcedata(
-					source.clone(),
-					Coredata::String("called from here".into()),
-				)));
-			}
-			temp.push(rc(Sourcedata(src.clone(), Coredata::String(error.clone()))));
+		let mut temp: Program = backtrace(program)
+			.into_iter()
+			.map(|(source, frame)| rc(Sourcedata(Some(source), Coredata::String(frame))))
+			.collect();
+		if src.is_some() && source.is_some() && source != src {
+			temp.push(rc(Sourcedata(
+				source.clone(),
+				Coredata::String("called from here".into()),
+			)));
 		}
+		let src = src.clone().or_else(|| source.clone()).or_else(|| Some(Source::default()));
+		temp.push(rc(Sourcedata(src.clone(), Coredata::String(error.clone()))));
 		let trace = internal_trace(&mut temp, env);
-		Some(trace)
+		let error = EvalError { message: Some(error.clone()), source: src.clone(), cause: None, data: trace };
+		Some(rc(Sourcedata(src, Coredata::Error(error))))
 	} else {
 		None
 	};
 	if let Some(error) = error {
-		env.params.push(vec![rcs(Coredata::Error(error))]);
+		env.params.push(vec![error]);
 		unwind(program, env);
 		if env.params.pop().is_none() {
 			panic!["Stack corruption"];
@@ -645,6 +1641,34 @@ pub fn err(
 	}
 }
 
+/// Get the calls in progress, outermost first.
+///
+/// A call is in progress while its arguments are evaluated (`calling`) and, for functions and
+/// macros written in Teko, while its code runs (`in call`). Tail calls replace the call they
+/// are in, and calls without a source are left out.
+pub fn backtrace(program: &Program) -> Vec<(Source, String)> {
+	let mut frames = vec![];
+	for statement in program {
+		if let Sourcedata(Some(ref source), Coredata::Internal(ref command)) = **statement {
+			match *command {
+				Commands::Call(ref function) => {
+					let name = if let Coredata::Function(Function::Builtin(_, ref name, _)) = function.1 {
+						name.as_str()
+					} else {
+						"function"
+					};
+					frames.push((source.clone(), format!["calling {}", name]));
+				}
+				Commands::Deparize(..) => {
+					frames.push((source.clone(), "in call".into()));
+				}
+				_ => {}
+			}
+		}
+	}
+	frames
+}
+
 /// Create a string of the entire program stack.
 pub fn internal_trace(program: &mut Program, _: &mut Env) -> Arc<Sourcedata> {
 	use data_structures::Coredata::*;
@@ -666,43 +1690,19 @@ pub fn internal_trace(program: &mut Program, _: &mut Env) -> Arc<Sourcedata> {
 	lst
 }
 
-/// Optimizes tail calls by seeing if the current `params` can be merged with the top of the stack.
+/// Run the code of a call in `scope`, putting back the current scope once it is done.
 ///
-/// If the top of the stack contains `Commands::Deparize`, then the variables to be popped
-/// are merged into that [top] object. This is all that's needed to optimize tail calls.
-pub fn optimize_tail_call(program: &mut Program, env: &mut Env, params2: &[Symbol]) -> Deparize {
-	if let Some(top) = program.pop() {
-		match top.1 {
-			Coredata::Internal(Commands::Deparize(ref content2)) => {
-				let mut content = content2.clone();
-				for i in params2 {
-					if content.check_preexistence_and_merge_single(i) {
-						if env.pop(i).is_some() {
-							// OK
-						} else {
-							panic!["Store inconsistency; entry empty"];
-						}
-					}
-				}
-				// TODO remove this clone, quite unnecessary
-				content.clone()
-			}
-			_ => {
-				let mut deparize = Deparize::default();
-				program.push(top.clone()); // Put top back on the program stack
-				for i in params2 {
-					deparize.check_preexistence_and_merge_single(i);
-				}
-				deparize
-			}
-		}
-	} else {
-			let mut deparize = Deparize::default();
-			for i in params2 {
-				deparize.check_preexistence_and_merge_single(i);
-			}
-			deparize
+/// A call ends with `Commands::Deparize`, which puts back the scope of its caller. If that is
+/// already on top of the stack then the call is a tail call and nothing is left to do in the
+/// current scope, so the call ends with that `Deparize` instead of a new one. This is all that's
+/// needed to optimize tail calls.
+pub fn enter_scope(program: &mut Program, env: &mut Env, source: &Option<Source>, scope: Scope) {
+	let tail_call = matches![program.last().map(|top| &top.1), Some(&Coredata::Internal(Commands::Deparize(..)))];
+	if !tail_call {
+		let frame = Deparize { scope: env.scope.take() };
+		program.push(rc(Sourcedata(source.clone(), Coredata::Internal(Commands::Deparize(frame)))));
 	}
+	env.scope = Some(Arc::new(scope));
 }
 
 pub fn optional_source(source: &Option<Source>) -> String {
@@ -714,22 +1714,6 @@ pub fn optional_source(source: &Option<Source>) -> String {
 }
 
 
-// TODO change from panic to unwind, but can we be safe about such a serious error by
-// unwinding? Maybe a stop function that freezes the interpreter...
-/// Pops the specified parameters from the stack.
-///
-/// If the parameters do not exist then there's an internal programmer error and
-/// this function will panic.
-pub fn pop_parameters(_: &mut Program, env: &mut Env, args: &Deparize) {
-	for arg in args.iter() {
-		if env.pop(arg).is_some() {
-			// OK
-		} else {
-			panic!["Store entry does not exist"];
-		}
-	}
-}
-
 /// Alias for `Rc::new(_)`.
 pub fn rc<T>(rc: T) -> Arc<T> {
 	Arc::new(rc)
@@ -740,15 +1724,6 @@ pub fn rcs(rcs: Coredata) -> Arc<Sourcedata> {
 	rc(Sourcedata(None, rcs))
 }
 
-pub fn find_earliest_depar(program: &mut Program) -> Option<&mut Deparize> {
-	for i in program.iter_mut().rev() {
-		if let Some(&mut Sourcedata(_, Coredata::Internal(Commands::Deparize(ref mut dep)))) = Arc::get_mut(i) {
-			return Some(dep);
-		}
-	}
-	None
-}
-
 /// Unwinds the stack until first wind is encountered.
 ///
 /// Preserves stack consistency (pops parameters when necessary).
@@ -766,8 +1741,8 @@ pub fn unwind(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, S
 	env.set_result(result);
 	while let Some(top) = program.pop() {
 		match top.1 {
-			Coredata::Internal(Commands::Deparize(ref arguments)) => {
-				pop_parameters(program, env, arguments);
+			Coredata::Internal(Commands::Deparize(ref frame)) => {
+				env.scope = frame.scope.clone();
 			}
 			Coredata::Internal(Commands::Call(..)) => {
 				env.params.pop();
@@ -783,6 +1758,134 @@ pub fn unwind(program: &mut Program, env: &mut Env) -> Option<(Option<Source>, S
 
 #[cfg(test)]
 mod tests {
+	#[test]
+	fn serialization_format() {
+		use super::*;
+		let data = rcs(Coredata::Cell(rcs(Coredata::Integer((-2).into())), rcs(Coredata::Null())));
+		let bytes = serialize(&data).unwrap();
+		assert_eq![bytes, vec![6, 3, 0, 0, 0, 1, 0xFE, 0]];
+		assert_eq![deserialize(&bytes).unwrap(), data];
+		assert![deserialize(&bytes[..5]).is_err()];
+		assert![deserialize(&[0, 0]).is_err()];
+	}
+	#[test]
+	fn deep_copies() {
+		use super::*;
+		let null = rcs(Coredata::Null());
+		let boxed = rcs(Coredata::Box(Boxed::new(null.clone())));
+		if let Coredata::Box(ref inner) = boxed.1 {
+			inner.set(rcs(Coredata::Cell(boxed.clone(), null.clone())));
+		}
+		let shared = rcs(Coredata::Cell(rcs(Coredata::Integer(1.into())), rcs(Coredata::Integer(2.into()))));
+		let list = rcs(Coredata::Cell(boxed.clone(), rcs(Coredata::Cell(shared.clone(), rcs(Coredata::Cell(shared, null))))));
+		let copy = deep_copy(&list);
+		let elements = collect_cell_into_revvec(&copy);
+		assert![Arc::ptr_eq(&elements[0], &elements[1])];
+		assert![!Arc::ptr_eq(&elements[2], &boxed)];
+		assert_eq![elements[1].tail(), Some(rcs(Coredata::Integer(2.into())))];
+		if let Coredata::Box(ref inner) = elements[2].1 {
+			assert![Arc::ptr_eq(&inner.get().head().unwrap(), &elements[2])];
+		} else {
+			panic!["expected a box"];
+		}
+	}
+	#[test]
+	fn display_long_and_deep_data() {
+		use super::*;
+		let null = || rcs(Coredata::Null());
+		let long = (0..100_000).fold(null(), |tail, n| rcs(Coredata::Cell(rcs(Coredata::Integer(n.into())), tail)));
+		let written = format!["{}", long];
+		assert![written.starts_with("(list 99999 99998 ")];
+		assert![written.ends_with(" 1 0)")];
+		let deep = (0..100_000).fold(null(), |nested, _| rcs(Coredata::Cell(nested, null())));
+		let written = format!["{}", deep];
+		assert_eq![written.len(), "(list ".len() * 100_000 + ")".len() * 100_000 + "()".len()];
+	}
+	#[test]
+	fn cyclic_equality() {
+		use super::*;
+		let cycle = || {
+			let vector = rcs(Coredata::Vector(Vector::new(vec![rcs(Coredata::Null())])));
+			if let Coredata::Vector(ref inner) = vector.1 {
+				inner.set(0, vector.clone());
+			}
+			vector
+		};
+		let (first, second) = (cycle(), cycle());
+		let nested = rcs(Coredata::Vector(Vector::new(vec![rcs(Coredata::Vector(Vector::new(vec![rcs(Coredata::Null())])))])));
+		assert_eq![first, first];
+		assert_eq![first, second];
+		assert_ne![first, nested];
+		let long = |last: i32| {
+			(0..100_000).fold(rcs(Coredata::Integer(last.into())), |tail, _| rcs(Coredata::Cell(rcs(Coredata::Null()), tail)))
+		};
+		assert_eq![long(1), long(1)];
+		assert_ne![long(1), long(2)];
+		let through_table = |value: i32| {
+			let vector = rcs(Coredata::Vector(Vector::new(vec![rcs(Coredata::Null())])));
+			let mut table = Table::default();
+			table = table.insert(Table::key(&Sourcedata(None, Coredata::Integer(0.into()))).unwrap(), vector.clone());
+			table = table.insert(Table::key(&Sourcedata(None, Coredata::Integer(1.into()))).unwrap(), rcs(Coredata::Integer(value.into())));
+			if let Coredata::Vector(ref inner) = vector.1 {
+				inner.set(0, rcs(Coredata::Table(table)));
+			}
+			vector
+		};
+		assert_eq![through_table(1), through_table(1)];
+		assert_ne![through_table(1), through_table(2)];
+	}
+	#[test]
+	fn write_cyclic_data() {
+		use super::*;
+		use std::collections::hash_map::DefaultHasher;
+		use std::hash::{Hash, Hasher};
+		let vector = rcs(Coredata::Vector(Vector::new(vec![rcs(Coredata::Integer(0.into()))])));
+		if let Coredata::Vector(ref inner) = vector.1 {
+			inner.set(0, vector.clone());
+		}
+		let boxed = rcs(Coredata::Box(Boxed::new(rcs(Coredata::Null()))));
+		if let Coredata::Box(ref inner) = boxed.1 {
+			inner.set(boxed.clone());
+		}
+		assert_eq![format!["{}", vector], "(vector (@ #<cycle>))"];
+		assert_eq![format!["{}", boxed], "(make-box (@ #<cycle>))"];
+		assert_eq![::print::to_string(&vector), "#(#<cycle>)"];
+		assert_eq![::print::to_string(&boxed), "(make-box (@ #<cycle>))"];
+		assert![format!["{:?}", vector].contains("#<cycle>")];
+		assert![format!["{:?}", boxed].contains("#<cycle>")];
+		let twice = rcs(Coredata::Vector(Vector::new(vec![boxed.clone(), boxed.clone()])));
+		assert_eq![format!["{}", twice], "(vector (make-box (@ #<cycle>)) (make-box (@ #<cycle>)))"];
+		vector.hash(&mut DefaultHasher::new());
+		boxed.hash(&mut DefaultHasher::new());
+	}
+	#[test]
+	fn float_literals() {
+		use super::*;
+		assert_eq![parse_float("3.14"), Some(3.14)];
+		assert_eq![parse_float("-.5"), Some(-0.5)];
+		assert_eq![parse_float("1e-3"), Some(0.001)];
+		assert_eq![parse_float("inf"), None];
+		assert_eq![parse_float("NaN"), None];
+		assert_eq![parse_float("e5"), None];
+		assert_eq![parse_float("-"), None];
+		assert_eq![parse_float("1-2"), None];
+	}
+	#[test]
+	fn char_literals() {
+		use super::*;
+		assert_eq![parse_char("#\\a"), Some('a')];
+		assert_eq![parse_char("#\\space"), Some(' ')];
+		assert_eq![parse_char("#\\u3bb"), Some('λ')];
+		assert_eq![parse_char("#\\u"), Some('u')];
+		assert_eq![parse_char("#\\ud800"), None];
+		assert_eq![parse_char("#\\u+41"), None];
+		assert_eq![parse_char("#\\spaces"), None];
+		assert_eq![parse_char("#\\"), None];
+		assert_eq![parse_char("a"), None];
+		for character in vec!['a', '(', ' ', '\n', '\t', '\u{0}', 'λ'] {
+			assert_eq![parse_char(&char_literal(character)), Some(character)];
+		}
+	}
 	fn test_string(input: &str, output: &str) {
 		use data_structures::{Coredata, Sourcedata};
 		assert_eq![output, format!["{}", Sourcedata(None, Coredata::String(input.to_string()))]];
@@ -805,4 +1908,44 @@ mod tests {
 		test_string("A\n\nBC\t", "(\" A(10 2)BC(9))");
 		test_string("A\nD\nBC\t", "(\" A(10)D(10)BC(9))");
 	}
+	#[test]
+	fn native_values() {
+		use super::*;
+		use num::BigInt;
+		assert_eq![rcs(Coredata::Integer((-42).into())).as_i64(), Ok(-42)];
+		let big = rcs(Coredata::Integer(BigInt::from(i64::MAX) + 1));
+		assert_eq![big.as_i64(), Err("integer 9223372036854775808 does not fit in an i64".into())];
+		assert_eq![rcs(Coredata::String("7".into())).as_i64(), Err("expected Integer but got String".into())];
+		assert_eq![rcs(Coredata::String("a b".into())).as_str(), Ok("a b")];
+		assert_eq![rcs(Coredata::Null()).as_str(), Err("expected String but got Null".into())];
+		assert_eq![rcs(Coredata::Boolean(true)).as_bool(), Ok(true)];
+		assert![rcs(Coredata::Integer(0.into())).as_bool().is_err()];
+	}
+	#[test]
+	fn from_native_values() {
+		use super::*;
+		let integer: Statement = rc(42i64.into());
+		assert_eq![integer.1, Coredata::Integer(42.into())];
+		assert_eq![integer.as_i64(), Ok(42)];
+		let list: Statement = rc(vec![rc(1.into()), rc("x".into()), rc(vec![].into())].into());
+		let elements = collect_cell_into_revvec(&list);
+		assert_eq![elements.len(), 3];
+		assert_eq![elements[2].as_i64(), Ok(1)];
+		assert_eq![elements[1].as_str(), Ok("x")];
+		assert_eq![elements[0].1, Coredata::Null()];
+		assert_eq![format!["{}", list], "(list 1 (\" x) ())"];
+		assert_eq![Sourcedata::from(false).as_bool(), Ok(false)];
+	}
+	#[test]
+	fn error_writing() {
+		use super::*;
+		let symbol = rcs(Coredata::Symbol(Symbol::from("a")));
+		let tail = rcs(Coredata::Cell(rcs(Coredata::Integer(2.into())), rcs(Coredata::Null())));
+		let pair = rcs(Coredata::Cell(rcs(Coredata::Integer(1.into())), tail));
+		assert_eq![format!["{}", Sourcedata(None, Coredata::Error(EvalError::new(symbol.clone())))], "(make-error (@ a))"];
+		assert_eq![format!["{}", Sourcedata(None, Coredata::Error(EvalError::new(pair)))], "(make-error (list 1 2))"];
+		assert_eq![format!["{}", Sourcedata(None, Coredata::Error(EvalError::new(rcs(Coredata::Null()))))], "(make-error)"];
+		let source = Source { line: 3, column: 7, source: "tty".into(), ..Source::default() };
+		assert_eq![format!["{}", Sourcedata(Some(source), Coredata::Error(EvalError::new(symbol)))], "(make-error (@ a)) at 3:7:tty"];
+	}
 }
@@ -3,11 +3,12 @@ extern crate teko;
 
 use std::sync::Arc;
 
-use teko::data_structures::Coredata;
+use teko::data_structures::{Coredata, Float};
 use teko::interpret::interpret;
 use teko::parse::parse_file;
+use teko::repl;
 
-use num::BigInt;
+use num::{BigInt, BigRational};
 
 #[test]
 fn main() {
@@ -18,27 +19,1101 @@ fn main() {
 	boolean("boolean-4.tko", true);
 	boolean("boolean-5.tko", false);
 	boolean("boolean-6.tko", true);
+	boolean("boolean-7.tko", true);
+	boolean("boolean-8.tko", true);
+	boolean("boolean-9.tko", true);
+	boolean("list-tail-1.tko", true);
+	boolean("exact-0.tko", true);
+	boolean("inexact-0.tko", true);
 
 	boolean("local-does-not-leak.tko", true);
 	boolean("local-does-not-leak-tail.tko", true);
 	boolean("local-does-not-leak-active.tko", true);
 
+	boolean("try-0.tko", true);
+	boolean("try-1.tko", true);
+	boolean("try-raised.tko", true);
+	boolean("try-returned-error.tko", true);
+
+	boolean("string-interpolate-0.tko", true);
+	boolean("string-interpolate-1.tko", true);
+
+	boolean("deep-reverse-0.tko", true);
+	boolean("deep-reverse-1.tko", true);
+
+	boolean("serialize-0.tko", true);
+	boolean("serialize-1.tko", true);
+	boolean("serialize-2.tko", true);
+
+	boolean("eqv-0.tko", true);
+	boolean("eqv-1.tko", false);
+	boolean("eqv-2.tko", true);
+	boolean("eqv-3.tko", false);
+	boolean("eqv-4.tko", true);
+	boolean("null-0.tko", true);
+	boolean("null-1.tko", false);
+	boolean("pair-0.tko", true);
+	boolean("pair-1.tko", false);
+	boolean("integer-0.tko", false);
+	boolean("integer-1.tko", true);
+	boolean("type-predicates-0.tko", true);
+	boolean("type-of-0.tko", true);
+	boolean("assert-type-0.tko", true);
+	boolean("gensym-0.tko", false);
+	boolean("gensym-1.tko", true);
+	boolean("define-macro-1.tko", true);
+	boolean("raise-1.tko", true);
+	boolean("float-5.tko", true);
+	boolean("float-compare.tko", true);
+	boolean("min-max-0.tko", true);
+	boolean("gcd-lcm-0.tko", true);
+	boolean("set-1.tko", true);
+	boolean("closure-2.tko", true);
+	boolean("do-1.tko", true);
+	boolean("let-0.tko", true);
+	boolean("let-sequential-1.tko", true);
+	boolean("if-let-1.tko", true);
+	boolean("truthy-zero.tko", true);
+	boolean("truthy-empty-string.tko", true);
+	boolean("truthy-empty-list.tko", true);
+	boolean("truthy-only-false.tko", true);
+	boolean("letrec-0.tko", true);
+	boolean("float-serialize.tko", true);
+	boolean("fold-1.tko", true);
+	boolean("fold-2.tko", true);
+	boolean("fold-3.tko", true);
+	boolean("foldr-0.tko", true);
+	boolean("foldr-1.tko", true);
+	boolean("foldr-long.tko", true);
+	boolean("filter-0.tko", true);
+	boolean("filter-1.tko", true);
+	boolean("filter-2.tko", true);
+	boolean("dotted-pair-0.tko", true);
+	boolean("string-dot-0.tko", true);
+	boolean("string-dot-1.tko", true);
+	boolean("string-dot-2.tko", true);
+	boolean("vector-1.tko", true);
+	boolean("vector-2.tko", true);
+	boolean("table-0.tko", true);
+	boolean("table-default.tko", true);
+	boolean("table-keys.tko", true);
+	boolean("char-1.tko", true);
+
+	boolean("begin-empty.tko", true);
+
+	boolean("pretty-print-0.tko", true);
+	boolean("pretty-print-1.tko", true);
+
+	boolean("unless-0.tko", true);
+	boolean("when-1.tko", true);
+
+	boolean("string-append-0.tko", true);
+	boolean("string-append-1.tko", true);
+
+	boolean("subst-0.tko", true);
+	boolean("subst-1.tko", true);
+	boolean("subst-2.tko", true);
+
+	boolean("symbol-string-0.tko", true);
+	boolean("symbol-string-1.tko", true);
+	boolean("symbol-string-2.tko", true);
+
+	boolean("box-identity.tko", true);
+
+	boolean("eq-0.tko", true);
+	boolean("eq-1.tko", false);
+	boolean("eq-2.tko", true);
+	boolean("not-eq-0.tko", true);
+	boolean("not-eq-1.tko", false);
+	boolean("not-eq-2.tko", false);
+	boolean("not-eq-3.tko", true);
+	boolean("equal-0.tko", true);
+	boolean("equal-1.tko", false);
+
+	boolean("digits-0.tko", true);
+	boolean("digits-1.tko", true);
+	boolean("digits-2.tko", true);
+
 	error("divide-by-zero.tko");
 	error("define-0.tko");
 	error("define-1.tko");
 	error("define-2.tko");
+	error("define-twice.tko");
+	error("define-builtin-alias.tko");
+	error("string-interpolate-2.tko");
+	error("try-handler-error.tko");
+	error("try-not-callable.tko");
+	error("string-interpolate-3.tko");
+	error("modulo-by-zero.tko");
+	error("modulo-non-integer.tko");
+	error("quotient-by-zero.tko");
+	error("deep-reverse-non-list.tko");
+	error("eval-not-callable.tko");
+	error("rec-builtin.tko");
+	error("serialize-function.tko");
+	error("deserialize-non-bytes.tko");
+	error("when-empty.tko");
+	error("expt-negative.tko");
+	error("string-append-non-string.tko");
+	error("string-length-non-string.tko");
+	error("symbol-to-string-non-symbol.tko");
+	error("string-to-symbol-non-string.tko");
+	error("box-ref-non-box.tko");
+	error("digits-negative.tko");
+	error("assert-type-mismatch.tko");
+	error("define-macro-no-body.tko");
+	error("arity-mismatch.tko");
+	error("raise-0.tko");
+	error("raise-non-string.tko");
+	error("float-non-number.tko");
+	error("fold-not-callable.tko");
+	error("not-eq-not-number.tko");
+	error("foldr-not-callable.tko");
+	error("filter-not-callable.tko");
+	error("vector-out-of-bounds.tko");
+	error("vector-negative-index.tko");
+	error("vector-set-out-of-bounds.tko");
+	error("table-missing-key.tko");
+	error("table-unhashable-key.tko");
+	error("char-invalid.tko");
+	error("char-too-large.tko");
+	error("load-missing.tko");
+	error("load-parse-error.tko");
+	error("min-no-arguments.tko");
+	error("max-non-number.tko");
+	error("gcd-non-integer.tko");
+	error("lcm-non-integer.tko");
+	error("set-undefined.tko");
+	error("do-bad-binding.tko");
+	error("do-bad-test.tko");
+	error("let-bad-binding.tko");
+	error("let-sequential-bad-binding.tko");
+	error("if-let-bad-binding.tko");
+	error("if-let-arity.tko");
+	error("letrec-uninitialized.tko");
+	error("cons-star-no-arguments.tko");
+	error("list-ref-out-of-range.tko");
+	error("list-ref-negative.tko");
+	error("list-tail-out-of-range.tko");
+	error("sort-not-callable.tko");
+	error("copy-list-not-list.tko");
+	error("inexact-to-exact-nan.tko");
+	error("string-ref-out-of-range.tko");
+	error("from-digits-invalid.tko");
 
 	integer("addition-0.tko", "0");
 	integer("addition-1.tko", "0");
 	integer("addition-2.tko", "3");
 	integer("addition-3.tko", "6");
 	integer("addition-4.tko", "10");
+
+	integer("modulo-0.tko", "1");
+	integer("modulo-1.tko", "2");
+	integer("modulo-2.tko", "-2");
+	integer("quotient-0.tko", "2");
+	integer("quotient-1.tko", "-2");
+
+	integer("eval-0.tko", "3");
+	integer("eval-1.tko", "10");
+	integer("define-order.tko", "2");
+	integer("define-builtin.tko", "5");
+
+	integer("rec-0.tko", "120");
+	integer("rec-1.tko", "0");
+
+	integer("try-2.tko", "3");
+
+	integer("begin-0.tko", "3");
+	integer("begin-1.tko", "2");
+
+	integer("when-0.tko", "42");
+	integer("when-2.tko", "3");
+	integer("unless-1.tko", "2");
+
+	integer("expt-0.tko", "1024");
+	integer("expt-1.tko", "1");
+	integer("factorial-0.tko", "120");
+	integer("factorial-1.tko", "1");
+
+	integer("string-length-0.tko", "5");
+	integer("string-length-1.tko", "0");
+
+	integer("box-0.tko", "5");
+	integer("box-1.tko", "2");
+
+	integer("from-digits-0.tko", "12345");
+	integer("define-macro-0.tko", "7");
+	integer("fold-0.tko", "10");
+	integer("vector-0.tko", "20");
+	integer("char-0.tko", "65");
+	integer("char-newline.tko", "10");
+	integer("load-0.tko", "42");
+	integer("max-0.tko", "3");
+	integer("gcd-0.tko", "6");
+	integer("lcm-0.tko", "12");
+	integer("abs-0.tko", "5");
+	integer("set-0.tko", "2");
+	integer("closure-0.tko", "7");
+	integer("closure-1.tko", "42");
+	integer("closure-3.tko", "3");
+	integer("closure-let.tko", "7");
+	integer("do-0.tko", "15");
+	integer("let-named-0.tko", "120");
+	integer("let-sequential-0.tko", "2");
+	integer("if-let-0.tko", "30");
+	integer("letrec-1.tko", "2");
+	integer("if-boolean-0.tko", "2");
+	integer("builtin-cache-set.tko", "-1");
+	integer("builtin-cache-let.tko", "2");
+	integer("builtin-cache-parameter.tko", "12");
+	integer("cons-star-0.tko", "3");
+	integer("cons-star-1.tko", "7");
+	integer("list-ref-0.tko", "20");
+	integer("list-tail-0.tko", "30");
+	integer("assoc-0.tko", "20");
+	integer("member-0.tko", "4");
+	integer("sort-0.tko", "7");
+	integer("copy-list-0.tko", "4");
+	integer("inexact-to-exact-0.tko", "-3");
+
+	float("float-0.tko", 3.14);
+	float("float-1.tko", 3.5);
+	float("float-2.tko", 1.0 / 3.0);
+	float("float-3.tko", 0.001);
+	float("float-4.tko", -0.5);
+	float("min-0.tko", 0.25);
+	float("max-inexact.tko", 3.0);
+	float("max-rational-inexact.tko", 0.5);
+	float("exact-to-inexact-0.tko", 3.0);
+	float("exact-to-inexact-rational.tko", 0.5);
+
+	rational("inexact-to-exact-fraction.tko", "1/2");
+	rational("min-rational.tko", "1/3");
+}
+
+#[test]
+fn integer_size_limit() {
+	use teko::interpret::{eval, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let program = |code| parse_string(code).ok().unwrap();
+	let mut env = initialize_environment_with_standard_library();
+	env.max_integer_bits = Some(4096);
+	let env = eval(program("(expt 2 1000000000)"), env);
+	assert![is_error(&env.get_result())];
+	let env = eval(program("(factorial 100000000)"), env);
+	assert![is_error(&env.get_result())];
+	let env = eval(program("(expt 2 4000)"), env);
+	assert![!is_error(&env.get_result())];
+	let env = eval(program("(expt 1 1000000000)"), env);
+	assert_eq![env.get_result().1, Coredata::Integer(BigInt::from(1))];
+}
+
+#[test]
+fn saved_environments_reload() {
+	use teko::interpret::{eval, interpret, load_environment, save_environment};
+	use teko::parse::parse_string;
+	let program = |code| parse_string(code).ok().unwrap();
+	let env = interpret(program("
+		(define square (function (n) (* n n)))
+		(define cube (function (n) (* n (square n))))
+		(define names (list (\" a b) (@ c) 1.5 1/2 #\\a))
+		(define greeting (\" hello, world . done))
+		(define counter (make-box (list 1 (vector 2 3))))
+		(define ages (make-table (@ ann) 31))
+		(define failure (make-error (\" bad) (make-error 1)))
+		(define total (+ 1 2))
+		(set! tail (function (x) x))
+	"));
+	let path = std::env::temp_dir().join(format!["teko-saved-environment-{}.tko", std::process::id()]);
+	let path = path.to_str().unwrap();
+	save_environment(&env, path).unwrap();
+	let saved = std::fs::read_to_string(path).unwrap();
+	assert![!saved.contains("(define head ")];
+	let env = load_environment(path).unwrap();
+	std::fs::remove_file(path).unwrap();
+	let checks = [
+		("(cube total)", "27"),
+		("(same? names (list (\" a b) (@ c) 1.5 1/2 #\\a))", "true"),
+		("greeting", "(\" hello, world . done)"),
+		("(same? (box-ref counter) (list 1 (vector 2 3)))", "true"),
+		("(box-set! counter 5)", "5"),
+		("(table-get ages (@ ann))", "31"),
+		("(error-message (error-cause failure))", "false"),
+		("(error-message failure)", "(\" bad)"),
+		("(tail 4)", "4"),
+	];
+	let mut env = env;
+	for (code, expected) in checks.iter() {
+		env = eval(program(code), env);
+		assert_eq![format!["{}", env.get_result()], *expected, "{}", code];
+	}
+}
+
+#[test]
+fn unreadable_environments_are_not_saved() {
+	use teko::interpret::{interpret, save_environment};
+	use teko::parse::parse_string;
+	let path = std::env::temp_dir().join(format!["teko-unreadable-environment-{}.tko", std::process::id()]);
+	let path = path.to_str().unwrap();
+	let cases = [
+		("(define f (let ((n 1)) (function () n)))", "a function that keeps local variables"),
+		("(define b (make-box 0)) (box-set! b (list b))", "data that contains itself"),
+		("(define e (wind (head 1)))", "a raised error"),
+		("(define s (serialize 1))", "bytes"),
+	];
+	for (code, reason) in cases.iter() {
+		let env = interpret(parse_string(code).ok().unwrap());
+		let error = save_environment(&env, path).unwrap_err();
+		assert_eq![error.kind(), std::io::ErrorKind::InvalidData];
+		assert![error.to_string().contains(reason), "{}: {}", code, error];
+		assert![!std::path::Path::new(path).exists()];
+	}
+}
+
+#[test]
+fn step_limit() {
+	use teko::interpret::{eval, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let program = |code| parse_string(code).ok().unwrap();
+	let mut env = initialize_environment_with_standard_library();
+	env.max_steps = Some(10_000);
+	let env = eval(program("(define loop (function () (loop))) (loop)"), env);
+	assert![format!["{}", env.get_result()].contains("step limit exceeded")];
+	assert_eq![env.max_steps, Some(0)];
+}
+
+#[test]
+fn assert_type_names_both_types() {
+	let result = file2result("assert-type-mismatch.tko");
+	let message = format!["{}", result];
+	assert![message.contains("expected integer but got string")];
+}
+
+#[test]
+fn errors_report_line_and_column() {
+	let message = format!["{}", file2result("arity-mismatch.tko")];
+	assert![message.contains("(list 2 4 (\" tests/arity-mismatch.tko)) (\" arity mismatch")];
+	let program = teko::parse::parse_string("(eval (list head 1 2))").ok().unwrap();
+	let message = format!["{}", interpret(program).get_result()];
+	assert![message.contains("(list 1 1 (\" unknown)) (\" arity mismatch")];
+	assert![message.ends_with(") at 1:1:unknown")];
+}
+
+#[test]
+fn arity_mismatches_give_counts() {
+	use teko::interpret::eval_str;
+	let error = eval_str("(define pair (function (first second) (list first second))) (pair 1)").unwrap_err();
+	assert![error.contains("arity mismatch: expected 2, got 1 for parameters first second")];
+	assert![eval_str("((function () 1) 2)").unwrap_err().contains("arity mismatch: expected 0, got 1)")];
+	assert![eval_str("(head 1 2)").unwrap_err().contains("arity mismatch: expected 1, got 2 for head")];
+	assert![eval_str("(display)").unwrap_err().contains("arity mismatch: expected 1, got 0 for display")];
+	let env = teko::interpret::initialize_environment_with_standard_library();
+	let head = env.get(&teko::data_structures::Symbol::from("head")).unwrap();
+	assert![matches![head.1, Coredata::Function(teko::data_structures::Function::Builtin(_, _, Some((1, 1))))]];
+	assert![eval_str("(cons* )").unwrap_err().contains("arity mismatch: expected >=1, got 0")];
+}
+
+#[test]
+fn load_reports_parse_error_location() {
+	let message = format!["{}", file2result("load-parse-error.tko")];
+	assert![message.contains("tests/load-unbalanced.tko")];
+	assert![message.contains("parse error")];
+}
+
+#[test]
+fn registered_builtins_are_callable() {
+	use teko::data_structures::{Env, Program, Source};
+	use teko::interpret::{eval, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	fn double(_: &mut Program, env: &mut Env) -> Option<(Option<Source>, String)> {
+		let value = match env.params.last().and_then(|args| args.first()).map(|arg| arg.as_i64()) {
+			Some(Ok(value)) => value,
+			Some(Err(error)) => return Some((None, error)),
+			None => return Some((None, "expected an argument".into())),
+		};
+		env.set_result(teko::utilities::rc((value * 2).into()));
+		None
+	}
+	let mut env = initialize_environment_with_standard_library();
+	env.register_builtin("double", double);
+	let env = eval(parse_string("(+ 1 (double 20) (double 0))").ok().unwrap(), env);
+	assert_eq![env.get_result().as_i64(), Ok(41)];
+	let mut env = eval(parse_string("(double (\" x))").ok().unwrap(), env);
+	assert![is_error(&env.get_result())];
+	env.register_builtin("+", double);
+	let env = eval(parse_string("(+ 4)").ok().unwrap(), env);
+	assert_eq![env.get_result().as_i64(), Ok(8)];
+}
+
+#[test]
+fn display_writes_to_output() {
+	use teko::interpret::{eval, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let mut env = initialize_environment_with_standard_library();
+	let captured = capture_output(&mut env);
+	let env = eval(parse_string("(begin (display (\" hi)) (newline))").ok().unwrap(), env);
+	assert![!is_error(&env.get_result())];
+	let env = eval(parse_string("(display (list 1 (\" a b) (@ c)))").ok().unwrap(), env);
+	if let Coredata::Null() = env.get_result().1 {
+	} else {
+		panic!["display should return null"];
+	}
+	assert_eq![String::from_utf8(captured.lock().unwrap().clone()).unwrap(), "hi\n(1 a b c)"];
+}
+
+#[test]
+fn for_each_displays_in_order() {
+	use teko::interpret::{eval, eval_str, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let mut env = initialize_environment_with_standard_library();
+	let captured = capture_output(&mut env);
+	let env = eval(parse_string("(for-each (function (e) (display e) (newline)) (list 1 (\" b) (@ c)))").ok().unwrap(), env);
+	assert_eq![env.get_result().1, Coredata::Null()];
+	assert_eq![String::from_utf8(captured.lock().unwrap().clone()).unwrap(), "1\nb\nc\n"];
+	assert_eq![eval_str("(for-each (function (e) e) (list))").unwrap().1, Coredata::Null()];
+	assert![eval_str("(for-each 1 (list 1))").unwrap_err().contains("expected Function")];
+	assert![eval_str("(for-each (function (e) e) 1)").unwrap_err().contains("expected Cell or Null")];
+}
+
+#[test]
+fn eval_is_silent_unless_verbose() {
+	use teko::interpret::{eval, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let mut env = initialize_environment_with_standard_library();
+	let sink = capture_output(&mut env);
+	assert![!env.verbose];
+	let mut env = eval(parse_string("(+ 1 2) (list 3 4)").ok().unwrap(), env);
+	assert![sink.lock().unwrap().is_empty()];
+	env.verbose = true;
+	eval(parse_string("(list 3 4)").ok().unwrap(), env);
+	assert_eq![String::from_utf8(sink.lock().unwrap().clone()).unwrap(), "(3 4)\n"];
+}
+
+#[test]
+fn output_builtins_write_to_sink() {
+	use teko::interpret::{eval, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let mut env = initialize_environment_with_standard_library();
+	let sink = capture_output(&mut env);
+	env.verbose = true;
+	let env = eval(parse_string("(display 1) (print (\" a) 2) (pp (list 3))").ok().unwrap(), env);
+	assert_eq![String::from_utf8(sink.lock().unwrap().clone()).unwrap(), "1a\n2\n(list 3)\n(3)\n"];
+	let mut env = env;
+	env.verbose = false;
+	env.capture_output();
+	let mut env = eval(parse_string("(display (\" buffered))").ok().unwrap(), env);
+	assert_eq![env.release_output(), Some(b"buffered".to_vec())];
+	assert_eq![env.release_output(), None];
+	let env = eval(parse_string("(display 4)").ok().unwrap(), env);
+	assert![String::from_utf8(sink.lock().unwrap().clone()).unwrap().ends_with("(3)\n4")];
+}
+
+#[test]
+fn output_written_to_files() {
+	use teko::interpret::{eval, eval_str, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let path = std::env::temp_dir().join(format!["teko-output-file-{}.txt", std::process::id()]);
+	let path = path.to_str().unwrap();
+	let mut env = initialize_environment_with_standard_library();
+	let output = capture_output(&mut env);
+	let code = format!["(call-with-output-file (\" {}) (function () (display (\" hello)) (newline) 5))", path];
+	let env = eval(parse_string(&code).ok().unwrap(), env);
+	assert_eq![env.get_result().as_i64(), Ok(5)];
+	assert_eq![std::fs::read_to_string(path).unwrap(), "hello\n"];
+	let code = format!["(error? (wind (call-with-output-file (\" {}) (function () (display 1) (raise (\" boom))))))", path];
+	let env = eval(parse_string(&code).ok().unwrap(), env);
+	assert_eq![env.get_result().1, Coredata::Boolean(true)];
+	assert_eq![std::fs::read_to_string(path).unwrap(), "1"];
+	// A returned error is a result like any other, it is not raised
+	let code = format!["(error? (call-with-output-file (\" {}) (function () (display 3) (make-error 1))))", path];
+	let env = eval(parse_string(&code).ok().unwrap(), env);
+	assert_eq![env.get_result().1, Coredata::Boolean(true)];
+	assert_eq![std::fs::read_to_string(path).unwrap(), "3"];
+	std::fs::remove_file(path).unwrap();
+	eval(parse_string("(display 2)").ok().unwrap(), env);
+	assert_eq![String::from_utf8(output.lock().unwrap().clone()).unwrap(), "2"];
+	let missing = std::env::temp_dir().join("teko-missing-directory").join("file.txt");
+	let code = format!["(call-with-output-file (\" {}) (function () 1))", missing.to_str().unwrap()];
+	assert![eval_str(&code).unwrap_err().contains("unable to open")];
+	assert![eval_str("(call-with-output-file 1 (function () 1))").unwrap_err().contains("expected String")];
+}
+
+#[test]
+fn files_read_as_strings_and_data() {
+	use teko::interpret::eval_str;
+	use teko::print::to_string;
+	let path = std::env::temp_dir().join(format!["teko-read-file-{}.tko", std::process::id()]);
+	let path = path.to_str().unwrap();
+	std::fs::write(path, "(+ 1 2)\nsymbol (\" text)\n").unwrap();
+	let contents = eval_str(&format!["(read-file (\" {}))", path]).unwrap();
+	assert_eq![contents.as_str(), Ok("(+ 1 2)\nsymbol (\" text)\n")];
+	let forms = eval_str(&format!["(read-all (\" {}))", path]).unwrap();
+	assert_eq![to_string(&forms), "((+ 1 2) symbol (\" text))"];
+	std::fs::write(path, "(+ 1").unwrap();
+	assert![eval_str(&format!["(read-all (\" {}))", path]).unwrap_err().contains("Unmatched opening parenthesis")];
+	std::fs::remove_file(path).unwrap();
+	for builtin in &["read-file", "read-all"] {
+		let error = eval_str(&format!["({} (\" {}))", builtin, path]).unwrap_err();
+		assert![error.contains(&format!["unable to read {}: entity not found", path]), "{}", error];
+		assert![eval_str(&format!["({} 1)", builtin]).unwrap_err().contains("expected String")];
+	}
+}
+
+#[test]
+fn output_captured_into_strings() {
+	use teko::interpret::{eval, eval_str, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let captured = |code: &str| eval_str(code).unwrap().as_str().map(String::from);
+	assert_eq![captured("(with-output-to-string (function () (display (\" hi))))"), Ok("hi".into())];
+	assert_eq![captured("(with-output-to-string (function () (write (\" a b))))"), Ok("(\" a b)\n".into())];
+	assert_eq![captured("(with-output-to-string (function () 1))"), Ok("".into())];
+	assert_eq![captured("(with-output-to-string (function () (make-error 1)))"), Ok("".into())];
+	assert_eq![captured("(with-output-to-string (function () (display 1) (make-error 1)))"), Ok("1".into())];
+	assert_eq![
+		captured("(with-output-to-string (function () (display (with-output-to-string (function () (display 1)))) (display 2)))"),
+		Ok("12".into())
+	];
+	assert![eval_str("(with-output-to-string 1)").unwrap_err().contains("expected Function")];
+	let mut env = initialize_environment_with_standard_library();
+	let output = capture_output(&mut env);
+	let env = eval(parse_string("(error? (wind (with-output-to-string (function () (display 1) (raise (\" boom))))))").ok().unwrap(), env);
+	assert_eq![env.get_result().1, Coredata::Boolean(true)];
+	let env = eval(parse_string("(display 2)").ok().unwrap(), env);
+	assert_eq![env.get_result().1, Coredata::Null()];
+	assert_eq![String::from_utf8(output.lock().unwrap().clone()).unwrap(), "2"];
+}
+
+#[test]
+fn clock_and_time() {
+	use teko::interpret::{eval, eval_str, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	assert_eq![eval_str("(define a (clock)) (@msleep 2) (< a (clock))").unwrap().1, Coredata::Boolean(true)];
+	let mut env = initialize_environment_with_standard_library();
+	let captured = capture_output(&mut env);
+	let env = eval(parse_string("(time (+ 1 2))").ok().unwrap(), env);
+	assert_eq![env.get_result().as_i64(), Ok(3)];
+	let written = String::from_utf8(captured.lock().unwrap().clone()).unwrap();
+	assert![written.starts_with("time: ") && written.ends_with(" s\n")];
+}
+
+#[test]
+fn write_reads_back() {
+	use teko::interpret::{eval, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let mut env = initialize_environment_with_standard_library();
+	let captured = capture_output(&mut env);
+	let env = eval(parse_string("(write (list (\" a b) 1 (@ c)) #\\d)").ok().unwrap(), env);
+	assert_eq![env.get_result().1, Coredata::Char('d')];
+	let written = String::from_utf8(captured.lock().unwrap().clone()).unwrap();
+	assert_eq![written, "((\" a b) 1 c)\n#\\d\n"];
+	let read = parse_string(&written).ok().unwrap();
+	assert_eq![read.len(), 2];
+	let list = &read[1];
+	assert_eq![*list, parse_string("((\" a b) 1 c)").ok().unwrap()[0]];
+	let elements: Vec<_> = std::iter::successors(Some(list.clone()), |cell| cell.tail()).filter_map(|cell| cell.head()).collect();
+	// Symbols are read as they are, strings and numbers as the code that evaluates to them
+	assert_eq![elements[2].1, Coredata::Symbol("c".into())];
+	let evaluate = |data: &teko::data_structures::Statement| eval(vec![data.clone()], initialize_environment_with_standard_library()).get_result();
+	assert_eq![evaluate(&elements[0]).1, Coredata::String("a b".into())];
+	assert_eq![evaluate(&elements[1]).1, Coredata::Integer(1.into())];
+	assert![teko::interpret::eval_str("(write)").unwrap_err().contains("arity mismatch")];
+}
+
+#[test]
+fn cons_star_sets_the_final_tail() {
+	use teko::interpret::eval_str;
+	use teko::print::to_string;
+	assert_eq![to_string(&eval_str("(cons* 1 2 3)").unwrap()), "(1 2 . 3)"];
+	assert_eq![to_string(&eval_str("(list* 1 (list 2 3))").unwrap()), "(1 2 3)"];
+	assert_eq![to_string(&eval_str("(cons* (list 1 2))").unwrap()), "(1 2)"];
+}
+
+#[test]
+fn list_index_errors_name_index_and_length() {
+	use teko::interpret::eval_str;
+	let error = eval_str("(list-ref (list 10 20 30) 3)").unwrap_err();
+	assert![error.contains("index 3 out of bounds for list of length 3")];
+	let error = eval_str("(list-tail (list 1) -2)").unwrap_err();
+	assert![error.contains("index -2 out of bounds for list of length 1")];
+}
+
+#[test]
+fn caught_errors_expose_message_and_source() {
+	use teko::interpret::eval_str;
+	use teko::print::to_string;
+	let caught = "(define caught (wind\n  (raise (\" out of range) 7)))";
+	let message = eval_str(&format!["{} (error-message caught)", caught]).unwrap();
+	assert_eq![message.as_str(), Ok("out of range 7")];
+	let source = eval_str(&format!["{} (error-source caught)", caught]).unwrap();
+	assert_eq![to_string(&source), "(2 4 (\" unknown))"];
+	assert_eq![eval_str("(head (error-source (wind (head 1))))").unwrap().as_i64(), Ok(1)];
+	assert_eq![eval_str("(error-message (make-error (\" plain)))").unwrap().as_str(), Ok("plain")];
+	assert_eq![eval_str("(error-message (make-error 1))").unwrap().1, Coredata::Boolean(false)];
+	assert_eq![eval_str("(error-source (make-error 1))").unwrap().1, Coredata::Boolean(false)];
+	assert![eval_str("(error-message 1)").is_err()];
+}
+
+#[test]
+fn errors_have_a_chain_of_causes() {
+	use teko::interpret::{eval_str, interpret};
+	use teko::parse::parse_string;
+	let chained = interpret(parse_string("(make-error 1 (make-error (\" inner) (make-error 3)))").ok().unwrap()).get_result();
+	assert_eq![format!["{}", chained], "(make-error 1 (make-error (\" inner) (make-error 3)))"];
+	if let Coredata::Error(ref error) = chained.1 {
+		let messages: Vec<Option<&str>> = error.causes().map(|cause| cause.message.as_deref()).collect();
+		assert_eq![messages, vec![Some("inner"), None]];
+	} else {
+		panic!["expected an error but got {}", chained];
+	}
+	assert_eq![eval_str("(error-message (error-cause (make-error 1 (make-error (\" inner)))))").unwrap().as_str(), Ok("inner")];
+	assert_eq![eval_str("(error-cause (make-error 1))").unwrap().1, Coredata::Boolean(false)];
+	assert![eval_str("(make-error 1 2)").unwrap_err().contains("expected Error")];
+}
+
+#[test]
+fn letrec_before_initialization() {
+	let message = format!["{}", file2result("letrec-uninitialized.tko")];
+	assert![message.contains("accessed before initialization: b")];
+}
+
+#[test]
+fn multiple_values() {
+	use teko::interpret::eval_str;
+	use teko::print::to_string;
+	assert_eq![eval_str("(call-with-values (function () (values 1 2)) +)").unwrap().as_i64(), Ok(3)];
+	assert_eq![to_string(&eval_str("(call-with-values (function () (values)) list)").unwrap()), "()"];
+	assert_eq![to_string(&eval_str("(call-with-values (function () (values (@ a) (list 2))) list)").unwrap()), "(a (2))"];
+	assert_eq![eval_str("(call-with-values (function () 4) (function (x) (* x x)))").unwrap().as_i64(), Ok(16)];
+	assert_eq![eval_str("(values 5)").unwrap().as_i64(), Ok(5)];
+	assert_eq![format!["{}", eval_str("(values 1 (\" a))").unwrap()], "(values 1 (\" a))"];
+	assert![eval_str("(call-with-values (function () (values 1 2)) (function (x) x))").is_err()];
+	assert![eval_str("(call-with-values 1 +)").is_err()];
+}
+
+#[test]
+fn assertions_unwind_with_message() {
+	use teko::interpret::eval_str;
+	assert_eq![eval_str("(assert (= 1 1) (\" math works))").unwrap().1, Coredata::Null()];
+	assert_eq![eval_str("(assert-equal (list 1 2) (list 1 2))").unwrap().1, Coredata::Null()];
+	let error = eval_str("\n (assert (= 1 2) (\" math is broken))").unwrap_err();
+	assert![error.contains("math is broken")];
+	assert![error.contains("(list 2 3 ")];
+	assert![eval_str("(assert false)").unwrap_err().contains("assertion failed")];
+	assert![eval_str("(assert-equal 1 2)").unwrap_err().contains("expected 1 but got 2")];
+	assert![eval_str("(assert true 1)").is_err()];
+}
+
+#[test]
+fn range_lists_numbers() {
+	use teko::interpret::eval_str;
+	use teko::print::to_string;
+	assert_eq![to_string(&eval_str("(range 0 5 1)").unwrap()), "(0 1 2 3 4)"];
+	assert_eq![to_string(&eval_str("(range 5)").unwrap()), "(0 1 2 3 4)"];
+	assert_eq![to_string(&eval_str("(range 2 5)").unwrap()), "(2 3 4)"];
+	assert_eq![to_string(&eval_str("(range 5 0 -2)").unwrap()), "(5 3 1)"];
+	assert_eq![to_string(&eval_str("(range 0 1 0.25)").unwrap()), "(0.0 0.25 0.5 0.75)"];
+	assert_eq![to_string(&eval_str("(range 0 2.5)").unwrap()), "(0 1 2)"];
+	assert_eq![to_string(&eval_str("(range 3 3)").unwrap()), "()"];
+	assert![eval_str("(range 0 5 0)").unwrap_err().contains("step is zero")];
+	assert![eval_str("(range (\" a))").is_err()];
+}
+
+#[test]
+fn take_and_drop_slice_lists() {
+	use teko::interpret::eval_str;
+	use teko::print::to_string;
+	assert_eq![to_string(&eval_str("(take (list 1 2 3 4) 2)").unwrap()), "(1 2)"];
+	assert_eq![to_string(&eval_str("(drop (list 1 2 3 4) 2)").unwrap()), "(3 4)"];
+	assert_eq![to_string(&eval_str("(take (list 1 2) 0)").unwrap()), "()"];
+	assert_eq![to_string(&eval_str("(take (list 1 2) 2)").unwrap()), "(1 2)"];
+	assert_eq![to_string(&eval_str("(drop (list 1 2) 2)").unwrap()), "()"];
+	assert![eval_str("(take (list 1 2) 3)").unwrap_err().contains("index 3 out of bounds for list of length 2")];
+	assert![eval_str("(take (list 1 2) -1)").is_err()];
+	assert![eval_str("(drop (list 1 2) -1)").is_err()];
+}
+
+#[test]
+fn assoc_and_member_search_lists() {
+	use teko::interpret::eval_str;
+	use teko::print::{display, to_string};
+	assert_eq![display(&eval_str("(assoc 2 (list (list 1 (\" a)) (list 2 (\" b))))").unwrap()), "(2 b)"];
+	assert_eq![display(&eval_str("(assoc (list 1) (list (cons* 1 2) (cons* (list 1) 3)))").unwrap()), "((1) . 3)"];
+	assert_eq![to_string(&eval_str("(assoc 3 (list (list 1 2)))").unwrap()), "false"];
+	assert_eq![to_string(&eval_str("(member 3 (list 1 2 3 4))").unwrap()), "(3 4)"];
+	assert_eq![to_string(&eval_str("(member 5 (list 1 2 3 4))").unwrap()), "false"];
+	assert![eval_str("(assoc 1 (list 1 2))").is_err()];
+}
+
+#[test]
+fn sort_is_stable() {
+	use teko::interpret::eval_str;
+	use teko::print::to_string;
+	assert_eq![to_string(&eval_str("(sort (list 3 1 2) <)").unwrap()), "(1 2 3)"];
+	assert_eq![to_string(&eval_str("(sort (list) <)").unwrap()), "()"];
+	let pairs = "(list (list 1 (@ a)) (list 0 (@ b)) (list 1 (@ c)) (list 0 (@ d)) (list 1 (@ e)))";
+	let sorted = eval_str(&format!["(sort {} (function (x y) (< (head x) (head y))))", pairs]).unwrap();
+	assert_eq![to_string(&sorted), "((0 b) (0 d) (1 a) (1 c) (1 e))"];
+}
+
+#[test]
+fn format_directives() {
+	use teko::interpret::eval_str;
+	let format = |code: &str| eval_str(code).map(|result| result.as_str().unwrap().to_string());
+	assert_eq![format("(format (\" ~a + ~a = ~a) 1 2 3)"), Ok("1 + 2 = 3".into())];
+	assert_eq![format("(format (\" ~a and ~s~%~~) (\" x y) (\" x y))"), Ok("x y and (\" x y)\n~".into())];
+	assert_eq![format("(format (\" ~s) (list 1 (@ a)))"), Ok("(list 1 (@ a))".into())];
+	assert![format("(format (\" ~a ~a) 1)").unwrap_err().contains("format string has 2 directives but got 1 arguments")];
+	assert![format("(format (\" ~a) 1 2)").is_err()];
+	assert![format("(format (\" ~x) 1)").unwrap_err().contains("unknown format directive: ~x")];
+}
+
+#[test]
+fn string_split_and_join() {
+	use teko::interpret::eval_str;
+	use teko::print::to_string;
+	assert_eq![to_string(&eval_str("(string-split (\" a,b,c) (\" ,))").unwrap()), "((\" a) (\" b) (\" c))"];
+	assert_eq![to_string(&eval_str("(string-split (\" ,a,,b) (\" ,))").unwrap()), "((\") (\" a) (\") (\" b))"];
+	let joined = eval_str("(string-join (string-split (\" a,b,,c) (\" ,)) (\" ,))").unwrap();
+	assert_eq![joined.as_str(), Ok("a,b,,c")];
+	assert_eq![eval_str("(string-join (list) (\" ,))").unwrap().as_str(), Ok("")];
+	assert![eval_str("(string-split (\" abc) (\"))").unwrap_err().contains("separator is empty")];
+	assert![eval_str("(string-join (list 1) (\" ,))").is_err()];
+}
+
+#[test]
+fn string_for_each_visits_characters() {
+	use teko::interpret::eval_str;
+	use teko::print::to_string;
+	let visit = |string: &str| eval_str(&format![
+		"(define seen (list)) (string-for-each (function (c) (set! seen (cell c seen))) {}) (deep-reverse seen)", string
+	]);
+	assert_eq![to_string(&visit("(\" abc)").unwrap()), "(#\\a #\\b #\\c)"];
+	assert_eq![to_string(&visit("(\" \u{e9}\u{1f600})").unwrap()), "(#\\\u{e9} #\\\u{1f600})"];
+	assert_eq![to_string(&visit("(\")").unwrap()), "()"];
+	assert![eval_str("(string-for-each (function (c) c) 1)").unwrap_err().contains("expected String")];
+	assert![eval_str("(string-for-each 1 (\" a))").unwrap_err().contains("expected Function")];
+	assert_eq![eval_str("(string-for-each (function (c) c) (\" a))").unwrap().1, Coredata::Null()];
+}
+
+#[test]
+fn number_to_string_radix() {
+	use teko::interpret::eval_str;
+	assert_eq![eval_str("(number->string 255 16)").unwrap().as_str(), Ok("ff")];
+	assert_eq![eval_str("(number->string 10 2)").unwrap().as_str(), Ok("1010")];
+	assert_eq![eval_str("(number->string -8 8)").unwrap().as_str(), Ok("-10")];
+	assert_eq![eval_str("(number->string 42)").unwrap().as_str(), Ok("42")];
+	assert_eq![eval_str("(number->string 1.5)").unwrap().as_str(), Ok("1.5")];
+	assert![eval_str("(number->string 10 3)").unwrap_err().contains("unsupported radix 3")];
+	assert![eval_str("(number->string 1.5 2)").is_err()];
+}
+
+#[test]
+fn string_to_number_reads_literals() {
+	use teko::interpret::eval_str;
+	assert_eq![eval_str("(string->number (\" ff) 16)").unwrap().as_i64(), Ok(255)];
+	assert_eq![eval_str("(string->number (\" -42))").unwrap().as_i64(), Ok(-42)];
+	assert_eq![eval_str("(string->number (\" 1e3))").unwrap().1, Coredata::Float(teko::data_structures::Float(1000.0))];
+	assert_eq![eval_str("(string->number (\" abc))").unwrap().1, Coredata::Boolean(false)];
+	assert_eq![eval_str("(string->number (\" 1.5) 16)").unwrap().1, Coredata::Boolean(false)];
+	assert_eq![eval_str("(string->number (number->string 1010 2) 2)").unwrap().as_i64(), Ok(1010)];
+	assert![eval_str("(string->number (\" 1) 1)").is_err()];
+	assert![eval_str("(string->number (\" z) 36)").unwrap_err().contains("unsupported radix 36")];
+}
+
+#[test]
+fn case_dispatch() {
+	use teko::interpret::eval_str;
+	assert_eq![eval_str("(case 2 ((1) (\" one)) ((2) (\" two)) (else (\" other)))").unwrap().as_str(), Ok("two")];
+	assert_eq![eval_str("(case 9 ((1) (\" one)) ((2) (\" two)) (else (\" other)))").unwrap().as_str(), Ok("other")];
+	assert_eq![eval_str("(case (@ c) (((@ a)) 1) (((@ b) (@ c)) 2 3))").unwrap().as_i64(), Ok(3)];
+	assert_eq![eval_str("(case 3 ((1 2) 1))").unwrap().1, Coredata::Null()];
+	assert_eq![eval_str("(define n 0) (case (begin (set! n (+ n 1)) n) ((2) 0) ((3) 0) (else n))").unwrap().as_i64(), Ok(1)];
+	assert![eval_str("(case 1 (else 2) ((1) 3))").unwrap_err().contains("else clause must be last")];
+	assert![eval_str("(case 1 2)").is_err()];
+}
+
+#[test]
+fn cyclic_vectors_compare() {
+	use teko::interpret::eval_str;
+	let cycles = "(define a (vector 0)) (vector-set! a 0 a) (define b (vector 0)) (vector-set! b 0 b)";
+	assert_eq![eval_str(&format!["{} (equal? a a)", cycles]).unwrap().1, Coredata::Boolean(true)];
+	assert_eq![eval_str(&format!["{} (equal? a b)", cycles]).unwrap().1, Coredata::Boolean(true)];
+	assert_eq![eval_str(&format!["{} (equal? a (vector (vector 0)))", cycles]).unwrap().1, Coredata::Boolean(false)];
+}
+
+#[test]
+fn substring_and_string_ref() {
+	use teko::interpret::eval_str;
+	assert_eq![eval_str("(substring (\" hello) 1 3)").unwrap().as_str(), Ok("el")];
+	assert_eq![eval_str("(substring (\" hello) 5 5)").unwrap().as_str(), Ok("")];
+	assert_eq![eval_str("(substring (\" æøå) 1 3)").unwrap().as_str(), Ok("øå")];
+	assert_eq![eval_str("(char->integer (string-ref (\" aø) 1))").unwrap().as_i64(), Ok(0xF8)];
+	assert![eval_str("(string-ref (\" hi) 5)").unwrap_err().contains("index 5 out of bounds for string of length 2")];
+	assert![eval_str("(string-ref (\" hi) -1)").is_err()];
+	assert![eval_str("(substring (\" hello) 3 1)").unwrap_err().contains("range 3 to 1 is not within string of length 5")];
+	assert![eval_str("(substring (\" hello) 2 6)").is_err()];
+}
+
+#[test]
+fn read_parses_one_datum() {
+	use teko::interpret::eval_str;
+	use teko::print::to_string;
+	assert_eq![to_string(&eval_str("(read (\" (40)+ 1 2(41)))").unwrap()), "(+ 1 2)"];
+	assert_eq![eval_str("(eval (read (\" (40)+ 1 2(41))))").unwrap().as_i64(), Ok(3)];
+	assert_eq![to_string(&eval_str("(read (\" abc def))").unwrap()), "abc"];
+	assert_eq![to_string(&eval_str("(read (\" (40)a(41) (40)))").unwrap()), "(a)"];
+	assert![eval_str("(read (\" (40)+ 1))").unwrap_err().contains("parse error")];
+	assert![eval_str("(read (\"))").unwrap_err().contains("input ended before a complete form")];
+	assert![eval_str("(read 1)").is_err()];
+	let data = "(list 1 (\" a b) (@ c))";
+	let round_trip = format!["(equal? (eval (read (->string {}))) {})", data, data];
+	assert_eq![eval_str(&round_trip).unwrap().as_bool(), Ok(true)];
+}
+
+#[test]
+fn snapshots_discard_bindings() {
+	use teko::data_structures::Symbol;
+	use teko::interpret::{eval, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let env = eval(parse_string("(define y 1)").ok().unwrap(), initialize_environment_with_standard_library());
+	let snapshot = env.snapshot();
+	let mut env = eval(parse_string("(define x 2) (set! y 3) (+ x y)").ok().unwrap(), env);
+	assert_eq![env.get_result().as_i64(), Ok(5)];
+	env.restore(snapshot);
+	assert![!env.does_variable_exist(&Symbol::from("x"))];
+	let env = eval(parse_string("y").ok().unwrap(), env);
+	assert_eq![env.get_result().as_i64(), Ok(1)];
+}
+
+#[test]
+fn long_argument_lists() {
+	let ones = vec!["1"; 100_000].join(" ");
+	let program = teko::parse::parse_string(&format!["(+ {})", ones]).ok().unwrap();
+	assert_eq![interpret(program).get_result().as_i64(), Ok(100_000)];
+	let program = teko::parse::parse_string(&format!["(len (list {}))", ones]).ok().unwrap();
+	assert_eq![interpret(program).get_result().as_i64(), Ok(100_000)];
+}
+
+#[test]
+fn deep_lists_copy() {
+	let depth = 100_000;
+	let code = format!["(copy-list {}{})", "(list ".repeat(depth), ")".repeat(depth)];
+	let program = teko::parse::parse_string(&code).ok().unwrap();
+	let mut current = interpret(program).get_result();
+	let mut levels = 0;
+	while let Some(head) = current.head() {
+		current = head;
+		levels += 1;
+	}
+	assert_eq![levels, depth - 1];
+}
+
+#[test]
+fn backtraces_report_nested_calls() {
+	let message = format!["{}", file2result("backtrace-0.tko")];
+	assert_eq![message.matches("(\" in call)").count(), 2];
+	assert![message.contains("(list (list 4 3 (\" tests/backtrace-0.tko)) (\" calling +))")];
+	assert![message.contains("(list (list 2 3 (\" tests/backtrace-0.tko)) (\" calling +))")];
+	let frames = format!["{}", file2result("backtrace-1.tko")];
+	assert_eq![
+		frames,
+		"(list (\" in call at 5:2:tests/backtrace-1.tko) (\" calling head at 4:3:tests/backtrace-1.tko) \
+		 (\" in call at 4:9:tests/backtrace-1.tko) (\" calling list at 2:3:tests/backtrace-1.tko))"
+	];
+}
+
+#[test]
+fn raise_carries_message() {
+	use teko::interpret::eval_str;
+	assert![format!["{}", file2result("raise-0.tko")].contains("boom")];
+	let program = teko::parse::parse_string("(raise (@ bad-value:) 1 2)").ok().unwrap();
+	let message = format!["{}", interpret(program).get_result()];
+	assert![message.contains("bad-value: 1 2")];
+	assert![!format!["{}", file2result("raise-non-string.tko")].contains("boom")];
+	assert![eval_str("(error (\" boom))").unwrap_err().contains("boom")];
+	assert![eval_str("(begin (error (\" boom)) 5)").unwrap_err().contains("boom")];
+	assert![eval_str("(error 5)").unwrap_err().contains("expected String or Symbol")];
+	assert_eq![eval_str("(error-message (wind (error (\" boom))))").unwrap().as_str(), Ok("boom")];
+}
+
+#[test]
+fn cyclic_data_is_written() {
+	use teko::interpret::eval_str;
+	let vector = eval_str("(define a (vector 0)) (vector-set! a 0 a) a").unwrap();
+	assert_eq![format!["{}", vector], "(vector (@ #<cycle>))"];
+	assert_eq![teko::print::to_string(&vector), "#(#<cycle>)"];
+	let boxed = eval_str("(define b (make-box 0)) (box-set! b b) b").unwrap();
+	assert_eq![format!["{}", boxed], "(make-box (@ #<cycle>))"];
+}
+
+#[test]
+fn rationals_are_exact() {
+	use teko::interpret::eval_str;
+	let written = |code: &str| format!["{}", eval_str(code).unwrap()];
+	assert_eq![written("-3/6"), "-1/2"];
+	assert_eq![written("(+ 1/3 1)"), "4/3"];
+	assert_eq![written("(* 2/3 3/4)"), "1/2"];
+	assert_eq![written("(- 1/2 1/2)"), "0"];
+	assert_eq![written("(/ 3 1/2)"), "6"];
+	assert_eq![written("(/ 7 2)"), "3"];
+	assert_eq![written("(+ 1/2 0.25)"), "0.75"];
+	assert_eq![written("(exact->inexact 1/3)"), "0.3333333333333333"];
+	assert_eq![written("(inexact->exact 0.1)"), "3602879701896397/36028797018963968"];
+	assert_eq![written("(< 1/3 0.34 1/2)"), "true"];
+	assert_eq![written("(= 1/2 2/4 0.5)"), "true"];
+	assert_eq![written("(number->string -1/2 2)"), "(\" -1/10)"];
+	assert_eq![written("(string->number (\" ff/3) 16)"), "85"];
+	assert_eq![written("(exact? 1/2)"), "true"];
+	assert![eval_str("(/ 1/2 0)").unwrap_err().contains("argument is zero")];
+	assert![eval_str("1/0").unwrap_err().contains("variable not found: 1/0")];
+	assert![eval_str("1/-2").unwrap_err().contains("variable not found: 1/-2")];
+}
+
+#[test]
+fn booleans_are_bound() {
+	use teko::data_structures::Symbol;
+	use teko::interpret::initialize_environment_with_standard_library;
+	let env = initialize_environment_with_standard_library();
+	assert_eq![env.get(&Symbol::from("true")).unwrap().1, Coredata::Boolean(true)];
+	assert_eq![env.get(&Symbol::from("false")).unwrap().1, Coredata::Boolean(false)];
+}
+
+#[test]
+fn tail_calls_run_in_constant_space() {
+	use teko::interpret::{eval, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let program = |code| parse_string(code).ok().unwrap();
+	let env = initialize_environment_with_standard_library();
+	let env = eval(program("(define loop (function (n) (if (= n 0) (@variable-count) (loop (- n 1)))))"), env);
+	let env = eval(program("(loop 1)"), env);
+	let shallow = env.get_result();
+	let env = eval(program("(loop 100000)"), env);
+	assert_eq![env.get_result().1, shallow.1];
+}
+
+#[test]
+fn calls_leave_no_variables() {
+	use teko::interpret::{eval, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let code = [
+		("(define count (function (n acc) (if (= n 0) acc (count (- n 1) (+ acc n))))) (count 10000 0)", "50005000"),
+		("(define even (function (n) (if (= n 0) true (odd (- n 1))))) \
+		 (define odd (function (m) (if (= m 0) false (even (- m 1))))) (even 10001)", "false"),
+		("(define depth (function (n) (if (= n 0) 0 (+ 1 (depth (- n 1)))))) (depth 1000)", "1000"),
+		("(let ((a 1) (b 2)) (let ((c 3)) (+ a b c)))", "6"),
+		("(let ((a 1)) (let ((b 2)) (+ a b)))", "3"),
+		("(do ((i 0 (+ i 1)) (acc 0 (+ acc i))) ((= i 5) acc))", "10"),
+		("(let loop ((i 0) (acc 1)) (if (= i 5) acc (loop (+ i 1) (* acc 2))))", "32"),
+		("(define x 1) (define f (function () x)) (define g (function (x) (f))) (g 100)", "1"),
+	];
+	for &(code, expected) in &code {
+		let env = initialize_environment_with_standard_library();
+		let variables = env.count_variables();
+		let env = eval(parse_string(code).ok().unwrap(), env);
+		assert_eq![format!["{}", env.get_result()], expected, "{}", code];
+		let defined = code.matches("(define ").count();
+		assert_eq![env.count_variables(), variables + defined, "{}", code];
+	}
+}
+
+#[test]
+fn functions_are_lexically_scoped() {
+	use teko::interpret::eval_str;
+	let free = "(define x 1) (define f (function () x)) (define g (function (x) (f))) (g 100)";
+	assert_eq![eval_str(free).unwrap().as_i64(), Ok(1)];
+	let caller = "(define f (function (x) (g))) (define g (function () x)) (f 3)";
+	assert![eval_str(caller).is_err()];
+	let counter = "(define make (function (n) (function () (set! n (+ n 1)) n))) \
+		(define c (make 0)) (c) (c)";
+	assert_eq![eval_str(counter).unwrap().as_i64(), Ok(2)];
+	let shared = "(define make (function (n) (list (function () (set! n (+ n 1))) (function () n)))) \
+		(define pair (make 5)) ((head pair)) ((head (tail pair)))";
+	assert_eq![eval_str(shared).unwrap().as_i64(), Ok(6)];
+	let separate = "(define make (function (n) (function () (set! n (+ n 1)) n))) \
+		(define a (make 0)) (define b (make 10)) (a) (a) (b)";
+	assert_eq![eval_str(separate).unwrap().as_i64(), Ok(11)];
+}
+
+#[test]
+fn store_bounded_across_tail_calls() {
+	use teko::data_structures::Symbol;
+	use teko::interpret::{eval, initialize_environment_with_standard_library};
+	use teko::parse::parse_string;
+	let program = |code| parse_string(code).ok().unwrap();
+	let env = initialize_environment_with_standard_library();
+	let env = eval(program("(define loop (function (n acc) (if (= n 0) (@variable-count) (loop (- n 1) (+ acc 1)))))"), env);
+	let env = eval(program("(loop 1 0)"), env);
+	let shallow = env.get_result();
+	let env = eval(program("(loop 1000000 0)"), env);
+	assert_eq![env.get_result().1, shallow.1];
+	assert![env.get(&Symbol::from("n")).is_none()];
+	assert![env.get(&Symbol::from("acc")).is_none()];
+}
+
+#[test]
+fn top_level_forms_run_left_to_right() {
+	use teko::interpret::eval_str;
+	let code = "(define x 1) (set! x 2) x";
+	assert_eq![eval_str(code).unwrap().as_i64(), Ok(2)];
+	assert_eq![interpret(teko::parse::parse_string(code).ok().unwrap()).get_result().as_i64(), Ok(2)];
+	assert_eq![file2result("define-order.tko").as_i64(), Ok(2)];
+	let env = repl::run(code.as_bytes(), &mut vec![]);
+	assert_eq![env.get_result().as_i64(), Ok(2)];
+}
+
+#[test]
+fn repl_prints_results() {
+	let mut output = vec![];
+	repl::run("(+ 1 2)\n(head 1)\n(+ 3 4)\n(list 1 (list 2))\n".as_bytes(), &mut output);
+	let output = String::from_utf8(output).unwrap();
+	let lines: Vec<&str> = output.lines().collect();
+	assert_eq![lines.len(), 4];
+	assert_eq![lines[0], "3"];
+	assert![lines[1].starts_with("(make-error")];
+	assert_eq![lines[2], "7"];
+	assert_eq![lines[3], "(1 (2))"];
+	let mut output = vec![];
+	repl::run("(+ 1 2) (head 1) (+ 3 4) x\n".as_bytes(), &mut output);
+	let output = String::from_utf8(output).unwrap();
+	let lines: Vec<&str> = output.lines().collect();
+	assert_eq![lines.len(), 4];
+	assert_eq![lines[0], "3"];
+	assert![lines[1].starts_with("(make-error")];
+	assert_eq![lines[2], "7"];
+	assert![lines[3].contains("variable not found: x")];
+}
+
+#[test]
+fn repl_continues_incomplete_forms() {
+	let mut output = vec![];
+	repl::run("(+ 1\n2)\n".as_bytes(), &mut output);
+	assert_eq![String::from_utf8(output).unwrap(), "3\n"];
+	let mut output = vec![];
+	repl::run_with_prompts("(+ 1\n\n2) (list\n3)\n)\n".as_bytes(), &mut output, "> ", ". ");
+	assert_eq![String::from_utf8(output).unwrap(), "> . . 3\n. (3)\n> parse error: Unmatched closing parenthesis\n> "];
 }
 
 // //////////////////////////////////////////////////////////
 // Utility functions
 // //////////////////////////////////////////////////////////
 
+/// Redirect the output of `env` into a buffer that can be inspected afterwards.
+fn capture_output(env: &mut teko::data_structures::Env) -> Arc<std::sync::Mutex<Vec<u8>>> {
+	use std::io::{self, Write};
+	struct Capture(Arc<std::sync::Mutex<Vec<u8>>>);
+	impl Write for Capture {
+		fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+			self.0.lock().unwrap().write(buffer)
+		}
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+	let captured = Arc::new(std::sync::Mutex::new(vec![]));
+	env.output = Box::new(Capture(captured.clone()));
+	captured
+}
+
 fn boolean(filename: &str, value: bool) {
 	let result = &file2result(&filename).1;
 	if let Coredata::Boolean(true) = *result {
@@ -51,11 +1126,7 @@ fn boolean(filename: &str, value: bool) {
 }
 
 fn error(filename: &str) {
-	if let Coredata::Error(_) = file2result(filename).1 {
-		assert![true];
-	} else {
-		assert![false];
-	}
+	assert![is_error(&file2result(filename))];
 }
 
 fn file2result(filename: &str) -> Arc<teko::data_structures::Sourcedata> {
@@ -66,6 +1137,22 @@ fn file2result(filename: &str) -> Arc<teko::data_structures::Sourcedata> {
 	env.get_result()
 }
 
+fn is_error(data: &teko::data_structures::Sourcedata) -> bool {
+	if let Coredata::Error(_) = data.1 {
+		true
+	} else {
+		false
+	}
+}
+
+fn float(filename: &str, number: f64) {
+	assert_eq![file2result(filename).1, Coredata::Float(Float(number))];
+}
+
+fn rational(filename: &str, number: &str) {
+	assert_eq![file2result(filename).1, Coredata::Rational(number.parse::<BigRational>().unwrap())];
+}
+
 fn integer(filename: &str, number: &str) {
 	let result = file2result(filename);
 	assert_eq![